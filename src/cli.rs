@@ -3,12 +3,29 @@ use clap::{crate_version, Arg, ArgAction, ArgMatches, Command};
 use std::path::PathBuf;
 use std::str::FromStr;
 
-pub fn parse_arguments() -> Result<Options> {
+/// What should desed do.
+pub enum Mode {
+    /// Debug sed script in TUI
+    Debug(Options),
+    /// Run sed script with input files and compare its output with expected output
+    Test(TestOptions),
+    /// Print all debugging states without TUI
+    Trace(TraceOptions),
+    /// Check sed script for mistakes without running it
+    Lint(LintOptions),
+    /// Print control-flow graph of sed script
+    Graph(GraphOptions),
+}
+
+pub fn parse_arguments() -> Result<Mode> {
     let matches = Command::new("Desed")
         .version(crate_version!())
         .author("Petr Šťastný <desed@soptik.tech>")
         .about("Sed script debugger. Debug and demystify your sed scripts with TUI debugger.")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(Arg::new("sed_n")
+            .global(true)
             .action(ArgAction::SetTrue)
             .short('n')
             .long("quiet")
@@ -16,32 +33,52 @@ pub fn parse_arguments() -> Result<Options> {
             .help("sed: suppress automatic printing of pattern space")
             .required(false))
         .arg(Arg::new("sed_E")
+            .global(true)
             .action(ArgAction::SetTrue)
             .short('E')
             .long("regexp-extended")
             .help("sed: use extended regular expressions in the script")
             .required(false))
         .arg(Arg::new("sed_sandbox")
+            .global(true)
             .action(ArgAction::SetTrue)
             .long("sandbox")
             .help("sed: operate in sandbox mode (disable e/r/w commands).")
             .required(false))
         .arg(Arg::new("sed_z")
+            .global(true)
             .action(ArgAction::SetTrue)
             .long("null-data")
             .short('z')
             .help("sed: separate lines by NUL characters")
             .required(false))
+        .arg(Arg::new("sed_posix")
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .long("posix")
+            .help("sed: disable all GNU extensions. Lint reports them as well.")
+            .required(false))
         .arg(Arg::new("verbose")
+            .global(true)
             .action(ArgAction::SetTrue)
             .long("verbose")
             .short('v')
             .help("This will enable various debug printing to stderr.")
             .required(false))
         .arg(Arg::new("sed-path")
+            .global(true)
             .long("sed-path")
             .help("Specify path to sed that should be used. If omitted, gsed/sed from your $PATH will run.")
             .required(false))
+        .arg(hold_space_arg())
+        .arg(skip_lines_arg())
+        .arg(Arg::new("expect")
+            .long("expect")
+            .value_name("FILE")
+            .help("Compare final output of sed with FILE and start at the state that printed the first wrong line.")
+            // Output of skipped lines isn't kept, so it couldn't be compared
+            .conflicts_with("skip-lines")
+            .required(false))
         .arg(Arg::new("sed-script")
             .help("Input file with sed script")
             .required(true)
@@ -52,60 +89,233 @@ pub fn parse_arguments() -> Result<Options> {
             .index(2))
         .after_help("EXAMPLE:\
             \n\tdesed increment-number.sed test-suite.txt\n\t\tRuns script stored in increment-number.sed with input in test-suite.txt\
-            \n\n\tdesed print-matching.sed test-cases.txt -nE\n\t\tRuns script in .sed file with input in .txt file and parameters -n -E to launched sed\n\n\
+            \n\n\tdesed print-matching.sed test-cases.txt -nE\n\t\tRuns script in .sed file with input in .txt file and parameters -n -E to launched sed\
+            \n\n\tdesed --hold-space 'header' --skip-lines 100 join-records.sed data.txt\n\t\tRuns script with \"header\" in hold space and opens debugger at the cycle processing line 101\
+            \n\n\tdesed --expect expected.txt increment-number.sed test-suite.txt\n\t\tRuns script and opens debugger at the state that printed the first line different from expected.txt\n\n\
         CONTROLS:\
             \n\tMouse scroll, j, k, g, G (just as in vi): scroll through file\
             \n\tMouse click, b: toggle breakpoint on target line\
+            \n\tB: open breakpoint manager (Enter: jump, d: delete, e: enable/disable, Ni: ignore next N hits, Esc: leave)\
+            \n\tp: attach logpoint message to line, e.g. \"line {input_line}: PS={pattern}\"\
+            \n\tL: open log with messages of all logpoints (Enter: jump to state)\
+            \n\tc: show how many times was each line executed and highlight lines that never ran\
+            \n\to: show original script file with executing command highlighted in place\
+            \n\tz: fold or unfold block under cursor, Z: fold or unfold all blocks\
+            \n\tx: explain command under cursor or at execution pointer in plain English\
+            \n\tv: break down regexes of command under cursor into groups, classes, anchors and quantifiers\
+            \n\t/: test regex, or regex/replacement/flags, on pattern space (Enter: keep results, Esc: close)\
+            \n\t:: run sed commands from pattern and hold space of current state as a temporary branch\
+            \n\tw/W: edit pattern/hold space and run the rest of cycle (Tab: input) again as a fork\
+            \n\te: edit script in place (Ctrl-S: save and run again if sed accepts it, Ctrl-K: delete line, Esc: discard, twice if modified)\
+            \n\tV: open script in $EDITOR at line under cursor, reload it when editor exits\
+            \n\tP: open profile with the most expensive cycles, loops and lines (Enter: jump to cycle or line)\
             \n\ts: Step forward\
             \n\ta: step bAckwards\
             \n\tr: run towards end or next breakpoint\
             \n\tR: the same as r, but backwards\
             \n\tl: instantly reload source code and attempt to stay in the same state you were in\
+            \n\tD: compare with execution before last reload (Enter: jump to first differing state of input line)\
+            \n\tf: jump to first state that differs from execution before last reload\
+            \n\tE: show expected and actual output side by side (Enter: jump to state, n: next wrong line)\
             \n\tq: quit\
             \n\tYou can prefix most commands with numbers, just as in vi.")
+        .subcommand(Command::new("test")
+            .about("Run sed script with each input file and compare its output with expected output.")
+            .arg(Arg::new("sed-script")
+                .help("Input file with sed script")
+                .required(true)
+                .index(1))
+            .arg(Arg::new("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("File listing test cases, one per line as \"INPUT EXPECTED\". If omitted, each *.in file next to the script with matching *.out file is a test case.")
+                .required(false))
+            .arg(Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["human", "junit", "tap"])
+                .default_value("human")
+                .help("Format of the report: human-readable text, JUnit XML or TAP.")
+                .required(false))
+            .arg(Arg::new("lcov")
+                .long("lcov")
+                .value_name("FILE")
+                .help("Write coverage of the script over all test cases into FILE in lcov format.")
+                .required(false))
+            .arg(Arg::new("open")
+                .action(ArgAction::SetTrue)
+                .long("open")
+                .help("Open the first failing test case in debugger.")
+                .required(false))
+            .arg(hold_space_arg())
+            .after_help("EXAMPLE:\
+                \n\tdesed test increment-number.sed\n\t\tRuns increment-number.sed with each *.in file in the same directory and compares output with the *.out file of the same name\
+                \n\n\tdesed test -E --manifest cases.txt --open print-matching.sed\n\t\tRuns test cases listed in cases.txt with -E and opens the first failing one in debugger\
+                \n\n\tdesed test --format junit increment-number.sed > report.xml\n\t\tRuns test cases and writes JUnit XML report for CI\
+                \n\n\tdesed test --lcov coverage.info increment-number.sed\n\t\tRuns test cases and writes which lines of the script they executed"))
+        .subcommand(Command::new("trace")
+            .about("Run sed script and print every debugging state, without TUI.")
+            .arg(Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["human", "json", "csv"])
+                .default_value("human")
+                .help("Format of the trace: human-readable text, JSON lines or CSV.")
+                .required(false))
+            .arg(Arg::new("input-lines")
+                .long("input-lines")
+                .value_name("RANGE")
+                .value_parser(LineRange::from_str)
+                .help("Print only states processing these input lines, such as 3, 3-5, 3- or -5.")
+                .required(false))
+            .arg(Arg::new("script-lines")
+                .long("script-lines")
+                .value_name("RANGE")
+                .value_parser(LineRange::from_str)
+                .help("Print only states executing commands on these lines of the program as desed shows it, such as 3, 3-5, 3- or -5.")
+                .required(false))
+            .arg(hold_space_arg())
+            .arg(skip_lines_arg())
+            .arg(Arg::new("sed-script")
+                .help("Input file with sed script")
+                .required(true)
+                .index(1))
+            .arg(Arg::new("input-file")
+                .help("File with data for sed to process.")
+                .required(true)
+                .index(2))
+            .after_help("EXAMPLE:\
+                \n\tdesed trace increment-number.sed test-suite.txt | grep PATTERN\n\t\tPrints all states and shows how pattern space evolved\
+                \n\n\tdesed trace --format json --input-lines 3 increment-number.sed test-suite.txt\n\t\tPrints states processing the third input line as JSON lines"))
+        .subcommand(Command::new("lint")
+            .about("Check sed script for mistakes without running it.")
+            .arg(Arg::new("sed-script")
+                .help("Input file with sed script")
+                .required(true)
+                .index(1))
+            .after_help("EXAMPLE:\
+                \n\tdesed lint increment-number.sed\n\t\tReports undefined and unused labels, unreachable code and tests without substitution\
+                \n\n\tdesed lint --posix increment-number.sed\n\t\tReports GNU extensions as well"))
+        .subcommand(Command::new("graph")
+            .about("Print control-flow graph of sed script in Graphviz DOT format.")
+            .arg(Arg::new("sed-script")
+                .help("Input file with sed script")
+                .required(true)
+                .index(1))
+            .arg(Arg::new("input-file")
+                .help("File with data for sed to process. If given, edges show how many times they were taken.")
+                .required(false)
+                .index(2))
+            .arg(hold_space_arg())
+            .after_help("EXAMPLE:\
+                \n\tdesed graph increment-number.sed | dot -Tsvg > graph.svg\n\t\tDraws basic blocks of the script, connected by branches\
+                \n\n\tdesed graph increment-number.sed test-suite.txt | dot -Tsvg > graph.svg\n\t\tDraws the same graph, with how many times was each edge taken"))
         .get_matches();
-    Options::from_matches(matches)
+    match matches.subcommand() {
+        Some(("test", test_matches)) => Ok(Mode::Test(TestOptions::from_matches(test_matches)?)),
+        Some(("trace", trace_matches)) => {
+            Ok(Mode::Trace(TraceOptions::from_matches(trace_matches)?))
+        }
+        Some(("lint", lint_matches)) => Ok(Mode::Lint(LintOptions::from_matches(lint_matches)?)),
+        Some(("graph", graph_matches)) => {
+            Ok(Mode::Graph(GraphOptions::from_matches(graph_matches)?))
+        }
+        _ => Ok(Mode::Debug(Options::from_matches(&matches)?)),
+    }
+}
+
+/// `--hold-space`, for commands that run the script.
+fn hold_space_arg() -> Arg {
+    Arg::new("hold-space")
+        .long("hold-space")
+        .value_name("TEXT")
+        .help("Start with TEXT in hold space instead of empty one. Write newlines as \\n, just as desed shows them. Can't be used with --posix.")
+        .required(false)
+}
+
+/// `--skip-lines`, for commands that show states of the run.
+fn skip_lines_arg() -> Arg {
+    Arg::new("skip-lines")
+        .long("skip-lines")
+        .value_name("N")
+        .value_parser(clap::value_parser!(usize))
+        .help("Run the script on the first N input lines, but start debugging after them, keeping the state they built.")
+        .required(false)
+}
+
+/// Collect parameters that should be passed to sed.
+fn sed_parameters(matches: &ArgMatches) -> Vec<String> {
+    let mut sed_parameters: Vec<String> = Vec::with_capacity(5);
+
+    if matches.get_flag("sed_n") {
+        sed_parameters.push(String::from("-n"));
+    }
+    if matches.get_flag("sed_E") {
+        sed_parameters.push(String::from("-E"));
+    }
+    if matches.get_flag("sed_sandbox") {
+        sed_parameters.push(String::from("--sandbox"));
+    }
+    if matches.get_flag("sed_z") {
+        sed_parameters.push(String::from("-z"));
+    }
+    if matches.get_flag("sed_posix") {
+        sed_parameters.push(String::from("--posix"));
+    }
+    sed_parameters
+}
+
+/// Initial content of hold space, if user wants to seed it.
+fn hold_space(matches: &ArgMatches) -> Result<Option<String>> {
+    let hold_space = matches.get_one::<String>("hold-space").cloned();
+    if hold_space.is_some() && matches.get_flag("sed_posix") {
+        anyhow::bail!(
+            "--hold-space can't be used with --posix, seeding hold space needs GNU extensions."
+        );
+    }
+    Ok(hold_space)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Options {
     pub sed_script: PathBuf,
     pub input_file: PathBuf,
     pub sed_parameters: Vec<String>,
     pub verbose: bool,
     pub sed_path: Option<String>,
+    /// File with output sed is expected to produce
+    pub expected_output: Option<PathBuf>,
+    /// Initial content of hold space, escaped as sed prints it, if it shouldn't start empty
+    pub hold_space: Option<String>,
+    /// Number of input lines that run, but aren't shown in debugger
+    pub skip_lines: usize,
 }
 impl Options {
-    pub fn from_matches(matches: ArgMatches) -> Result<Options> {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Options> {
         // UNWRAP: It's safe because we define sed-script in the CLI code above, so we are certain it exists.
         let sed_script: PathBuf =
             PathBuf::from_str(matches.get_one::<String>("sed-script").unwrap())
                 .with_context(|| "Failed to load sed script path")?;
-        // UNWRAP: It's safe because we define input-file in the CLI code above, so we are certain it exists.
-        let input_file: PathBuf =
-            PathBuf::from_str(matches.get_one::<String>("input-file").unwrap())
-                .with_context(|| "Failed to load input file path.")?;
+        // Graph doesn't need input, but sed needs some to print the program
+        let input_file: PathBuf = PathBuf::from_str(
+            matches
+                .get_one::<String>("input-file")
+                .map(String::as_str)
+                .unwrap_or("/dev/null"),
+        )
+        .with_context(|| "Failed to load input file path.")?;
 
         let sed_path: Option<String> = matches.get_one::<String>("sed-path").map(ToOwned::to_owned);
+        // Subcommands don't define --expect at all
+        let expected_output: Option<PathBuf> = matches
+            .try_get_one::<String>("expect")
+            .ok()
+            .flatten()
+            .map(PathBuf::from);
 
-        let mut sed_parameters: Vec<String> = Vec::with_capacity(4);
-        let mut debug = false;
-
-        if matches.get_flag("sed_n") {
-            sed_parameters.push(String::from("-n"));
-        }
-        if matches.get_flag("sed_E") {
-            sed_parameters.push(String::from("-E"));
-        }
-        if matches.get_flag("sed_sandbox") {
-            sed_parameters.push(String::from("--sandbox"));
-        }
-        if matches.get_flag("sed_z") {
-            sed_parameters.push(String::from("-z"));
-        }
-        if matches.get_flag("verbose") {
-            debug = true;
-        }
+        let sed_parameters = sed_parameters(matches);
+        let debug = matches.get_flag("verbose");
+        let hold_space = hold_space(matches)?;
 
         Ok(Options {
             sed_script,
@@ -113,6 +323,203 @@ impl Options {
             input_file,
             sed_parameters,
             verbose: debug,
+            expected_output,
+            hold_space,
+            // Graph counts edges over the whole run, so it doesn't define --skip-lines
+            skip_lines: matches
+                .try_get_one::<usize>("skip-lines")
+                .ok()
+                .flatten()
+                .copied()
+                .unwrap_or(0),
+        })
+    }
+
+    /// True if sed reads regexes as extended ones, with `-E`.
+    pub fn extended_regex(&self) -> bool {
+        self.sed_parameters.iter().any(|p| p == "-E")
+    }
+
+    /// True if sed disables GNU extensions, with `--posix`.
+    pub fn posix(&self) -> bool {
+        self.sed_parameters.iter().any(|p| p == "--posix")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestOptions {
+    pub sed_script: PathBuf,
+    /// File listing test cases. If None, test cases are discovered next to the script.
+    pub manifest: Option<PathBuf>,
+    /// Open first failing test case in debugger
+    pub open: bool,
+    pub format: ReportFormat,
+    /// Where to write line coverage in lcov format, if anywhere
+    pub lcov: Option<PathBuf>,
+    pub sed_parameters: Vec<String>,
+    pub verbose: bool,
+    pub sed_path: Option<String>,
+    /// Initial content of hold space, see [Options::hold_space]
+    pub hold_space: Option<String>,
+}
+impl TestOptions {
+    pub fn from_matches(matches: &ArgMatches) -> Result<TestOptions> {
+        // UNWRAP: It's safe because we define sed-script in the CLI code above, so we are certain it exists.
+        let sed_script: PathBuf =
+            PathBuf::from_str(matches.get_one::<String>("sed-script").unwrap())
+                .with_context(|| "Failed to load sed script path")?;
+
+        Ok(TestOptions {
+            sed_script,
+            manifest: matches.get_one::<String>("manifest").map(PathBuf::from),
+            open: matches.get_flag("open"),
+            lcov: matches.get_one::<String>("lcov").map(PathBuf::from),
+            // UNWRAP: Format has default value and clap checks it's one of the possible values
+            format: ReportFormat::from_name(matches.get_one::<String>("format").unwrap()).unwrap(),
+            sed_parameters: sed_parameters(matches),
+            verbose: matches.get_flag("verbose"),
+            sed_path: matches.get_one::<String>("sed-path").map(ToOwned::to_owned),
+            hold_space: hold_space(matches)?,
+        })
+    }
+
+    /// Options for debugging the script with given input, comparing output with expected output.
+    pub fn debug_options(&self, input_file: PathBuf, expected_output: PathBuf) -> Options {
+        Options {
+            sed_script: self.sed_script.clone(),
+            input_file,
+            sed_parameters: self.sed_parameters.clone(),
+            verbose: self.verbose,
+            sed_path: self.sed_path.clone(),
+            expected_output: Some(expected_output),
+            hold_space: self.hold_space.clone(),
+            skip_lines: 0,
+        }
+    }
+}
+
+/// How should `desed test` report results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Human,
+    JUnit,
+    Tap,
+}
+impl ReportFormat {
+    fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "human" => Some(ReportFormat::Human),
+            "junit" => Some(ReportFormat::JUnit),
+            "tap" => Some(ReportFormat::Tap),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    /// How to run sed
+    pub options: Options,
+    pub format: TraceFormat,
+    /// Print only states processing these input lines (1-based)
+    pub input_lines: Option<LineRange>,
+    /// Print only states executing these lines of the program (1-based)
+    pub script_lines: Option<LineRange>,
+}
+impl TraceOptions {
+    pub fn from_matches(matches: &ArgMatches) -> Result<TraceOptions> {
+        // UNWRAP: Format has default value and clap checks it's one of the possible values
+        let format = match matches.get_one::<String>("format").unwrap().as_str() {
+            "json" => TraceFormat::JsonLines,
+            "csv" => TraceFormat::Csv,
+            _ => TraceFormat::Human,
+        };
+        Ok(TraceOptions {
+            options: Options::from_matches(matches)?,
+            format,
+            input_lines: matches.get_one::<LineRange>("input-lines").copied(),
+            script_lines: matches.get_one::<LineRange>("script-lines").copied(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintOptions {
+    pub sed_script: PathBuf,
+    /// Report GNU extensions
+    pub posix: bool,
+}
+impl LintOptions {
+    pub fn from_matches(matches: &ArgMatches) -> Result<LintOptions> {
+        // UNWRAP: It's safe because we define sed-script in the CLI code above, so we are certain it exists.
+        let sed_script: PathBuf =
+            PathBuf::from_str(matches.get_one::<String>("sed-script").unwrap())
+                .with_context(|| "Failed to load sed script path")?;
+        Ok(LintOptions {
+            sed_script,
+            posix: matches.get_flag("sed_posix"),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphOptions {
+    /// How to run sed
+    pub options: Options,
+    /// Count how many times was each edge taken, if input file was given
+    pub count_edges: bool,
+}
+impl GraphOptions {
+    pub fn from_matches(matches: &ArgMatches) -> Result<GraphOptions> {
+        Ok(GraphOptions {
+            options: Options::from_matches(matches)?,
+            count_edges: matches.contains_id("input-file"),
         })
     }
 }
+
+/// How should `desed trace` print debugging states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Human,
+    JsonLines,
+    Csv,
+}
+
+/// Inclusive range of 1-based line numbers, written as `N`, `N-M`, `N-` or `-M`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub first: usize,
+    pub last: usize,
+}
+impl LineRange {
+    pub fn contains(&self, line: usize) -> bool {
+        self.first <= line && line <= self.last
+    }
+}
+impl FromStr for LineRange {
+    type Err = String;
+
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        let number = |text: &str, default: usize| -> std::result::Result<usize, String> {
+            if text.is_empty() {
+                Ok(default)
+            } else {
+                text.trim()
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a line number", text))
+            }
+        };
+        let (first, last) = match text.split_once('-') {
+            Some((first, last)) => (number(first, 1)?, number(last, usize::MAX)?),
+            None => {
+                let line = number(text, 0)?;
+                (line, line)
+            }
+        };
+        if first == 0 || first > last {
+            return Err(format!("'{}' is not a valid range of lines", text));
+        }
+        Ok(LineRange { first, last })
+    }
+}
@@ -0,0 +1,86 @@
+/// Escape text so it can be used as XML text.
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Other control characters are not allowed in XML at all
+            c if c.is_control() && c != '\t' && c != '\n' => {
+                escaped.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote string for YAML, escaping anything that could break the document.
+pub fn yaml_string(text: &str) -> String {
+    quote(text, |c| format!("\\x{:02x}", c as u32))
+}
+
+/// Quote string for JSON.
+pub fn json_string(text: &str) -> String {
+    quote(text, |c| format!("\\u{:04x}", c as u32))
+}
+
+/// Put text into double quotes, escaping quotes, backslashes and control characters
+/// the way C-like string literals do. Control characters without a short form are
+/// written by `escape_control`, as formats differ in how they spell them.
+fn quote(text: &str, escape_control: fn(char) -> String) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&escape_control(c)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote CSV field if needed.
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        String::from(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_escape_replaces_markup_and_control_characters() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">'&'</a>\u{1}\t"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;\\x01\t"
+        );
+    }
+
+    #[test]
+    fn yaml_and_json_differ_only_in_control_characters() {
+        let text = "say \"hi\"\\\n\t\u{1b}";
+        assert_eq!(yaml_string(text), "\"say \\\"hi\\\"\\\\\\n\\t\\x1b\"");
+        assert_eq!(json_string(text), "\"say \\\"hi\\\"\\\\\\n\\t\\u001b\"");
+    }
+
+    #[test]
+    fn csv_field_is_quoted_only_when_needed() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,28 @@
+use crate::cli::GraphOptions;
+use crate::sed::control_flow::ControlFlowGraph;
+use crate::sed::debugger::Debugger;
+use crate::sed::script::Script;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+
+/// Print control-flow graph of the script in DOT format. If input was given,
+/// edges are annotated with how many times they were taken.
+pub fn print_graph(options: &GraphOptions) -> Result<()> {
+    let path = &options.options.sed_script;
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read sed script {}", path.display()))?;
+    let script =
+        Script::parse(&source).map_err(|error| anyhow!("{}: {}", path.display(), error))?;
+    // Sed prints the program even without any input
+    let debugger = Debugger::new(options.options.clone())?;
+    let mut graph = ControlFlowGraph::new(&script, &debugger.source_code);
+    if options.count_edges {
+        graph.count_edges(&debugger);
+    }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    print!("{}", graph.to_dot(&name));
+    Ok(())
+}
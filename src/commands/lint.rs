@@ -0,0 +1,25 @@
+use crate::cli::LintOptions;
+use crate::sed::lint::lint;
+use anyhow::{Context, Result};
+use std::fs;
+
+/// Lint the script and print diagnostics, one per line, as `file:line:column: severity: message`.
+///
+/// Returns whether any problem was found.
+pub fn print_diagnostics(options: &LintOptions) -> Result<bool> {
+    let path = &options.sed_script;
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read sed script {}", path.display()))?;
+    let (_, diagnostics) = lint(&source, options.posix);
+    for diagnostic in &diagnostics {
+        println!(
+            "{}:{}:{}: {}: {}",
+            path.display(),
+            diagnostic.position.line + 1,
+            diagnostic.position.column + 1,
+            diagnostic.severity,
+            diagnostic.message
+        );
+    }
+    Ok(!diagnostics.is_empty())
+}
@@ -0,0 +1,5 @@
+pub mod escape;
+pub mod graph;
+pub mod lint;
+pub mod test;
+pub mod trace;
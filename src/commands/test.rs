@@ -0,0 +1,461 @@
+use crate::cli::{Options, ReportFormat, TestOptions};
+use crate::commands::escape::{xml_escape, yaml_string};
+use crate::diff::{diff, Change};
+use crate::sed::coverage::Coverage;
+use crate::sed::debugger::Debugger;
+use crate::sed::expectation::Expectation;
+use crate::sed::script::Script;
+use crate::sed::source_map::SourceMap;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many unchanged lines are shown around each difference in output.
+const DIFF_CONTEXT: usize = 2;
+
+/// Input file together with output the sed script should produce for it.
+pub struct TestCase {
+    pub name: String,
+    pub input_file: PathBuf,
+    pub expected_output: PathBuf,
+}
+
+pub struct TestResult {
+    pub case: TestCase,
+    pub outcome: Outcome,
+    /// Lines of the script executed by the test case, if sed ran at all
+    pub coverage: Option<Coverage>,
+}
+
+pub enum Outcome {
+    Passed,
+    Failed(Failure),
+    /// Sed couldn't be run at all, for example because of syntax error in the script
+    Error(String),
+}
+
+/// Description of a test case whose output differs from the expected one.
+pub struct Failure {
+    pub expected: Vec<String>,
+    pub actual: Vec<String>,
+    /// Index (0-based) of the first wrong output line
+    pub first_mismatch: usize,
+    /// State (0-based) that printed the first wrong line
+    pub state: usize,
+    /// Input line sed was processing when it printed the first wrong line
+    pub input_line: Option<usize>,
+    /// Command that printed the first wrong line
+    pub command: Option<String>,
+}
+
+/// Find test cases and run each of them.
+pub fn run_tests(options: &TestOptions) -> Result<Vec<TestResult>> {
+    let cases = match &options.manifest {
+        Some(manifest) => read_manifest(manifest)?,
+        None => discover_test_cases(&options.sed_script)?,
+    };
+    if cases.is_empty() {
+        anyhow::bail!(
+            "No test cases found. Put pairs of *.in and *.out files next to the script, or use --manifest."
+        );
+    }
+    Ok(cases
+        .into_iter()
+        .map(|case| run_test_case(options, case))
+        .collect())
+}
+
+/// Run sed script with input of one test case, the same way it's run when debugging.
+fn run_test_case(options: &TestOptions, case: TestCase) -> TestResult {
+    let (outcome, coverage) = match evaluate(
+        options.debug_options(case.input_file.clone(), case.expected_output.clone()),
+    ) {
+        Ok((None, coverage)) => (Outcome::Passed, Some(coverage)),
+        Ok((Some(failure), coverage)) => (Outcome::Failed(failure), Some(coverage)),
+        Err(error) => (Outcome::Error(format!("{:#}", error)), None),
+    };
+    TestResult {
+        case,
+        outcome,
+        coverage,
+    }
+}
+
+/// Run sed and compare its output with expected output. Returns None instead of failure
+/// if the output is right, together with lines of the script the run executed.
+fn evaluate(options: Options) -> Result<(Option<Failure>, Coverage)> {
+    // UNWRAP: Test options always have expected output set
+    let expected_path = options.expected_output.clone().unwrap();
+    let expected: Vec<String> = fs::read_to_string(&expected_path)
+        .with_context(|| format!("Failed to read expected output {}", expected_path.display()))?
+        .lines()
+        .map(String::from)
+        .collect();
+    let debugger = Debugger::new(options)?;
+    if let Some(infinite_loop) = &debugger.infinite_loop {
+        anyhow::bail!(infinite_loop.describe(&debugger.source_code));
+    }
+    let expectation = Expectation::new(&expected, &debugger);
+    let coverage = Coverage::new(&debugger);
+    let first_mismatch = match expectation.first_mismatch() {
+        Some(first_mismatch) => first_mismatch,
+        None => return Ok((None, coverage)),
+    };
+    // UNWRAP: There is a mismatch, so there is a state to blame for it
+    let state = expectation.first_mismatch_state(&debugger).unwrap();
+    let failure = Failure {
+        actual: debugger
+            .output_lines()
+            .into_iter()
+            .map(|(_, line)| String::from(line))
+            .collect(),
+        expected,
+        first_mismatch,
+        state,
+        input_line: debugger.peek_at_state(state).map(|s| s.input_line),
+        command: debugger
+            .peek_at_state(state)
+            .and_then(|s| debugger.source_code.get(s.current_line))
+            .cloned(),
+    };
+    Ok((Some(failure), coverage))
+}
+
+/// Write coverage of the script, summed over all test cases, as lcov tracefile.
+pub fn write_lcov(options: &TestOptions, results: &[TestResult], path: &Path) -> Result<()> {
+    let mut total: Option<Coverage> = None;
+    for coverage in results.iter().filter_map(|result| result.coverage.as_ref()) {
+        match &mut total {
+            Some(total) => total.merge(coverage),
+            None => total = Some(coverage.clone()),
+        }
+    }
+    let total = total.with_context(|| "No test case ran, there is no coverage to write.")?;
+    let source = fs::read_to_string(&options.sed_script)
+        .with_context(|| format!("Failed to read {}", options.sed_script.display()))?;
+    let script = Script::parse(&source).map_err(|error| {
+        anyhow!(
+            "Failed to map coverage to lines of {}: {}",
+            options.sed_script.display(),
+            error
+        )
+    })?;
+    let source_map = SourceMap::new(&source, &script, &total.program);
+    let script_path = fs::canonicalize(&options.sed_script).unwrap_or(options.sed_script.clone());
+    fs::write(path, total.to_lcov(&script_path, &source_map))
+        .with_context(|| format!("Failed to write coverage into {}", path.display()))
+}
+
+/// Each `*.in` file in the directory of the sed script, that has `*.out` file with the same name,
+/// is one test case.
+fn discover_test_cases(sed_script: &Path) -> Result<Vec<TestCase>> {
+    let directory = match sed_script.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut cases: Vec<TestCase> = fs::read_dir(directory)
+        .with_context(|| format!("Failed to list directory {}", directory.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        // Keep paths as short as user wrote them, they are shown in the report
+        .map(|path| match path.strip_prefix("./") {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        })
+        .filter(|path| path.extension().map(|e| e == "in").unwrap_or(false))
+        .filter_map(|input_file| {
+            let expected_output = input_file.with_extension("out");
+            if !expected_output.is_file() {
+                return None;
+            }
+            Some(TestCase {
+                name: test_case_name(&input_file),
+                input_file,
+                expected_output,
+            })
+        })
+        .collect();
+    cases.sort_by(|a, b| a.input_file.cmp(&b.input_file));
+    Ok(cases)
+}
+
+/// Read test cases from manifest. Each line contains path to input file and path to file
+/// with expected output, separated by whitespace. Paths are relative to the manifest.
+/// Empty lines and lines starting with `#` are ignored.
+fn read_manifest(manifest: &Path) -> Result<Vec<TestCase>> {
+    let directory = manifest.parent().unwrap_or_else(|| Path::new("."));
+    let contents = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read manifest {}", manifest.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            let mut paths = line.split_whitespace();
+            match (paths.next(), paths.next()) {
+                (Some(input_file), Some(expected_output)) => {
+                    let input_file = directory.join(input_file);
+                    Ok(TestCase {
+                        name: test_case_name(&input_file),
+                        input_file,
+                        expected_output: directory.join(expected_output),
+                    })
+                }
+                _ => anyhow::bail!(
+                    "Line {} of manifest {} should contain input file and expected output file",
+                    i + 1,
+                    manifest.display()
+                ),
+            }
+        })
+        .collect()
+}
+
+fn test_case_name(input_file: &Path) -> String {
+    input_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| input_file.to_string_lossy().into_owned())
+}
+
+/// Command that opens failing test case in debugger.
+pub fn debug_command(options: &TestOptions, case: &TestCase) -> String {
+    let mut command = vec![
+        String::from("desed"),
+        String::from("--expect"),
+        case.expected_output.to_string_lossy().into_owned(),
+    ];
+    command.extend(options.sed_parameters.iter().cloned());
+    if let Some(sed_path) = &options.sed_path {
+        command.push(format!("--sed-path={}", sed_path));
+    }
+    if let Some(hold_space) = &options.hold_space {
+        command.push(format!(
+            "--hold-space='{}'",
+            hold_space.replace('\'', "'\\''")
+        ));
+    }
+    command.push(options.sed_script.to_string_lossy().into_owned());
+    command.push(case.input_file.to_string_lossy().into_owned());
+    command.join(" ")
+}
+
+/// Show difference between expected and actual output. Only changed lines and few lines
+/// around them are shown.
+pub fn format_diff(expected: &[String], actual: &[String]) -> Vec<String> {
+    let changes = diff(expected, actual);
+    let changed: Vec<bool> = changes
+        .iter()
+        .map(|change| !matches!(change, Change::Same(_)))
+        .collect();
+    let near_change = |i: usize| {
+        let from = i.saturating_sub(DIFF_CONTEXT);
+        let to = (i + DIFF_CONTEXT + 1).min(changed.len());
+        changed[from..to].iter().any(|c| *c)
+    };
+    let mut lines = Vec::new();
+    let mut skipped = false;
+    for (i, change) in changes.iter().enumerate() {
+        if !near_change(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped && !lines.is_empty() {
+            lines.push(String::from("  ..."));
+        }
+        skipped = false;
+        lines.push(match change {
+            Change::Same(line) => format!("  {}", line),
+            Change::Removed(line) => format!("- {}", line),
+            Change::Added(line) => format!("+ {}", line),
+        });
+    }
+    lines
+}
+
+/// Print report of test results in format user asked for.
+pub fn print_report(options: &TestOptions, results: &[TestResult]) {
+    match options.format {
+        ReportFormat::Human => print_human_report(options, results),
+        ReportFormat::JUnit => print!("{}", junit_report(options, results)),
+        ReportFormat::Tap => print!("{}", tap_report(options, results)),
+    }
+}
+
+/// Print human-readable report of test results.
+fn print_human_report(options: &TestOptions, results: &[TestResult]) {
+    for result in results {
+        match &result.outcome {
+            Outcome::Passed => println!("PASS  {}", result.case.name),
+            Outcome::Failed(failure) => {
+                println!("FAIL  {}", result.case.name);
+                for line in failure_details(options, &result.case, failure) {
+                    println!("      {}", line);
+                }
+            }
+            Outcome::Error(error) => {
+                println!("ERROR {}", result.case.name);
+                println!("      {}", error);
+            }
+        }
+    }
+    let (passed, failed, errors) = count_outcomes(results);
+    println!("\n{} passed, {} failed, {} errors", passed, failed, errors);
+}
+
+/// Count passed, failed and errored test cases.
+fn count_outcomes(results: &[TestResult]) -> (usize, usize, usize) {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    (
+        count(|o| matches!(o, Outcome::Passed)),
+        count(|o| matches!(o, Outcome::Failed(_))),
+        count(|o| matches!(o, Outcome::Error(_))),
+    )
+}
+
+/// Full description of a failure: where did the output start to differ, the diff,
+/// and how to debug it.
+fn failure_details(options: &TestOptions, case: &TestCase, failure: &Failure) -> Vec<String> {
+    let mut lines = vec![describe_failure(failure)];
+    lines.extend(format_diff(&failure.expected, &failure.actual));
+    lines.push(format!("debug: {}", debug_command(options, case)));
+    lines
+}
+
+/// Report test results as JUnit XML, with one test suite for the script and one test case
+/// per input file.
+fn junit_report(options: &TestOptions, results: &[TestResult]) -> String {
+    let (_, failed, errors) = count_outcomes(results);
+    let suite = xml_attribute(&options.sed_script.to_string_lossy());
+    let mut xml = vec![
+        String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#),
+        format!(
+            r#"<testsuites tests="{}" failures="{}" errors="{}">"#,
+            results.len(),
+            failed,
+            errors
+        ),
+        format!(
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}">"#,
+            suite,
+            results.len(),
+            failed,
+            errors
+        ),
+    ];
+    for result in results {
+        let testcase = format!(
+            r#"    <testcase name="{}" classname="{}""#,
+            xml_attribute(&result.case.name),
+            suite
+        );
+        match &result.outcome {
+            Outcome::Passed => xml.push(format!("{}/>", testcase)),
+            Outcome::Failed(failure) => {
+                xml.push(format!("{}>", testcase));
+                xml.push(format!(
+                    r#"      <failure message="{}" type="WrongOutput">{}</failure>"#,
+                    xml_attribute(&describe_failure(failure)),
+                    xml_escape(&failure_details(options, &result.case, failure).join("\n"))
+                ));
+                xml.push(String::from("    </testcase>"));
+            }
+            Outcome::Error(error) => {
+                xml.push(format!("{}>", testcase));
+                xml.push(format!(
+                    r#"      <error message="{}" type="SedError"/>"#,
+                    xml_attribute(error)
+                ));
+                xml.push(String::from("    </testcase>"));
+            }
+        }
+    }
+    xml.push(String::from("  </testsuite>"));
+    xml.push(String::from("</testsuites>"));
+    xml.push(String::new());
+    xml.join("\n")
+}
+
+/// Escape text so it can be used as XML attribute value.
+fn xml_attribute(text: &str) -> String {
+    xml_escape(text).replace('\n', "&#10;")
+}
+
+/// Report test results in Test Anything Protocol (version 13), with one test per input file.
+/// Details of failures are attached as YAML blocks.
+fn tap_report(options: &TestOptions, results: &[TestResult]) -> String {
+    let mut tap = vec![
+        String::from("TAP version 13"),
+        format!("1..{}", results.len()),
+    ];
+    for (i, result) in results.iter().enumerate() {
+        match &result.outcome {
+            Outcome::Passed => tap.push(format!("ok {} - {}", i + 1, result.case.name)),
+            Outcome::Failed(failure) => {
+                tap.push(format!("not ok {} - {}", i + 1, result.case.name));
+                tap.push(String::from("  ---"));
+                tap.push(format!(
+                    "  message: {}",
+                    yaml_string(&describe_failure(failure))
+                ));
+                tap.push(format!("  state: {}", failure.state));
+                if let Some(input_line) = failure.input_line {
+                    tap.push(format!("  input_line: {}", input_line));
+                }
+                tap.push(format!("  output_line: {}", failure.first_mismatch + 1));
+                // Diff lines start with spaces, so indentation must be given explicitly
+                tap.push(String::from("  diff: |2"));
+                for line in format_diff(&failure.expected, &failure.actual) {
+                    tap.push(format!("    {}", line));
+                }
+                tap.push(format!(
+                    "  debug: {}",
+                    yaml_string(&debug_command(options, &result.case))
+                ));
+                tap.push(String::from("  ..."));
+            }
+            Outcome::Error(error) => {
+                tap.push(format!("not ok {} - {}", i + 1, result.case.name));
+                tap.push(String::from("  ---"));
+                tap.push(format!("  message: {}", yaml_string(error)));
+                tap.push(String::from("  ..."));
+            }
+        }
+    }
+    tap.push(String::new());
+    tap.join("\n")
+}
+
+/// Describe where did the output start to differ.
+pub fn describe_failure(failure: &Failure) -> String {
+    format!(
+        "first wrong output line {}, printed by state {}{}{}",
+        failure.first_mismatch + 1,
+        failure.state,
+        failure
+            .command
+            .as_ref()
+            .map(|command| format!(" ({})", command))
+            .unwrap_or_default(),
+        failure
+            .input_line
+            .map(|line| format!(" while processing input line {}", line))
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_outputs_differing_at_both_ends() {
+        let expected: Vec<String> = (0..100_000).map(|i| i.to_string()).collect();
+        let mut actual = expected.clone();
+        actual[0] = String::from("first");
+        actual[99_999] = String::from("last");
+        assert_eq!(
+            format_diff(&expected, &actual),
+            ["- 0", "+ first", "  1", "  2", "  ...", "  99997", "  99998", "- 99999", "+ last",]
+        );
+    }
+}
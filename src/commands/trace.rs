@@ -0,0 +1,140 @@
+use crate::cli::{TraceFormat, TraceOptions};
+use crate::commands::escape::{csv_field, json_string};
+use crate::sed::debugger::{Debugger, DebuggingState};
+use anyhow::Result;
+use std::io::{self, Write};
+
+/// One debugging state, with everything the trace shows about it.
+struct Record<'a> {
+    /// Number of the state (0-based), the same as in the TUI
+    index: usize,
+    state: &'a DebuggingState,
+    /// Command on the line that is about to be executed, as desed shows it.
+    /// There is no command at the end of cycle.
+    command: Option<&'a str>,
+}
+
+/// Run sed and print all debugging states that pass the filters.
+pub fn print_trace(options: &TraceOptions) -> Result<()> {
+    let debugger = Debugger::new(options.options.clone())?;
+    let records = (0..debugger.count_of_states())
+        .filter_map(|index| debugger.peek_at_state(index).map(|state| (index, state)))
+        .filter(|(_, state)| {
+            options
+                .input_lines
+                .map(|range| range.contains(state.input_line))
+                .unwrap_or(true)
+                && options
+                    .script_lines
+                    .map(|range| range.contains(state.current_line + 1))
+                    .unwrap_or(true)
+        })
+        .map(|(index, state)| Record {
+            index,
+            state,
+            command: debugger
+                .source_code
+                .get(state.current_line)
+                .map(|command| command.trim()),
+        });
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if options.format == TraceFormat::Csv {
+        writeln!(
+            out,
+            "state,input_line,line,command,pattern,hold,regex_registers,output"
+        )?;
+    }
+    for record in records {
+        let result = match options.format {
+            TraceFormat::Human => write_human(&mut out, &record),
+            TraceFormat::JsonLines => writeln!(out, "{}", json_record(&record)),
+            TraceFormat::Csv => writeln!(out, "{}", csv_record(&record)),
+        };
+        match result {
+            // Output was closed, for example by `head`. This is not an error.
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+    if let Some(infinite_loop) = &debugger.infinite_loop {
+        eprintln!("{}", infinite_loop.describe(&debugger.source_code));
+    }
+    Ok(())
+}
+
+/// Print state on several lines, so it's easy to read and grep.
+fn write_human(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    let state = record.state;
+    writeln!(
+        out,
+        "STATE {} INPUT LINE {} LINE {}: {}",
+        record.index,
+        state.input_line,
+        state.current_line + 1,
+        record.command.unwrap_or("(end of cycle)")
+    )?;
+    writeln!(out, "  PATTERN: {}", state.pattern_buffer)?;
+    writeln!(out, "  HOLD:    {}", state.hold_buffer)?;
+    for (i, register) in state.matched_regex_registers.iter().enumerate() {
+        writeln!(out, "  REGEX \\{}: {}", i, register)?;
+    }
+    for line in state.output.iter().flatten() {
+        writeln!(out, "  OUTPUT:  {}", line)?;
+    }
+    Ok(())
+}
+
+/// Format state as one JSON object.
+fn json_record(record: &Record) -> String {
+    let state = record.state;
+    let list = |items: &[String]| {
+        format!(
+            "[{}]",
+            items
+                .iter()
+                .map(|item| json_string(item))
+                .collect::<Vec<String>>()
+                .join(",")
+        )
+    };
+    format!(
+        r#"{{"state":{},"input_line":{},"line":{},"command":{},"pattern":{},"hold":{},"regex_registers":{},"output":{}}}"#,
+        record.index,
+        state.input_line,
+        state.current_line + 1,
+        record
+            .command
+            .map(json_string)
+            .unwrap_or_else(|| String::from("null")),
+        json_string(&state.pattern_buffer),
+        json_string(&state.hold_buffer),
+        list(&state.matched_regex_registers),
+        list(state.output.as_deref().unwrap_or(&[])),
+    )
+}
+
+/// Format state as one CSV row. Regex registers and output lines are separated
+/// by newlines inside their fields.
+fn csv_record(record: &Record) -> String {
+    let state = record.state;
+    [
+        record.index.to_string(),
+        state.input_line.to_string(),
+        (state.current_line + 1).to_string(),
+        String::from(record.command.unwrap_or("")),
+        state.pattern_buffer.clone(),
+        state.hold_buffer.clone(),
+        state.matched_regex_registers.join("\n"),
+        state
+            .output
+            .as_ref()
+            .map(|output| output.join("\n"))
+            .unwrap_or_default(),
+    ]
+    .iter()
+    .map(|field| csv_field(field))
+    .collect::<Vec<String>>()
+    .join(",")
+}
@@ -0,0 +1,312 @@
+/// Find longest common subsequence of two sequences.
+///
+/// Returns pairs of indices `(old, new)` of items that are the same in both sequences,
+/// in increasing order.
+///
+/// This is Myers' O(ND) algorithm in its linear space variant, so it takes time proportional
+/// to the length of the sequences times the number of differences, and memory proportional
+/// to the length only. Whole outputs of sed are compared with it.
+pub fn longest_common_subsequence<T: PartialEq>(old: &[T], new: &[T]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    common_subsequence(old, new, (0, 0), &mut pairs);
+    pairs
+}
+
+/// Add pairs of the longest common subsequence of `old` and `new` to `pairs`. Indices are
+/// shifted by `offset`, as the sequences are parts of the original ones.
+fn common_subsequence<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    offset: (usize, usize),
+    pairs: &mut Vec<(usize, usize)>,
+) {
+    // Common prefix and suffix are matched right away, this keeps the search below short
+    // for the usual case of a few changed lines.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    pairs.extend((0..prefix).map(|i| (offset.0 + i, offset.1 + i)));
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    // If either is empty, the rest is just insertion or deletion
+    if !old_middle.is_empty() && !new_middle.is_empty() {
+        let middle_offset = (offset.0 + prefix, offset.1 + prefix);
+        let ((x, y), (u, v)) = middle_snake(old_middle, new_middle);
+        common_subsequence(&old_middle[..x], &new_middle[..y], middle_offset, pairs);
+        pairs.extend((0..u - x).map(|i| (middle_offset.0 + x + i, middle_offset.1 + y + i)));
+        common_subsequence(
+            &old_middle[u..],
+            &new_middle[v..],
+            (middle_offset.0 + u, middle_offset.1 + v),
+            pairs,
+        );
+    }
+    pairs.extend((0..suffix).map(|i| {
+        (
+            offset.0 + old.len() - suffix + i,
+            offset.1 + new.len() - suffix + i,
+        )
+    }));
+}
+
+/// Find the middle snake of the shortest edit script: a run of equal items, given by its
+/// start and end, that splits the script into two halves with about the same number of edits.
+///
+/// Paths are searched from both ends at once until they meet. `forward[k]` is the furthest
+/// position in `old` reached on diagonal `k = x - y` from the start, `backward[k]` the same
+/// counted from the end. The sequences must not be empty.
+fn middle_snake<T: PartialEq>(old: &[T], new: &[T]) -> ((usize, usize), (usize, usize)) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let mut forward = vec![0isize; 2 * max as usize + 1];
+    let mut backward = vec![0isize; 2 * max as usize + 1];
+    let index = |k: isize| (k + max) as usize;
+    for d in 0..max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let start = (x, x - k);
+            while x < n && x - k < m && old[x as usize] == new[(x - k) as usize] {
+                x += 1;
+            }
+            forward[index(k)] = x;
+            // Diagonal k from the start is diagonal delta - k from the end, which the search
+            // from the end reached in the previous round
+            let reverse = delta - k;
+            if delta % 2 != 0 && reverse.abs() < d && x + backward[index(reverse)] >= n {
+                return (
+                    (start.0 as usize, start.1 as usize),
+                    (x as usize, (x - k) as usize),
+                );
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let end = (n - x, m - (x - k));
+            while x < n && x - k < m && old[(n - x - 1) as usize] == new[(m - (x - k) - 1) as usize]
+            {
+                x += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if delta % 2 == 0 && forward_k.abs() <= d && x + forward[index(forward_k)] >= n {
+                return (
+                    ((n - x) as usize, (m - (x - k)) as usize),
+                    (end.0 as usize, end.1 as usize),
+                );
+            }
+        }
+    }
+    unreachable!("paths from both ends always meet within (n + m) / 2 edits")
+}
+
+/// Figure out where did each line of old text move in the new text.
+///
+/// Lines that stayed the same are mapped to their new position. Lines that were changed
+/// or removed are mapped relative to the nearest unchanged line above them, so for example
+/// a line that was edited stays in place even if some lines were inserted above it.
+///
+/// The result has one item for each old line, and every position is lower than
+/// `new.len()` (unless `new` is empty).
+pub fn line_mapping<T: PartialEq>(old: &[T], new: &[T]) -> Vec<usize> {
+    let mut mapping: Vec<Option<usize>> = vec![None; old.len()];
+    for (i, j) in longest_common_subsequence(old, new) {
+        mapping[i] = Some(j);
+    }
+
+    let last_line = new.len().saturating_sub(1);
+    let mut anchor: Option<(usize, usize)> = None;
+    mapping
+        .iter()
+        .enumerate()
+        .map(|(i, target)| match target {
+            Some(j) => {
+                anchor = Some((i, *j));
+                *j
+            }
+            None => match anchor {
+                Some((old_anchor, new_anchor)) => new_anchor + (i - old_anchor),
+                None => i,
+            }
+            .min(last_line),
+        })
+        .collect()
+}
+
+/// Figure out which line of the new text each line of old text became, if any.
+///
+/// Lines that stayed the same are mapped to their new position. Between them, changed
+/// old lines are paired with new lines in order, as they were most likely edited.
+/// Old lines left over were removed and have no new position. No two old lines are
+/// mapped to the same new line.
+pub fn line_moves<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Option<usize>> {
+    let mut moves = vec![None; old.len()];
+    let (mut i, mut j) = (0, 0);
+    for (old_index, new_index) in longest_common_subsequence(old, new)
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        for (k, target) in (i..old_index).zip(j..new_index) {
+            moves[k] = Some(target);
+        }
+        if old_index < old.len() {
+            moves[old_index] = Some(new_index);
+        }
+        i = old_index + 1;
+        j = new_index + 1;
+    }
+    moves
+}
+
+/// One line of a diff between two sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<T> {
+    /// Item is present in both sequences
+    Same(T),
+    /// Item is present only in the old sequence
+    Removed(T),
+    /// Item is present only in the new sequence
+    Added(T),
+}
+
+/// Compute difference between two sequences. Removed items go before added ones.
+pub fn diff<'a, T: PartialEq>(old: &'a [T], new: &'a [T]) -> Vec<Change<&'a T>> {
+    let mut changes = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    for (old_index, new_index) in longest_common_subsequence(old, new)
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        changes.extend(old[i..old_index].iter().map(Change::Removed));
+        changes.extend(new[j..new_index].iter().map(Change::Added));
+        if old_index < old.len() {
+            changes.push(Change::Same(&old[old_index]));
+        }
+        i = old_index + 1;
+        j = new_index + 1;
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of the longest common subsequence, computed the slow and obvious way.
+    fn lcs_length(old: &[u8], new: &[u8]) -> usize {
+        let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lengths[i][j] = if old[i] == new[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        lengths[0][0]
+    }
+
+    #[test]
+    fn common_subsequence_is_longest() {
+        // Pseudo-random sequences over a small alphabet, so there are many ways to match them
+        let mut seed: u32 = 1;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    b'a' + (seed >> 16) as u8 % 4
+                })
+                .collect()
+        };
+        for round in 0..200 {
+            let old = random(round % 23);
+            let new = random(round % 17);
+            let pairs = longest_common_subsequence(&old, &new);
+            assert_eq!(pairs.len(), lcs_length(&old, &new), "{:?} {:?}", old, new);
+            assert!(pairs.iter().all(|(i, j)| old[*i] == new[*j]));
+            assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        }
+    }
+
+    #[test]
+    fn large_inputs_differing_at_both_ends() {
+        let old: Vec<String> = (0..100_000).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new[0] = String::from("first");
+        new[99_999] = String::from("last");
+        new.insert(50_000, String::from("middle"));
+        let pairs = longest_common_subsequence(&old, &new);
+        assert_eq!(pairs.len(), 99_998);
+        assert_eq!(pairs[0], (1, 1));
+        assert_eq!(pairs[50_000], (50_001, 50_002));
+        let mapping = line_mapping(&old, &new);
+        assert_eq!(mapping[99_999], 100_000);
+    }
+
+    #[test]
+    fn unchanged_lines_follow_insertions() {
+        assert_eq!(
+            line_mapping(&["a", "b", "c"], &["x", "a", "b", "y", "c"]),
+            [1, 2, 4]
+        );
+    }
+
+    #[test]
+    fn deleted_lines_stay_next_to_the_line_above() {
+        assert_eq!(
+            line_mapping(&["a", "b", "c", "d"], &["a", "d"]),
+            [0, 1, 1, 1]
+        );
+        assert_eq!(line_mapping(&["a", "b", "c"], &["c"]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn everything_deleted() {
+        assert_eq!(line_mapping(&["a", "b"], &[]), [0, 0]);
+    }
+
+    #[test]
+    fn edited_line_stays_in_place() {
+        assert_eq!(
+            line_mapping(&["a", "b", "c"], &["new", "a", "B", "c"]),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn removed_lines_have_no_new_position() {
+        assert_eq!(
+            line_moves(&["a", "b", "c", "d"], &["a", "B", "d"]),
+            [Some(0), Some(1), None, Some(2)]
+        );
+        assert_eq!(line_moves(&["a", "b"], &["x", "a"]), [Some(1), None]);
+        assert_eq!(line_moves::<&str>(&["a"], &[]), [None]);
+    }
+
+    #[test]
+    fn diff_lists_removed_before_added() {
+        assert_eq!(
+            diff(&["a", "b", "c"], &["a", "B", "c", "d"]),
+            [
+                Change::Same(&"a"),
+                Change::Removed(&"b"),
+                Change::Added(&"B"),
+                Change::Same(&"c"),
+                Change::Added(&"d"),
+            ]
+        );
+    }
+}
@@ -1,26 +1,123 @@
 mod sed;
 use sed::debugger::Debugger;
 mod cli;
-use cli::Options;
+use cli::{GraphOptions, LintOptions, Mode, Options, TestOptions, TraceOptions};
+mod commands;
+mod diff;
 mod file_watcher;
 mod ui;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use file_watcher::FileWatcher;
+use sed::expectation::Expectation;
+use std::env;
+use std::path::Path;
+use std::process::Command;
 use ui::generic::{ApplicationExitReason, UiAgent};
-use ui::tui::Tui;
+use ui::session::Session;
+use ui::tui::{ToolPane, Tui};
 
 fn main() {
+    let exit_code = match cli::parse_arguments() {
+        Ok(Mode::Debug(settings)) => debug(&settings),
+        Ok(Mode::Test(settings)) => test(&settings),
+        Ok(Mode::Trace(settings)) => trace(&settings),
+        Ok(Mode::Lint(settings)) => lint(&settings),
+        Ok(Mode::Graph(settings)) => graph(&settings),
+        Err(error) => {
+            eprintln!("An error occurred: {}", error);
+            1
+        }
+    };
+    std::process::exit(exit_code);
+}
+
+/// Debug sed script in TUI and restore terminal afterwards. Returns exit code.
+fn debug(settings: &Options) -> i32 {
     // If an error occurs, we do not want to clear terminal, it's useful for the error to remain visible.
     // But we want to clear terminal when user just exited GUI normally.
     let mut clear_terminal: bool = true;
+    let mut exit_code = 0;
 
-    if let Err(error) = run(0) {
+    if let Err(error) = run(settings, None, None) {
         eprintln!("An error occurred: {}", error);
         clear_terminal = false;
+        exit_code = 1;
     }
     if let Err(error) = Tui::restore_terminal_state(clear_terminal) {
         eprintln!("An error occurred while attempting to reset terminal to previous state. Consider using 'reset' command. Error: {}", error);
     }
+    exit_code
+}
+
+/// Run all test cases without TUI and report results. Returns exit code,
+/// which is non-zero if any test case failed.
+fn test(settings: &TestOptions) -> i32 {
+    let results = match commands::test::run_tests(settings) {
+        Ok(results) => results,
+        Err(error) => {
+            eprintln!("An error occurred: {:#}", error);
+            return 2;
+        }
+    };
+    commands::test::print_report(settings, &results);
+    if let Some(lcov) = &settings.lcov {
+        if let Err(error) = commands::test::write_lcov(settings, &results, lcov) {
+            eprintln!("An error occurred: {:#}", error);
+            return 2;
+        }
+    }
+    let all_passed = results
+        .iter()
+        .all(|result| matches!(result.outcome, commands::test::Outcome::Passed));
+    if settings.open {
+        // Test cases where sed failed to run can't be debugged
+        let first_failure = results
+            .iter()
+            .find(|result| matches!(result.outcome, commands::test::Outcome::Failed(_)));
+        if let Some(result) = first_failure {
+            let case = &result.case;
+            debug(&settings.debug_options(case.input_file.clone(), case.expected_output.clone()));
+        }
+    }
+    if all_passed {
+        0
+    } else {
+        1
+    }
+}
+
+/// Print all debugging states without TUI. Returns exit code.
+fn trace(settings: &TraceOptions) -> i32 {
+    match commands::trace::print_trace(settings) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("An error occurred: {:#}", error);
+            1
+        }
+    }
+}
+
+/// Print problems found in the script. Returns exit code, which is non-zero if there are any.
+fn lint(settings: &LintOptions) -> i32 {
+    match commands::lint::print_diagnostics(settings) {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(error) => {
+            eprintln!("An error occurred: {:#}", error);
+            2
+        }
+    }
+}
+
+/// Print control-flow graph of the script. Returns exit code.
+fn graph(settings: &GraphOptions) -> i32 {
+    match commands::graph::print_graph(settings) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("An error occurred: {:#}", error);
+            1
+        }
+    }
 }
 
 fn watch_files(settings: &Options) -> Result<FileWatcher> {
@@ -33,15 +130,103 @@ fn watch_files(settings: &Options) -> Result<FileWatcher> {
     Result::Ok(fw)
 }
 
-/// Debug application and start at specified
-/// state if possible
-fn run(target_state_number: usize) -> Result<()> {
-    let settings = cli::parse_arguments()?;
-    let watcher = watch_files(&settings)?;
-    let debugger = Debugger::new(settings)?;
-    let tui = Tui::new(&debugger, watcher, target_state_number)?;
+/// Save session, so it can be restored next time the same script is debugged.
+///
+/// Failing to do so is not fatal, desed works just fine without saved sessions.
+fn save_session(session: &Session, sed_script: &Path, verbose: bool) {
+    if let Err(error) = session.save(sed_script) {
+        if verbose {
+            eprintln!("[Info] {:#}", error);
+        }
+    }
+}
+
+/// Debug application and restore given session,
+/// including the state that was examined, if possible
+///
+/// If no session is given, the one saved for this script
+/// is restored. If debugger from previous run is given,
+/// user can compare the two executions.
+fn run(
+    settings: &Options,
+    session: Option<Session>,
+    previous_debugger: Option<Debugger>,
+) -> Result<()> {
+    let sed_script = settings.sed_script.clone();
+    let verbose = settings.verbose;
+    let watcher = watch_files(settings)?;
+    let debugger = Debugger::new(settings.clone())?;
+    let expectation = match &settings.expected_output {
+        Some(path) => Some(Expectation::read(path, &debugger)?),
+        None => None,
+    };
+    let is_reload = session.is_some();
+    let mut session = session
+        .or_else(|| Session::load(&sed_script))
+        .unwrap_or_default();
+    // Lines set below refer to the current source code
+    session.relocate(&debugger.source_code);
+    // When starting, go straight to the state that printed the first wrong line
+    if let Some(expectation) = &expectation {
+        if !is_reload {
+            if let Some(state) = expectation.first_mismatch_state(&debugger) {
+                session.anchor = debugger.anchor(state);
+            }
+            session.tool_pane = Some(ToolPane::Expectation);
+        }
+    }
+    // The same goes for infinite loop, start where the loop is closed
+    if let Some(infinite_loop) = &debugger.infinite_loop {
+        if !is_reload {
+            session.anchor = debugger.anchor(infinite_loop.repeated_state);
+            if let Some(line) = infinite_loop.closing_branch {
+                session.cursor = line;
+            }
+        }
+    }
+    let tui = Tui::new(
+        settings,
+        debugger,
+        previous_debugger,
+        expectation,
+        watcher,
+        session,
+    )?;
     match tui.start()? {
-        ApplicationExitReason::UserExit => Ok(()),
-        ApplicationExitReason::Reload(instruction_number) => run(instruction_number),
+        ApplicationExitReason::UserExit(session) => {
+            save_session(&session, &sed_script, verbose);
+            Ok(())
+        }
+        ApplicationExitReason::Reload(session, debugger) => {
+            save_session(&session, &sed_script, verbose);
+            run(settings, Some(session), Some(debugger))
+        }
+        ApplicationExitReason::Edit(session, line, debugger) => {
+            save_session(&session, &sed_script, verbose);
+            edit_script(&sed_script, line)?;
+            run(settings, Some(session), Some(debugger))
+        }
+    }
+}
+
+/// Suspend TUI and open the script in $EDITOR at given line (0-based), waiting until
+/// user closes it. TUI starts again when the script is reloaded, unless the editor failed.
+///
+/// If $EDITOR isn't set, vi is used.
+fn edit_script(sed_script: &Path, line: usize) -> Result<()> {
+    Tui::restore_terminal_state(true)?;
+    let editor = env::var("EDITOR").unwrap_or_else(|_| String::from("vi"));
+    // Like git, let shell handle arguments and quoting in editor, such as "code --wait"
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg("sh")
+        .arg(format!("+{}", line + 1))
+        .arg(sed_script)
+        .status()
+        .with_context(|| format!("Failed to open script in editor \"{}\"", editor))?;
+    if !status.success() {
+        bail!("Editor \"{}\" failed with {}", editor, status);
     }
+    Ok(())
 }
@@ -1,8 +1,16 @@
-use super::debugger::DebuggingState;
+use super::debugger::{DebuggingState, InfiniteLoop};
+use super::evaluation::replacement;
 use crate::cli::Options;
 use anyhow::{Context, Result};
 use std::collections::HashMap;
-use std::process::{Command, Stdio};
+use std::io::{BufRead, BufReader};
+use std::iter::Peekable;
+use std::process::{Child, Command, Stdio};
+
+/// Label used by the prelude that seeds hold space, see [SedCommunicator::command_line].
+const SEED_LABEL: &str = "desed_seed";
+/// First command of the prelude that seeds hold space, as sed prints it.
+const SEED_PRELUDE_START: &str = "0,/^/ {";
 
 /// This handles communication with GNU sed.
 pub struct SedCommunicator {
@@ -12,80 +20,178 @@ impl SedCommunicator {
     pub fn new(options: Options) -> Self {
         SedCommunicator { options }
     }
+    /// Run sed and parse its debug output while it's running.
+    ///
+    /// If the script gets into an infinite loop, sed is stopped early,
+    /// see [SedCommunicator::parse_state_frames].
     pub fn get_execution_info_from_sed(&mut self) -> Result<DebugInfoFromSed> {
-        let output = self.get_sed_output()?;
+        let mut sed = self.spawn_sed()?;
+        // UNWRAP: Stdout of sed is always piped, see spawn_sed
+        let stdout = sed.stdout.take().unwrap();
+        let mut read_error = None;
+        let mut lines = self
+            .skip_seed_prelude(BufReader::new(stdout).lines().map_while(|line| match line {
+                Ok(line) => Some(line),
+                Err(error) => {
+                    read_error = Some(error);
+                    None
+                }
+            }))
+            .peekable();
+
+        // If sed returned no output (so it failed) and sed
+        // path wasn't specified by user,
+        // change executing path to "gsed" and try again.
+        if lines.peek().is_none() {
+            drop(lines);
+            // Sed has already failed, we don't care how
+            let _ = sed.wait();
+            if self.options.sed_path.is_none() {
+                self.options.sed_path = Some(String::from("gsed"));
+                if self.options.verbose {
+                    eprintln!(
+                        "[Info] Sed failed and didn't return any output. As sed path wasn't specified, trying again with \"gsed\". If even that won't work, make sure \
+                                sed is able to process your script. Most common mistake is forgetting to use -E."
+                    );
+                }
+                return self.get_execution_info_from_sed();
+            }
+            anyhow::bail!(self.failure_message());
+        }
 
-        let program_source = self.parse_program_source(&output);
+        let mut program_source = self.parse_program_source(&mut lines);
+        if self.options.hold_space.is_some() {
+            // The prelude is a single block, so it ends with the first closing brace
+            let prelude_end = program_source.iter().position(|line| line == "}");
+            program_source.drain(..=prelude_end.unwrap_or(0));
+        }
         let label_jump_map = self.build_jump_map(&program_source);
-        let frames = self.parse_state_frames(&output, &label_jump_map, program_source.len());
+        let frames = self.parse_state_frames(&mut lines, &label_jump_map, &program_source);
+        drop(lines);
+        if frames.infinite_loop.is_some() {
+            // Sed would run forever, there is nothing more to learn from it.
+            // It might have just finished by itself, so failing to kill it is fine.
+            let _ = sed.kill();
+        }
+        let _ = sed.wait();
+        if let Some(error) = read_error {
+            return Err(error).with_context(|| "String received from sed doesn't seem to be UTF-8. If this continues to happen, please report a bug.");
+        }
+        if self.options.verbose {
+            eprintln!(
+                "[Info] Called sed with \"{}\", which produced {} states.",
+                self.command_line().join(" "),
+                frames.states.len()
+            );
+        }
         Ok(DebugInfoFromSed {
             program_source,
-            states: frames.0,
-            last_output: frames.1,
+            states: frames.states,
+            last_output: frames.last_output,
+            infinite_loop: frames.infinite_loop,
         })
     }
-    fn get_sed_output(&mut self) -> Result<String> {
-        let mut path_to_be_used: &String = &String::from("sed");
-        if let Some(path) = &self.options.sed_path {
-            path_to_be_used = path;
+
+    /// Path to sed binary that should be used.
+    pub fn sed_path(&self) -> &str {
+        self.options.sed_path.as_deref().unwrap_or("sed")
+    }
+
+    /// Parameters sed is launched with.
+    ///
+    /// If hold space should be seeded, the script is prefixed with a prelude that does so
+    /// exactly once, even if `D` restarts the cycle. It resets the flag of `t`, so the
+    /// script can't tell it ran.
+    fn command_line(&self) -> Vec<String> {
+        let mut command_line: Vec<String> = vec![String::from(self.sed_path())];
+        command_line.extend(self.options.sed_parameters.iter().cloned());
+        command_line.push(String::from("--debug"));
+        if let Some(hold_space) = &self.options.hold_space {
+            command_line.extend([
+                String::from("-e"),
+                format!(
+                    "0,/^/{{x;s/^/{}/;x;t{}",
+                    replacement(hold_space),
+                    SEED_LABEL
+                ),
+                String::from("-e"),
+                format!(":{}", SEED_LABEL),
+                String::from("-e"),
+                String::from("}"),
+            ]);
         }
+        command_line.push(String::from("-f"));
+        command_line.push(self.options.sed_script.to_string_lossy().into_owned());
+        command_line.push(self.options.input_file.to_string_lossy().into_owned());
+        command_line
+    }
 
-        let mandatory_parameters = [
-            "--debug",
-            "-f",
-            self.options
-                .sed_script
-                .to_str()
-                .with_context(|| "Invalid sed script path. Is it valid UTF-8?".to_string())?,
-            self.options
-                .input_file
-                .to_str()
-                .with_context(|| "Invalid input path. Is it valid UTF-8?".to_string())?,
-        ];
-        let constructed_cmd_line = self
-            .options
-            .sed_parameters
-            .iter()
-            .map(|s| s.as_str())
-            .chain(mandatory_parameters.iter().copied())
-            .collect::<Vec<&str>>();
-        let sed_debug_command = Command::new(path_to_be_used)
-            .args(&constructed_cmd_line)
+    /// Message shown when sed didn't return anything.
+    fn failure_message(&self) -> String {
+        format!("Sed failed to return output. Shouldn't you use -E option? Are you using GNU sed? Is there sed/gsed in $PATH?{}" ,
+            if self.options.verbose { format!("\n[Info] Sed was called using \"{}\"", self.command_line().join(" ")) } else { String::new() }
+        )
+    }
+
+    /// Launch sed with debug output piped to us.
+    fn spawn_sed(&self) -> Result<Child> {
+        self.options
+            .sed_script
+            .to_str()
+            .with_context(|| "Invalid sed script path. Is it valid UTF-8?".to_string())?;
+        self.options
+            .input_file
+            .to_str()
+            .with_context(|| "Invalid input path. Is it valid UTF-8?".to_string())?;
+        let command_line = self.command_line();
+        Command::new(&command_line[0])
+            .args(&command_line[1..])
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
-            .output()
+            .spawn()
             .ok()
-            .with_context(
-                || format!("Sed failed to return output. Shouldn't you use -E option? Are you using GNU sed? Is there sed/gsed in $PATH?{}" ,
-                    if  self.options.verbose{ format!("\n[Info] Sed was called using \"{} {}\"", &path_to_be_used, constructed_cmd_line.join(" ")) } else { String::new() }
-            ))?
-            .stdout;
-
-        if self.options.verbose {
-            eprintln!(
-                "[Info] Called sed with \"{} {}\", which returned {} lines of output.",
-                path_to_be_used,
-                constructed_cmd_line.join(" "),
-                sed_debug_command.len()
-            );
-        }
+            .with_context(|| self.failure_message())
+    }
 
-        // If sed returned no output (so it failed) and sed
-        // path wasn't specified by user,
-        // change executing path to "gsed" and try again.
-        if self.options.sed_path.is_none() && sed_debug_command.is_empty() {
-            self.options.sed_path = Some(String::from("gsed"));
-            if self.options.verbose {
-                eprintln!(
-                        "[Info] Sed failed and didn't return any output. As sed path wasn't specified, trying again with \"gsed\". If even that won't work, make sure \
-                                sed is able to process your script. Most common mistake is forgetting to use -E."
-                    );
+    /// Remove commands of the prelude that seeds hold space from debug output of sed,
+    /// so it looks like the hold space was never empty.
+    ///
+    /// The prelude runs first in each cycle, including cycles restarted by `D`. Pattern
+    /// and hold space it prints are kept, so states that follow have the right buffers.
+    fn skip_seed_prelude(
+        &self,
+        sed_output: impl Iterator<Item = String>,
+    ) -> impl Iterator<Item = String> {
+        let seeding = self.options.hold_space.is_some();
+        let mut prelude_pending = false;
+        let mut in_prelude = false;
+        sed_output.filter(move |line| {
+            if !seeding {
+                return true;
             }
-            return self.get_sed_output();
-        }
-
-        String::from_utf8(sed_debug_command).with_context(|| "String received from sed doesn't seem to be UTF-8. If this continues to happen, please report a bug.")
+            if line.starts_with("INPUT:") {
+                prelude_pending = true;
+                return true;
+            }
+            if let Some(command) = line.strip_prefix("COMMAND:") {
+                let command = command.trim();
+                if prelude_pending && command == SEED_PRELUDE_START {
+                    in_prelude = true;
+                }
+                if in_prelude {
+                    in_prelude = command != "}";
+                    prelude_pending = false;
+                    return false;
+                }
+                // D restarts the cycle without reading input, if there is a newline to delete
+                prelude_pending = command_name(command) == Some('D');
+                return true;
+            }
+            // Only `s` of the prelude matches a regex, and matches just the empty string
+            !(in_prelude
+                && (line.starts_with("MATCHED REGEX REGISTERS") || line.starts_with("  regex[")))
+        })
     }
 
     /// Wait for line that looks like "SED PROGRAM:"
@@ -94,14 +200,21 @@ impl SedCommunicator {
     /// into output vector.
     ///
     /// When we meet a line that doesn't start with two spaces, stop reading and return.
-    fn parse_program_source(&self, sed_output: &str) -> Vec<String> {
-        sed_output
-            .lines()
-            .skip_while(|line| *line != "SED PROGRAM:")
-            .skip(1)
-            .take_while(|line| !line.starts_with("INPUT:   '"))
-            .map(|line| String::from(line.trim()))
-            .collect()
+    fn parse_program_source(
+        &self,
+        sed_output: &mut Peekable<impl Iterator<Item = String>>,
+    ) -> Vec<String> {
+        for line in sed_output.by_ref() {
+            if line == "SED PROGRAM:" {
+                break;
+            }
+        }
+        let mut program_source = Vec::new();
+        // The first line of execution is left for parse_state_frames
+        while let Some(line) = sed_output.next_if(|line| !line.starts_with("INPUT:   '")) {
+            program_source.push(String::from(line.trim()));
+        }
+        program_source
     }
 
     /// Parse state frames. They look like this:
@@ -152,26 +265,31 @@ impl SedCommunicator {
     /// ```
     ///
     /// This returns individual frames *and* output of the last segment of the sed script.
+    ///
+    /// If the same state (the same line, pattern space, hold space and result of the last
+    /// substitution) repeats within a cycle with no output in between, the script is stuck
+    /// in an infinite loop. Parsing stops at the repeated state.
     fn parse_state_frames(
         &self,
-        sed_output: &str,
+        sed_output: impl Iterator<Item = String>,
         label_jump_map: &HashMap<String, usize>,
-        lines_of_code: usize,
-    ) -> (Vec<DebuggingState>, Option<Vec<String>>) {
-        // First of all, skip the sed program source code.
-        let lines = sed_output
-            .lines()
-            .skip_while(|line| !line.starts_with("INPUT: "));
+        program_source: &[String],
+    ) -> ParsedFrames {
+        let lines_of_code = program_source.len();
+        // First of all, skip the sed program source code, if anything is left of it.
+        let lines = sed_output.skip_while(|line| !line.starts_with("INPUT: "));
 
         // Start parsing
+        // Line of input file (1-based) that is currently being processed
+        let mut input_line: usize = 0;
         let mut sed_line: usize = 0; // We need to try to keep track of this ourselves.
                                      // Sed doesn't exactly help with this one.
                                      // All the states will end up here
         let mut result: Vec<DebuggingState> = Vec::new();
         // The most recent pattern buffer
-        let mut current_pattern = "";
+        let mut current_pattern = String::new();
         // The most recent hold buffer
-        let mut current_hold = "";
+        let mut current_hold = String::new();
         // The last command that was executed, if any
         let mut previous_command: Option<String> = None;
         // All matched regexes by previous command
@@ -185,6 +303,9 @@ impl SedCommunicator {
         let mut currently_loading_multiline_regex_match: bool = false;
         // Was any substitution since last command successful?
         let mut substitution_successful: bool = false;
+        // States of the current cycle since the last output, with their numbers
+        let mut visited_states: HashMap<(usize, String, String, bool), usize> = HashMap::new();
+        let mut infinite_loop: Option<InfiniteLoop> = None;
 
         // TODO: Multiline regexes are not displayed correctly and will fall to output instead. FIXME!!
         for line in lines {
@@ -200,11 +321,11 @@ impl SedCommunicator {
                         regex_registers
                             .get_mut(last_regex_idx)
                             .unwrap()
-                            .push_str(line);
+                            .push_str(&line);
                         continue;
                     }
                 }
-                match line {
+                match line.as_str() {
                     x if x.starts_with("  ") => {
                         let rest_of_regex: String = String::from(
                             x.chars()
@@ -230,31 +351,69 @@ impl SedCommunicator {
             if currently_loading_regex_matches {
                 continue;
             }
-            match line {
+            match line.as_str() {
                 // Do not record INPUT lines, but reset line number, previous command and pattern space.
                 x if x.starts_with("INPUT:") => {
+                    input_line = x
+                        .rsplit(" line ")
+                        .next()
+                        .and_then(|n| n.trim().parse().ok())
+                        .unwrap_or(input_line + 1);
                     sed_line = 0;
-                    current_pattern = "";
+                    current_pattern = String::new();
                     previous_command = None;
+                    visited_states.clear();
                 }
                 // Save pattern space
                 x if x.starts_with("PATTERN:") => {
-                    current_pattern = x.trim_start_matches("PATTERN:").trim()
+                    current_pattern = String::from(x.trim_start_matches("PATTERN:").trim())
                 }
                 // Save hold space
-                x if x.starts_with("HOLD:") => current_hold = x.trim_start_matches("HOLD:").trim(),
+                x if x.starts_with("HOLD:") => {
+                    current_hold = String::from(x.trim_start_matches("HOLD:").trim())
+                }
                 // When we found a command, push previous debugging state
                 x if x.starts_with("COMMAND:") => {
                     let current_command = x.trim_start_matches("COMMAND:").trim();
-                    // Push state with the *previous* command and location
+                    // Our guess might be wrong, for example when sed skipped a block
+                    // whose address didn't match. Sed tells us the command at least.
+                    sed_line = self.align_line_position(sed_line, current_command, program_source);
+                    // Push state with the *previous* command and location.
+                    // Output or reading next line with `n` or `N` means the script got further.
+                    let read_input = previous_command
+                        .as_deref()
+                        .and_then(command_name)
+                        .map(|name| name == 'n' || name == 'N')
+                        .unwrap_or(false);
+                    if previous_output.is_some() || read_input {
+                        visited_states.clear();
+                    }
                     result.push(DebuggingState {
-                        pattern_buffer: String::from(current_pattern),
-                        hold_buffer: String::from(current_hold),
+                        pattern_buffer: current_pattern.clone(),
+                        hold_buffer: current_hold.clone(),
                         current_line: sed_line,
+                        input_line,
                         matched_regex_registers: regex_registers,
                         output: previous_output,
                         sed_command: previous_command,
                     });
+                    let state_number = result.len() - 1;
+                    let visited = (
+                        sed_line,
+                        current_pattern.clone(),
+                        current_hold.clone(),
+                        substitution_successful,
+                    );
+                    if let Some(first_state) = visited_states.insert(visited, state_number) {
+                        infinite_loop = Some(InfiniteLoop {
+                            first_state,
+                            repeated_state: state_number,
+                            closing_branch: self.closing_branch(&result[first_state..]),
+                        });
+                        // Output of the last command was already attached to the last state
+                        previous_output = None;
+                        break;
+                    }
 
                     // Push line number forward
                     sed_line = self.next_line_position(
@@ -283,9 +442,10 @@ impl SedCommunicator {
                 x if x.starts_with("END-OF-CYCLE:") => {
                     // Push last state, just as if we met next command, but the command was nil
                     result.push(DebuggingState {
-                        pattern_buffer: String::from(current_pattern),
-                        hold_buffer: String::from(current_hold),
+                        pattern_buffer: current_pattern.clone(),
+                        hold_buffer: current_hold.clone(),
                         current_line: sed_line,
+                        input_line,
                         matched_regex_registers: regex_registers,
                         output: previous_output,
                         sed_command: previous_command,
@@ -293,6 +453,7 @@ impl SedCommunicator {
 
                     // Start at the start again
                     sed_line = 0;
+                    visited_states.clear();
 
                     // Clear old info, such as output
                     previous_command = None;
@@ -312,7 +473,23 @@ impl SedCommunicator {
             }
         }
 
-        (result, previous_output)
+        ParsedFrames {
+            states: result,
+            last_output: previous_output,
+            infinite_loop,
+        }
+    }
+
+    /// Find line of the branch that jumps back to the start of a loop.
+    ///
+    /// This is the last command in the loop after which execution continued at the same line
+    /// or above it.
+    fn closing_branch(&self, loop_states: &[DebuggingState]) -> Option<usize> {
+        loop_states
+            .windows(2)
+            .rev()
+            .find(|pair| pair[1].current_line <= pair[0].current_line)
+            .map(|pair| pair[0].current_line)
     }
 
     /// Guess next command position.
@@ -374,6 +551,31 @@ impl SedCommunicator {
         }
     }
 
+    /// Correct guessed command position, so it points to the command sed reported.
+    ///
+    /// If the guessed line doesn't contain the command, use the nearest following line
+    /// that does. If there is no such line, use the first one that does. If no line
+    /// contains the command at all, keep the guess.
+    fn align_line_position(
+        &self,
+        guessed_position: usize,
+        current_command: &str,
+        program_source: &[String],
+    ) -> usize {
+        let is_command = |line: &String| line.trim() == current_command;
+        if program_source.get(guessed_position).map(is_command) == Some(true) {
+            return guessed_position;
+        }
+        program_source
+            .iter()
+            .enumerate()
+            .skip(guessed_position)
+            .chain(program_source.iter().enumerate())
+            .find(|(_, line)| is_command(line))
+            .map(|(i, _)| i)
+            .unwrap_or(guessed_position)
+    }
+
     /// Build label jump map
     fn build_jump_map(&self, source_code: &[String]) -> HashMap<String, usize> {
         let mut map: HashMap<String, usize> = HashMap::new();
@@ -387,8 +589,148 @@ impl SedCommunicator {
     }
 }
 
+/// Name of command as sed prints it, such as `N` in `$! N`. The address is skipped.
+fn command_name(command: &str) -> Option<char> {
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Regex address, such as `/x/` or `\,x,I`
+            '/' | '\\' => {
+                let delimiter = if c == '\\' { chars.next()? } else { c };
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == delimiter {
+                        break;
+                    }
+                }
+                while chars.next_if(|c| *c == 'I' || *c == 'M').is_some() {}
+            }
+            c if c.is_ascii_digit() || c.is_whitespace() || "$,~+!".contains(c) => {}
+            c => return Some(c),
+        }
+    }
+    None
+}
+
 pub struct DebugInfoFromSed {
     pub program_source: Vec<String>,
     pub states: Vec<DebuggingState>,
     pub last_output: Option<Vec<String>>,
+    /// Infinite loop sed got stuck in, if any
+    pub infinite_loop: Option<InfiniteLoop>,
+}
+
+/// Result of [SedCommunicator::parse_state_frames].
+struct ParsedFrames {
+    states: Vec<DebuggingState>,
+    /// Output of the last segment of the sed script
+    last_output: Option<Vec<String>>,
+    infinite_loop: Option<InfiniteLoop>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| String::from(*line)).collect()
+    }
+
+    /// Run parser over debug output of sed for given program.
+    fn parse(program: &[String], debug_output: &str) -> ParsedFrames {
+        let communicator = SedCommunicator::new(Options::default());
+        let label_jump_map = communicator.build_jump_map(program);
+        communicator.parse_state_frames(
+            debug_output.lines().map(String::from),
+            &label_jump_map,
+            program,
+        )
+    }
+
+    #[test]
+    fn command_name_skips_address() {
+        assert_eq!(command_name("N"), Some('N'));
+        assert_eq!(command_name("$! N"), Some('N'));
+        assert_eq!(command_name("1,+2 n"), Some('n'));
+        assert_eq!(command_name("/a n/ p"), Some('p'));
+        assert_eq!(command_name("\\,x\\,,I s/x/y/"), Some('s'));
+        assert_eq!(command_name("0,/^/ {"), Some('{'));
+        assert_eq!(command_name(""), None);
+    }
+
+    #[test]
+    fn identical_lines_read_by_n_are_not_infinite_loop() {
+        // sed -n ':x;n;bx' with three lines "a"
+        let program = program(&[":x", "n", "b x"]);
+        let frames = parse(
+            &program,
+            "INPUT:   'input.txt' line 1\n\
+             PATTERN: a\n\
+             COMMAND: :x\n\
+             COMMAND: n\n\
+             PATTERN: a\n\
+             COMMAND: b x\n\
+             COMMAND: :x\n\
+             COMMAND: n\n\
+             PATTERN: a\n\
+             COMMAND: b x\n\
+             COMMAND: :x\n\
+             COMMAND: n\n\
+             END-OF-CYCLE:\n",
+        );
+        assert!(frames.infinite_loop.is_none());
+        assert_eq!(frames.states.len(), 9);
+    }
+
+    #[test]
+    fn repeated_state_is_infinite_loop() {
+        // sed ':x;s/^a//;bx' with line "b", which never changes
+        let program = program(&[":x", "s/^a//", "b x"]);
+        let frames = parse(
+            &program,
+            "INPUT:   'input.txt' line 1\n\
+             PATTERN: b\n\
+             COMMAND: :x\n\
+             COMMAND: s/^a//\n\
+             COMMAND: b x\n\
+             COMMAND: :x\n\
+             COMMAND: s/^a//\n\
+             COMMAND: b x\n",
+        );
+        let infinite_loop = frames.infinite_loop.unwrap();
+        assert_eq!(infinite_loop.first_state, 0);
+        assert_eq!(infinite_loop.repeated_state, 3);
+    }
+
+    #[test]
+    fn align_keeps_right_guess() {
+        let communicator = SedCommunicator::new(Options::default());
+        let program = program(&["/x/ {", "p", "}", "p"]);
+        assert_eq!(communicator.align_line_position(1, "p", &program), 1);
+    }
+
+    #[test]
+    fn align_skips_block_whose_address_did_not_match() {
+        let communicator = SedCommunicator::new(Options::default());
+        let program = program(&["/x/ {", "p", "}", "p"]);
+        // Sed jumps from the opening brace straight to the closing one
+        assert_eq!(communicator.align_line_position(1, "}", &program), 2);
+        assert_eq!(communicator.align_line_position(1, "/x/ {", &program), 0);
+    }
+
+    #[test]
+    fn align_prefers_following_line() {
+        let communicator = SedCommunicator::new(Options::default());
+        let program = program(&["p", "x", "p"]);
+        assert_eq!(communicator.align_line_position(1, "p", &program), 2);
+        assert_eq!(communicator.align_line_position(3, "p", &program), 0);
+    }
+
+    #[test]
+    fn align_keeps_guess_of_unknown_command() {
+        let communicator = SedCommunicator::new(Options::default());
+        let program = program(&["p", "x"]);
+        assert_eq!(communicator.align_line_position(1, "d", &program), 1);
+    }
 }
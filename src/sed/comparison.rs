@@ -0,0 +1,115 @@
+use crate::diff::{diff, Change};
+use crate::sed::debugger::{Debugger, DebuggingState};
+
+/// Differences between two executions of a sed script, typically before and after
+/// the script was edited.
+pub struct TraceComparison {
+    /// Difference between final outputs of the two executions
+    pub output_diff: Vec<Change<String>>,
+    /// For each input line processed differently, where did the processing start to differ.
+    /// Ordered by input line.
+    pub divergences: Vec<Divergence>,
+}
+impl TraceComparison {
+    pub fn new(before: &Debugger, after: &Debugger) -> Self {
+        let output_before: Vec<&str> = before.output_lines().into_iter().map(|(_, l)| l).collect();
+        let output_after: Vec<&str> = after.output_lines().into_iter().map(|(_, l)| l).collect();
+        let output_diff = diff(&output_before, &output_after)
+            .into_iter()
+            .map(|change| match change {
+                Change::Same(line) => Change::Same(String::from(*line)),
+                Change::Removed(line) => Change::Removed(String::from(*line)),
+                Change::Added(line) => Change::Added(String::from(*line)),
+            })
+            .collect();
+
+        let cycles_before = before.cycles();
+        let cycles_after = after.cycles();
+        let mut divergences = Vec::new();
+        for (input_line, states_after) in &cycles_after {
+            let states_before = cycles_before
+                .iter()
+                .find(|(line, _)| line == input_line)
+                .map(|(_, states)| states.as_slice())
+                .unwrap_or(&[]);
+            let step = (0..)
+                .find(|step| {
+                    let state_before = states_before.get(*step).map(|i| (before, *i));
+                    let state_after = states_after.get(*step).map(|i| (after, *i));
+                    match (state_before, state_after) {
+                        (Some(b), Some(a)) => !same_state(b, a),
+                        // One of the cycles ended (or both did)
+                        _ => true,
+                    }
+                })
+                // UNWRAP: Both cycles are finite, so the search ends at their end at the latest
+                .unwrap();
+            if step < states_before.len().max(states_after.len()) {
+                divergences.push(Divergence {
+                    input_line: *input_line,
+                    step,
+                    state_before: states_before.get(step).copied(),
+                    state_after: states_after.get(step).copied(),
+                });
+            }
+        }
+        // Input lines that are not processed at all anymore
+        for (input_line, states_before) in &cycles_before {
+            if !cycles_after.iter().any(|(line, _)| line == input_line) {
+                divergences.push(Divergence {
+                    input_line: *input_line,
+                    step: 0,
+                    state_before: states_before.first().copied(),
+                    state_after: None,
+                });
+            }
+        }
+        divergences.sort_by_key(|d| d.input_line);
+
+        TraceComparison {
+            output_diff,
+            divergences,
+        }
+    }
+
+    /// First state of the new execution which differs from the old one, if any.
+    pub fn first_divergence(&self) -> Option<usize> {
+        self.divergences.iter().filter_map(|d| d.state_after).min()
+    }
+
+    /// Did the final output change?
+    pub fn output_changed(&self) -> bool {
+        self.output_diff
+            .iter()
+            .any(|change| !matches!(change, Change::Same(_)))
+    }
+}
+
+/// Place where processing of one input line started to differ.
+pub struct Divergence {
+    pub input_line: usize,
+    /// How many states since the start of the cycle
+    pub step: usize,
+    /// First differing state (0-based) of the old execution, if the cycle was long enough
+    pub state_before: Option<usize>,
+    /// First differing state (0-based) of the new execution, if the cycle was long enough
+    pub state_after: Option<usize>,
+}
+
+/// Do the two states look the same to the user? State numbers are not compared,
+/// as they shift whenever anything changes.
+fn same_state(before: (&Debugger, usize), after: (&Debugger, usize)) -> bool {
+    let (command_before, state_before) = command_and_state(before);
+    let (command_after, state_after) = command_and_state(after);
+    command_before == command_after
+        && state_before.pattern_buffer == state_after.pattern_buffer
+        && state_before.hold_buffer == state_after.hold_buffer
+        && state_before.output == state_after.output
+}
+
+/// Get state with given number, together with the command it executes.
+fn command_and_state((debugger, i): (&Debugger, usize)) -> (Option<&String>, &DebuggingState) {
+    // UNWRAP: We compare only existing states
+    let state = debugger.peek_at_state(i).unwrap();
+    (debugger.source_code.get(state.current_line), state)
+}
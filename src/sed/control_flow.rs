@@ -0,0 +1,347 @@
+use crate::sed::debugger::Debugger;
+use crate::sed::script::Script;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Control-flow graph of sed script: basic blocks of commands connected by edges
+/// of branches, blocks and fall through.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    /// Basic blocks, ordered as in the script
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+    /// `{...}` blocks of the script, as indices of the opening and the closing brace.
+    /// Basic blocks starting after the opening brace, up to the closing one, are inside.
+    pub clusters: Vec<(usize, usize)>,
+    /// Text of each command, as desed shows it
+    commands: Vec<String>,
+    /// Line of the program (0-based) of each command, if known
+    program_lines: Vec<Option<usize>>,
+}
+
+/// Commands that always run one after another.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Indices of commands in the block
+    pub commands: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    /// Start of cycle, after sed reads input line
+    Start,
+    Block(usize),
+    /// End of cycle, reached by running out of commands, by `b` without label or by `d` and `D`
+    EndOfCycle,
+    /// Sed exits, by `q` or `Q`
+    Quit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: Node,
+    pub to: Node,
+    /// When is the edge taken, empty if always
+    pub label: String,
+    /// How many times was the edge taken during execution, if counted
+    pub count: Option<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Build graph of the script. Commands are shown as sed prints them in the program,
+    /// if they can be found there.
+    pub fn new(script: &Script, program_source: &[String]) -> Self {
+        let commands = &script.commands;
+        let program_lines = script.program_lines(program_source);
+        let labels: HashMap<&str, usize> = script.labels().into_iter().collect();
+        let clusters = block_pairs(script);
+        let closing_brace: HashMap<usize, usize> = clusters.iter().copied().collect();
+
+        // Leaders start basic blocks: jump targets and commands after those that can jump
+        let mut leaders = vec![false; commands.len() + 1];
+        leaders[0] = true;
+        for (i, command) in commands.iter().enumerate() {
+            match command.name {
+                ':' => leaders[i] = true,
+                '}' => leaders[i + 1] = true,
+                '{' => {
+                    leaders[i + 1] = true;
+                    leaders[closing_brace[&i]] = true;
+                }
+                'b' | 't' | 'T' | 'd' | 'D' | 'q' | 'Q' => leaders[i + 1] = true,
+                _ => {}
+            }
+        }
+        let starts: Vec<usize> = (0..commands.len()).filter(|i| leaders[*i]).collect();
+        let blocks: Vec<BasicBlock> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| BasicBlock {
+                commands: *start..starts.get(i + 1).copied().unwrap_or(commands.len()),
+            })
+            .collect();
+        let block_of = |command: usize| -> Node {
+            match blocks
+                .iter()
+                .position(|block| block.commands.start == command)
+            {
+                Some(block) => Node::Block(block),
+                None => Node::EndOfCycle,
+            }
+        };
+
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut add_edge = |from: Node, to: Node, label: &str| {
+            match edges
+                .iter_mut()
+                .find(|edge| edge.from == from && edge.to == to)
+            {
+                // Both outcomes lead to the same place, so the edge is always taken
+                Some(edge) => edge.label.clear(),
+                None => edges.push(Edge {
+                    from,
+                    to,
+                    label: String::from(label),
+                    count: None,
+                }),
+            }
+        };
+        add_edge(Node::Start, block_of(0), "");
+        for (i, block) in blocks.iter().enumerate() {
+            let from = Node::Block(i);
+            let last = block.commands.end - 1;
+            let command = &commands[last];
+            let next = block_of(last + 1);
+            let addressed = command.address.is_some();
+            let target = || match command.label() {
+                Some(label) => labels
+                    .get(label)
+                    .map(|target| block_of(*target))
+                    .unwrap_or(Node::EndOfCycle),
+                None => Node::EndOfCycle,
+            };
+            match command.name {
+                '{' if addressed => {
+                    add_edge(from, next, "match");
+                    add_edge(from, block_of(closing_brace[&last]), "no match");
+                }
+                'b' if addressed => {
+                    add_edge(from, target(), "match");
+                    add_edge(from, next, "no match");
+                }
+                'b' => add_edge(from, target(), ""),
+                't' => {
+                    add_edge(from, target(), "replaced");
+                    add_edge(from, next, "not replaced");
+                }
+                'T' => {
+                    add_edge(from, target(), "not replaced");
+                    add_edge(from, next, "replaced");
+                }
+                'd' | 'D' | 'q' | 'Q' => {
+                    let to = if matches!(command.name, 'q' | 'Q') {
+                        Node::Quit
+                    } else {
+                        Node::EndOfCycle
+                    };
+                    if addressed {
+                        add_edge(from, to, "match");
+                        add_edge(from, next, "no match");
+                    } else {
+                        add_edge(from, to, "");
+                    }
+                }
+                _ => add_edge(from, next, ""),
+            }
+        }
+
+        let commands = commands
+            .iter()
+            .zip(&program_lines)
+            .map(|(command, line)| match line {
+                Some(line) => String::from(program_source[*line].trim()),
+                None => command.name.to_string(),
+            })
+            .collect();
+        ControlFlowGraph {
+            blocks,
+            edges,
+            clusters,
+            commands,
+            program_lines,
+        }
+    }
+
+    /// Count how many times was each edge taken during execution.
+    pub fn count_edges(&mut self, debugger: &Debugger) {
+        let mut block_starts: HashMap<usize, usize> = HashMap::new();
+        let mut block_ends: HashMap<usize, usize> = HashMap::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            if let Some(line) = self.program_lines[block.commands.start] {
+                block_starts.insert(line, i);
+            }
+            if let Some(line) = self.program_lines[block.commands.end - 1] {
+                block_ends.insert(line, i);
+            }
+        }
+        for edge in &mut self.edges {
+            edge.count = Some(0);
+        }
+        let mut take = |from: Node, to: Node| {
+            let edge = self.edges.iter_mut().find(|edge| {
+                edge.from == from
+                    && match to {
+                        // End of cycle can't tell whether sed quit or not
+                        Node::EndOfCycle => matches!(edge.to, Node::EndOfCycle | Node::Quit),
+                        to => edge.to == to,
+                    }
+            });
+            if let Some(edge) = edge {
+                // UNWRAP: All counts were set above
+                *edge.count.as_mut().unwrap() += 1;
+            }
+        };
+        for (_, states) in debugger.cycles() {
+            // The last state of each cycle is the end of cycle
+            let lines: Vec<usize> = states
+                .iter()
+                .filter_map(|i| debugger.peek_at_state(*i))
+                .map(|state| state.current_line)
+                .collect();
+            if let Some(first) = lines.first().and_then(|line| block_starts.get(line)) {
+                take(Node::Start, Node::Block(*first));
+            }
+            for (i, pair) in lines.windows(2).enumerate() {
+                let from = match block_ends.get(&pair[0]) {
+                    Some(from) => Node::Block(*from),
+                    None => continue,
+                };
+                if i + 2 == lines.len() {
+                    take(from, Node::EndOfCycle);
+                } else if let Some(to) = block_starts.get(&pair[1]) {
+                    take(from, Node::Block(*to));
+                }
+            }
+        }
+    }
+
+    /// Write the graph in Graphviz DOT format.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut lines = vec![
+            format!("digraph {} {{", dot_string(name)),
+            String::from("  node [shape=box, fontname=\"monospace\"];"),
+            String::from("  start [label=\"start of cycle\", shape=oval];"),
+            String::from("  end [label=\"end of cycle\", shape=oval];"),
+        ];
+        if self.edges.iter().any(|edge| edge.to == Node::Quit) {
+            lines.push(String::from("  quit [label=\"quit\", shape=oval];"));
+        }
+        self.write_blocks(&mut lines, 0..self.commands.len(), 1);
+        for edge in &self.edges {
+            let mut label = edge.label.clone();
+            if let Some(count) = edge.count {
+                if !label.is_empty() {
+                    label.push(' ');
+                }
+                label.push_str(&format!("({}×)", count));
+            }
+            let attributes = match (label.is_empty(), edge.count) {
+                (true, _) => String::new(),
+                (false, Some(0)) => format!(" [label={}, style=dashed]", dot_string(&label)),
+                (false, _) => format!(" [label={}]", dot_string(&label)),
+            };
+            lines.push(format!(
+                "  {} -> {}{};",
+                node_name(edge.from),
+                node_name(edge.to),
+                attributes
+            ));
+        }
+        lines.push(String::from("}"));
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Write basic blocks within range of commands, nesting `{...}` blocks as clusters.
+    fn write_blocks(&self, lines: &mut Vec<String>, range: Range<usize>, depth: usize) {
+        let indent = "  ".repeat(depth);
+        let mut command = range.start;
+        while command < range.end {
+            let i = match self
+                .blocks
+                .iter()
+                .position(|block| block.commands.start == command)
+            {
+                Some(i) => i,
+                None => break,
+            };
+            let block = &self.blocks[i];
+            let label: String = block
+                .commands
+                .clone()
+                .map(|command| format!("{}\\l", dot_escape(&self.describe_command(command))))
+                .collect();
+            lines.push(format!("{}block{} [label=\"{}\"];", indent, i, label));
+            command = block.commands.end;
+            // Block ending with opening brace is followed by the inside of the brace
+            let last = block.commands.end - 1;
+            if let Some((open, close)) = self.clusters.iter().find(|(open, _)| *open == last) {
+                lines.push(format!("{}subgraph cluster_{} {{", indent, open));
+                lines.push(format!(
+                    "{}  label={};",
+                    indent,
+                    dot_string(&self.describe_command(*open))
+                ));
+                self.write_blocks(lines, open + 1..close + 1, depth + 1);
+                lines.push(format!("{}}}", indent));
+                command = close + 1;
+            }
+        }
+    }
+
+    /// Command with number of its line in the program, if known.
+    fn describe_command(&self, command: usize) -> String {
+        match self.program_lines[command] {
+            Some(line) => format!("{}: {}", line + 1, self.commands[command]),
+            None => self.commands[command].clone(),
+        }
+    }
+}
+
+/// Find matching braces of `{...}` blocks, as pairs of command indices.
+fn block_pairs(script: &Script) -> Vec<(usize, usize)> {
+    let mut open: Vec<usize> = Vec::new();
+    let mut pairs = Vec::new();
+    for (i, command) in script.commands.iter().enumerate() {
+        match command.name {
+            '{' => open.push(i),
+            '}' => {
+                // Parser checks that braces are balanced
+                if let Some(start) = open.pop() {
+                    pairs.push((start, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+fn node_name(node: Node) -> String {
+    match node {
+        Node::Start => String::from("start"),
+        Node::Block(i) => format!("block{}", i),
+        Node::EndOfCycle => String::from("end"),
+        Node::Quit => String::from("quit"),
+    }
+}
+
+/// Escape text, so it can be put between quotes in DOT.
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", dot_escape(text))
+}
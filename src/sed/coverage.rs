@@ -0,0 +1,117 @@
+use crate::sed::debugger::Debugger;
+use crate::sed::source_map::SourceMap;
+use std::path::Path;
+
+/// How many times was each line of the sed program reached during execution.
+///
+/// A line counts as reached whenever sed got to its command, even if the command's
+/// address didn't match.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// Number of states that executed each line (0-based) of the program
+    counts: Vec<usize>,
+    /// The program as sed printed it, lines of the counts
+    pub program: Vec<String>,
+}
+impl Coverage {
+    pub fn new(debugger: &Debugger) -> Self {
+        let mut counts = vec![0; debugger.source_code.len()];
+        for state in (0..debugger.count_of_states()).filter_map(|i| debugger.peek_at_state(i)) {
+            // Line after the program marks end of cycle, it's not a command
+            if let Some(count) = counts.get_mut(state.current_line) {
+                *count += 1;
+            }
+        }
+        Coverage {
+            counts,
+            program: debugger.source_code.clone(),
+        }
+    }
+
+    /// How many times was given line (0-based) reached. Lines outside of program are never reached.
+    pub fn count(&self, line: usize) -> Option<usize> {
+        self.counts.get(line).copied()
+    }
+
+    /// Number of lines that were reached at least once.
+    pub fn covered_lines(&self) -> usize {
+        self.counts.iter().filter(|count| **count > 0).count()
+    }
+
+    pub fn total_lines(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Add counts from another execution of the same program.
+    pub fn merge(&mut self, other: &Coverage) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    /// Write coverage in lcov tracefile format. Line numbers are 1-based lines of the original
+    /// script. Lines without a command, such as comments, are left out, and a line with several
+    /// commands counts as reached as many times as the command on it that was reached most.
+    pub fn to_lcov(&self, source_file: &Path, source_map: &SourceMap) -> String {
+        let mut lines = vec![String::from("TN:"), format!("SF:{}", source_file.display())];
+        let (mut found, mut hit) = (0, 0);
+        for line in 0..source_map.lines.len() {
+            let count = source_map
+                .program_lines_on(line)
+                .into_iter()
+                .filter_map(|program_line| self.count(program_line))
+                .max();
+            if let Some(count) = count {
+                lines.push(format!("DA:{},{}", line + 1, count));
+                found += 1;
+                if count > 0 {
+                    hit += 1;
+                }
+            }
+        }
+        lines.push(format!("LF:{}", found));
+        lines.push(format!("LH:{}", hit));
+        lines.push(String::from("end_of_record"));
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sed::script::Script;
+
+    #[test]
+    fn lcov_uses_lines_of_original_script() {
+        let source = "# comment\n\ns/a/b/;s/c/d/\n$!{\n  p\n}\n";
+        let program: Vec<String> = ["s/a/b/", "s/c/d/", "$! {", "p", "}"]
+            .iter()
+            .map(|line| String::from(*line))
+            .collect();
+        let script = Script::parse(source).unwrap();
+        let source_map = SourceMap::new(source, &script, &program);
+        let coverage = Coverage {
+            counts: vec![2, 2, 2, 0, 2],
+            program,
+        };
+        let lcov = coverage.to_lcov(Path::new("/tmp/script.sed"), &source_map);
+        assert_eq!(
+            lcov.lines().collect::<Vec<&str>>(),
+            [
+                "TN:",
+                "SF:/tmp/script.sed",
+                "DA:3,2",
+                "DA:4,2",
+                "DA:5,0",
+                "DA:6,2",
+                "LF:4",
+                "LH:3",
+                "end_of_record",
+            ]
+        );
+    }
+}
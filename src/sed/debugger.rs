@@ -1,6 +1,7 @@
 use crate::cli::Options;
 use crate::sed::communication::DebugInfoFromSed;
 use crate::sed::communication::SedCommunicator;
+use crate::sed::script::Script;
 use anyhow::Result;
 
 /// Sed program debugger.
@@ -19,10 +20,19 @@ pub struct Debugger {
     pub source_code: Vec<String>,
     /// Previously visited debugging states, including the current one.
     state_frames: Vec<DebuggingState>,
+    /// If the script got stuck in an infinite loop, sed was stopped at the first
+    /// repeated state, which is the last state.
+    pub infinite_loop: Option<InfiniteLoop>,
+    /// Sed binary that ran the script, which might be "gsed" if plain "sed" failed.
+    pub sed_path: String,
 }
 impl Debugger {
     /// Create new instance of debugger and launch sed.
+    ///
+    /// States processing the first input lines user asked to skip are dropped. They still ran,
+    /// so the hold space they filled is kept.
     pub fn new(settings: Options) -> Result<Self> {
+        let skip_lines = settings.skip_lines;
         let mut communicator = SedCommunicator::new(settings);
         let data: DebugInfoFromSed = communicator.get_execution_info_from_sed()?;
         // Shift all pattern matches one frame earlier.
@@ -37,6 +47,7 @@ impl Debugger {
             states_shifted.push(DebuggingState {
                 pattern_buffer: state.pattern_buffer,
                 current_line: state.current_line,
+                input_line: state.input_line,
                 hold_buffer: state.hold_buffer,
                 matched_regex_registers: previous_matches,
                 output: previous_output,
@@ -46,9 +57,28 @@ impl Debugger {
             previous_matches = state.matched_regex_registers;
         }
         states_shifted.reverse();
+        let skipped = states_shifted
+            .iter()
+            .take_while(|state| state.input_line <= skip_lines)
+            .count();
+        if skipped > 0 && skipped == states_shifted.len() {
+            anyhow::bail!(
+                "Nothing is left to debug after skipping {} input lines, sed stopped at line {}.",
+                skip_lines,
+                states_shifted[skipped - 1].input_line
+            );
+        }
+        states_shifted.drain(..skipped);
+        let infinite_loop = data.infinite_loop.map(|infinite_loop| InfiniteLoop {
+            first_state: infinite_loop.first_state.saturating_sub(skipped),
+            repeated_state: infinite_loop.repeated_state - skipped,
+            closing_branch: infinite_loop.closing_branch,
+        });
         Ok(Debugger {
             source_code: data.program_source,
             state_frames: states_shifted,
+            infinite_loop,
+            sed_path: String::from(communicator.sed_path()),
         })
     }
     /// Peek at state with target number (0-based).
@@ -62,6 +92,182 @@ impl Debugger {
     pub fn count_of_states(&self) -> usize {
         self.state_frames.len()
     }
+
+    /// Collect everything sed printed during the whole execution, in order.
+    ///
+    /// Each line is paired with number of the state (0-based) whose command printed it.
+    pub fn output_lines(&self) -> Vec<(usize, &str)> {
+        self.state_frames
+            .iter()
+            .enumerate()
+            .flat_map(|(i, state)| {
+                state
+                    .output
+                    .iter()
+                    .flatten()
+                    .map(move |line| (i, line.as_str()))
+            })
+            .collect()
+    }
+
+    /// Describe state with target number (0-based) by its meaning, so it can be found again
+    /// after the script changes. See [Debugger::locate].
+    pub fn anchor(&self, frame: usize) -> StateAnchor {
+        let state = match self.peek_at_state(frame) {
+            Some(state) => state,
+            None => {
+                return StateAnchor {
+                    state: frame,
+                    ..StateAnchor::default()
+                }
+            }
+        };
+        let cycle_start = self.cycle_start(frame);
+        let command = self.source_code.get(state.current_line).cloned();
+        StateAnchor {
+            state: frame,
+            input_line: state.input_line,
+            cycle_step: frame - cycle_start,
+            command_occurrence: (cycle_start..frame)
+                .filter(|i| {
+                    self.source_code.get(self.state_frames[*i].current_line) == command.as_ref()
+                })
+                .count(),
+            command,
+        }
+    }
+
+    /// Find state that matches given anchor the best.
+    ///
+    /// This prefers state processing the same input line, executing the same command for the same
+    /// time in the cycle. If the command now executes fewer times, state at the same step of the cycle
+    /// or the nearest state with the same command is used. If the command doesn't exist at all, we stay
+    /// at the same step of the cycle, and if even the input line isn't processed anymore, just at
+    /// the same state number.
+    pub fn locate(&self, anchor: &StateAnchor) -> usize {
+        let last_state = self.count_of_states().saturating_sub(1);
+        let cycle: Vec<usize> = (0..self.count_of_states())
+            .filter(|i| self.state_frames[*i].input_line == anchor.input_line)
+            .collect();
+        if cycle.is_empty() {
+            return anchor.state.min(last_state);
+        }
+
+        let executes_anchored_command = |i: &usize| {
+            anchor.command.is_some()
+                && self.source_code.get(self.state_frames[*i].current_line)
+                    == anchor.command.as_ref()
+        };
+        let executions: Vec<usize> = cycle
+            .iter()
+            .copied()
+            .filter(executes_anchored_command)
+            .collect();
+        if let Some(same_occurrence) = executions.get(anchor.command_occurrence) {
+            return *same_occurrence;
+        }
+        let same_step = cycle[anchor.cycle_step.min(cycle.len() - 1)];
+        if executes_anchored_command(&same_step) {
+            return same_step;
+        }
+        executions
+            .into_iter()
+            .min_by_key(|i| (*i as isize - same_step as isize).abs())
+            .unwrap_or(same_step)
+    }
+
+    /// Split states into cycles, one for each input line sed read at start of a cycle.
+    ///
+    /// Returns input line (1-based) of each cycle together with numbers of its states.
+    pub fn cycles(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut result: Vec<(usize, Vec<usize>)> = Vec::new();
+        for (i, state) in self.state_frames.iter().enumerate() {
+            match result.last_mut() {
+                Some((line, states)) if *line == state.input_line => states.push(i),
+                _ => result.push((state.input_line, vec![i])),
+            }
+        }
+        result
+    }
+
+    /// How many lines of input sed has read in state with target number (0-based): lines before
+    /// the cycle, the line read at its start and lines read by `n` or `N` since then.
+    pub fn lines_read(&self, frame: usize) -> usize {
+        let reads_input = |i: &usize| {
+            self.source_code
+                .get(self.state_frames[*i].current_line)
+                .and_then(|command| Script::parse(command).ok())
+                .and_then(|script| script.commands.first().map(|command| command.name))
+                .map(|name| name == 'n' || name == 'N')
+                .unwrap_or(false)
+        };
+        match self.peek_at_state(frame) {
+            Some(state) => {
+                state.input_line + (self.cycle_start(frame)..frame).filter(reads_input).count()
+            }
+            None => 0,
+        }
+    }
+
+    /// Find first state of the cycle the target state belongs to.
+    fn cycle_start(&self, frame: usize) -> usize {
+        let input_line = self.state_frames[frame].input_line;
+        (0..frame)
+            .rev()
+            .take_while(|i| self.state_frames[*i].input_line == input_line)
+            .last()
+            .unwrap_or(frame)
+    }
+}
+
+/// Infinite loop detected in execution: a state repeated within a cycle, with no output
+/// in between.
+#[derive(Debug, Clone)]
+pub struct InfiniteLoop {
+    /// The state (0-based) that repeated later
+    pub first_state: usize,
+    /// The state (0-based) that is the same as the first one
+    pub repeated_state: usize,
+    /// Line (0-based) of the branch that jumps back to the start of the loop, if we found it
+    pub closing_branch: Option<usize>,
+}
+impl InfiniteLoop {
+    /// Is the state (0-based) part of the loop?
+    pub fn contains(&self, state: usize) -> bool {
+        self.first_state <= state && state <= self.repeated_state
+    }
+
+    /// Describe the loop in one sentence.
+    pub fn describe(&self, source_code: &[String]) -> String {
+        let branch = match self.closing_branch {
+            Some(line) => format!(
+                ", closed by `{}` on line {}",
+                source_code.get(line).map(|s| s.as_str()).unwrap_or(""),
+                line + 1
+            ),
+            None => String::new(),
+        };
+        format!(
+            "Infinite loop: state {} repeats state {} with no output in between{}",
+            self.repeated_state, self.first_state, branch
+        )
+    }
+}
+
+/// Describes a state by its meaning instead of by its number, which changes whenever
+/// the script is edited.
+#[derive(Debug, Clone, Default)]
+pub struct StateAnchor {
+    /// Number of the state (0-based). Used only when nothing better is found.
+    pub state: usize,
+    /// Line of input file being processed
+    pub input_line: usize,
+    /// How many states since the start of the cycle
+    pub cycle_step: usize,
+    /// Source code of the command about to be executed, if any
+    pub command: Option<String>,
+    /// How many times was the command executed since the start of the cycle
+    pub command_occurrence: usize,
 }
 
 /// One state of sed program execution.
@@ -83,6 +289,9 @@ pub struct DebuggingState {
     /// and is not retrieved from inner sed state. So this might in some cases be wrong.
     /// If that's the case, file a bug.
     pub current_line: usize,
+    /// Line of input file (1-based) sed was processing in this state. This is the line sed read
+    /// at start of the cycle, commands such as `n` or `N` might have read more since then.
+    pub input_line: usize,
     /// Command executed by sed. With a bit of luck, this should match command referenced
     /// by current_line. If these two don't match, this one (`sed_command`) is right and
     /// a bug in parsing code occurred.
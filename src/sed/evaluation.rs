@@ -0,0 +1,641 @@
+use crate::cli::Options;
+use crate::sed::debugger::Debugger;
+use crate::sed::regex::RegexTree;
+use crate::sed::script::Script;
+use crate::sed::source_map::SourceMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Marks start of each match when finding all matches, see [Evaluator::test_regex].
+const MATCH_START: u8 = 0x1e;
+/// Marks end of each match.
+const MATCH_END: u8 = 0x1f;
+/// Sed is stopped after executing this many commands, as it might be stuck in a loop.
+const MAX_STEPS: usize = 1000;
+/// Label after the prelude setting up pattern and hold space, see [Evaluator::run_commands].
+const START_LABEL: &str = "desed_start";
+/// Label inserted into the script where execution continues, see [Evaluator::what_if].
+const RESUME_LABEL: &str = "desed_resume";
+/// How GNU sed rejects `e`, `r` and `w` commands with `--sandbox`.
+const SANDBOX_ERROR: &str = "e/r/w commands disabled in sandbox mode";
+
+/// Runs snippets of sed on pattern and hold space of a state, with the same sed binary and
+/// parameters the debugged script runs with. The script itself is never touched.
+pub struct Evaluator {
+    sed_path: String,
+    sed_parameters: Vec<String>,
+    input_file: PathBuf,
+    extended_regex: bool,
+}
+
+/// What a regex typed by user matched in pattern space.
+pub struct RegexTest {
+    /// Pattern space, escaped the way sed prints it, but with UTF-8 kept as is
+    pub pattern_space: String,
+    /// Character ranges of all matches in [RegexTest::pattern_space]
+    pub matches: Vec<Range<usize>>,
+    /// Character ranges of groups of the first match, `\0` being the whole match.
+    /// Groups which didn't take part in the match are `None`.
+    pub groups: Vec<Option<Range<usize>>>,
+    /// Pattern space after replacement, if user typed any
+    pub result: Option<String>,
+}
+
+/// Commands run from a state, as a branch of execution that isn't part of the trace.
+pub struct Branch {
+    pub steps: Vec<BranchStep>,
+    /// True if sed was stopped after [MAX_STEPS], most likely in an infinite loop
+    pub stopped: bool,
+}
+
+/// One executed command of [Branch].
+pub struct BranchStep {
+    /// Command as sed prints it
+    pub command: String,
+    /// Line (0-based) of the program with the command, if the command is part of the script
+    pub line: Option<usize>,
+    /// Pattern space after the command, escaped the way sed prints it
+    pub pattern_buffer: String,
+    /// Hold space after the command
+    pub hold_buffer: String,
+    /// Lines printed by the command, including automatic printing at the end of cycle
+    pub output: Vec<String>,
+}
+
+impl Evaluator {
+    pub fn new(settings: &Options, debugger: &Debugger) -> Self {
+        Evaluator {
+            sed_path: debugger.sed_path.clone(),
+            sed_parameters: settings.sed_parameters.clone(),
+            input_file: settings.input_file.clone(),
+            extended_regex: settings.extended_regex(),
+        }
+    }
+
+    /// Match regex against pattern space, as printed in a debugging state. `query` is
+    /// `regex`, or `regex/replacement/flags` with optional flags, just like arguments of `s`.
+    ///
+    /// Sed finds all matches with `s///g` wrapping each one in markers, and prints groups
+    /// of the first match with `--debug`. The error is the one reported by sed, for example
+    /// when the regex isn't valid.
+    pub fn test_regex(&self, pattern_space: &str, query: &str) -> Result<RegexTest, String> {
+        let parts = split_query(query);
+        let regex = &parts[0];
+        let flags = parts.get(2).map(String::as_str).unwrap_or("");
+        // Only flags changing what matches are relevant to finding all matches
+        let match_flags: String = flags.chars().filter(|c| "IiMm".contains(*c)).collect();
+        let mut expressions = vec![
+            String::from("h"),
+            format!(
+                "s/{}/{}&{}/g{}",
+                regex, MATCH_START as char, MATCH_END as char, match_flags
+            ),
+        ];
+        if let Some(replacement) = parts.get(1) {
+            expressions.push(String::from("g"));
+            expressions.push(format!("s/{}/{}/{}", regex, replacement, flags));
+        }
+        let input = unescape_buffer(pattern_space);
+        let mut arguments = vec![String::from("-n"), String::from("-z")];
+        for expression in expressions {
+            arguments.push(String::from("-e"));
+            arguments.push(expression);
+        }
+        let mut record = input.clone();
+        record.push(b'\0');
+        let (debug_output, _) = self.run(&arguments, record, MAX_STEPS)?;
+
+        let mut registers: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut marked: Option<Vec<u8>> = None;
+        let mut result: Option<String> = None;
+        let mut command = 0;
+        for line in &debug_output {
+            if line.starts_with("COMMAND:") {
+                command += 1;
+            } else if let Some(register) = line.trim_start().strip_prefix("regex[") {
+                if command == 2 {
+                    registers.extend(parse_register(register));
+                }
+            } else if let Some(pattern) = line.strip_prefix("PATTERN: ") {
+                match command {
+                    2 => marked = Some(unescape_buffer(pattern)),
+                    4 => result = Some(pattern.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+
+        let (pattern_space, offsets) = escape_buffer(&input);
+        let characters = |range: Range<usize>| offsets[range.start]..offsets[range.end];
+        let mut matches = Vec::new();
+        let mut start = 0;
+        let mut position = 0;
+        for byte in marked.unwrap_or_default() {
+            match byte {
+                MATCH_START => start = position,
+                MATCH_END => matches.push(characters(start..position)),
+                _ => position += 1,
+            }
+        }
+        // Sed doesn't print groups which didn't take part in the match
+        let mut groups = Vec::new();
+        if !registers.is_empty() {
+            groups.resize(
+                RegexTree::parse(regex, self.extended_regex).groups + 1,
+                None,
+            );
+        }
+        for (group, range) in registers {
+            if range.end <= input.len() {
+                if group >= groups.len() {
+                    groups.resize(group + 1, None);
+                }
+                groups[group] = Some(characters(range));
+            }
+        }
+        Ok(RegexTest {
+            pattern_space,
+            matches,
+            groups,
+            result,
+        })
+    }
+
+    /// Run sed commands, such as `G;s/\n/,/`, starting with given pattern and hold space,
+    /// as printed in a debugging state.
+    ///
+    /// Sed can't be told what the buffers should be, so a prelude sets them up: the first line
+    /// of input is empty and gets replaced with hold space, the rest of input is joined into
+    /// pattern space. Commands run after the prelude, which is left out of the branch.
+    pub fn run_commands(
+        &self,
+        pattern_buffer: &str,
+        hold_buffer: &str,
+        commands: &str,
+    ) -> Result<Branch, String> {
+        let delimiter = self.delimiter();
+        let mut input = vec![delimiter];
+        input.extend(unescape_buffer(pattern_buffer));
+        input.push(delimiter);
+        let mut arguments = Vec::new();
+        for expression in [
+            format!("1{{s/^/{}/;h;d;}}", replacement(hold_buffer)),
+            String::from(":desed_join"),
+            String::from("$!{N;bdesed_join"),
+            String::from("}"),
+            format!(":{}", START_LABEL),
+            String::from(commands),
+        ] {
+            arguments.push(String::from("-e"));
+            arguments.push(expression);
+        }
+        let (debug_output, stopped) = self.run(&arguments, input, MAX_STEPS)?;
+        Ok(Branch {
+            steps: parse_branch(&debug_output, pattern_buffer, hold_buffer, &[], true),
+            stopped,
+        })
+    }
+
+    /// Run the script again from given state, as if it had different pattern and hold space,
+    /// until the end of cycle or, if `whole_input` is true, until the end of input.
+    ///
+    /// Like in [Evaluator::run_commands], a prelude sets up the buffers. Input starts with
+    /// empty lines, so line numbers stay the same: the first one is replaced with hold space,
+    /// the last one is joined with lines of pattern space. Rest of input follows. Then
+    /// execution jumps to a label inserted right before the command of the state.
+    pub fn what_if(
+        &self,
+        debugger: &Debugger,
+        source_map: &SourceMap,
+        state: usize,
+        pattern_buffer: &str,
+        hold_buffer: &str,
+        whole_input: bool,
+    ) -> Result<Branch, String> {
+        let line = debugger
+            .peek_at_state(state)
+            .map(|state| state.current_line)
+            .unwrap_or(0);
+        let script = source_map
+            .with_label(line, RESUME_LABEL)
+            .ok_or("The command isn't in the original script")?;
+        let delimiter = self.delimiter();
+        let input = fs::read(&self.input_file)
+            .map_err(|error| format!("Failed to read {}: {}", self.input_file.display(), error))?;
+        let lines_read = debugger.lines_read(state);
+        let pattern = unescape_buffer(pattern_buffer);
+        let pattern_lines = pattern.split(|b| *b == delimiter).count();
+        // Line numbers are off by one if pattern space has more lines than sed has read
+        let empty_lines = lines_read.saturating_sub(pattern_lines).max(1);
+
+        let mut records = vec![delimiter; empty_lines];
+        records.extend(pattern);
+        records.push(delimiter);
+        let rest = input.strip_suffix(&[delimiter]).unwrap_or(&input);
+        for record in rest.split(|b| *b == delimiter).skip(lines_read) {
+            records.extend(record);
+            records.push(delimiter);
+        }
+        let mut arguments = Vec::new();
+        for expression in [
+            format!("1,{}{{", empty_lines),
+            format!("1{{x;s/^/{}/;x;}}", replacement(hold_buffer)),
+            format!(
+                "{}{{{}s/^.//;tdesed_reset",
+                empty_lines,
+                "N;".repeat(pattern_lines)
+            ),
+            String::from(":desed_reset"),
+            format!("b{}", RESUME_LABEL),
+            String::from("}"),
+            String::from("d"),
+            String::from("}"),
+            format!(":{}", START_LABEL),
+            script,
+        ] {
+            arguments.push(String::from("-e"));
+            arguments.push(expression);
+        }
+        let max_steps = MAX_STEPS + debugger.count_of_states();
+        let (debug_output, stopped) = self.run(&arguments, records, max_steps)?;
+        Ok(Branch {
+            steps: parse_branch(
+                &debug_output,
+                pattern_buffer,
+                hold_buffer,
+                &debugger.source_code,
+                whole_input,
+            ),
+            stopped,
+        })
+    }
+
+    /// Check that sed accepts the script, without running it on any input.
+    ///
+    /// The error is the message of sed, with line (0-based) of the script sed complained
+    /// about, if it said which.
+    pub fn check_script(&self, script: &str) -> Result<(), (Option<usize>, String)> {
+        let output = Command::new(&self.sed_path)
+            .args(&self.sed_parameters)
+            // Without it, GNU sed creates and truncates files of `w` commands while parsing
+            .arg("--sandbox")
+            .args(["-e", script])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|error| (None, format!("Failed to run {}: {}", self.sed_path, error)))?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().next().unwrap_or("sed failed");
+        // Writing is fine when the script is run for real, unless sandbox was asked for
+        if message.ends_with(SANDBOX_ERROR) && !self.sed_parameters.iter().any(|p| p == "--sandbox")
+        {
+            return Ok(());
+        }
+        // Sed reports where it stopped reading as byte offset, like "-e expression #1, char 12: "
+        match message
+            .split_once(", char ")
+            .and_then(|(_, rest)| rest.split_once(": "))
+        {
+            Some((offset, message)) => {
+                let line = offset
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|offset| *offset > 0)
+                    .map(|offset| {
+                        script.as_bytes()[..(offset - 1).min(script.len())]
+                            .iter()
+                            .filter(|b| **b == b'\n')
+                            .count()
+                    });
+                Err((line, message.to_owned()))
+            }
+            None => Err((None, message.to_owned())),
+        }
+    }
+
+    /// Character separating lines, which is null with `-z`.
+    fn delimiter(&self) -> u8 {
+        if self.sed_parameters.iter().any(|p| p == "-z") {
+            b'\0'
+        } else {
+            b'\n'
+        }
+    }
+
+    /// Run sed with given arguments over input, returning lines of its debug output.
+    ///
+    /// Sed is stopped if it executes more than given number of commands, which is reported
+    /// as true. The error is the one reported by sed, without position in expression,
+    /// which means nothing to user, who typed something else.
+    fn run(
+        &self,
+        arguments: &[String],
+        input: Vec<u8>,
+        max_steps: usize,
+    ) -> Result<(Vec<String>, bool), String> {
+        let failure = |error: std::io::Error| format!("Failed to run {}: {}", self.sed_path, error);
+        let mut sed = Command::new(&self.sed_path)
+            .args(&self.sed_parameters)
+            // Nothing should be written or executed just by trying things out
+            .args(["--sandbox", "--debug"])
+            .args(arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(failure)?;
+        // UNWRAP: All streams are piped above
+        let mut stdin = sed.stdin.take().unwrap();
+        // Sed might exit without reading anything, if expressions are invalid, or block
+        // on writing output while input is still being written
+        thread::spawn(move || stdin.write_all(&input));
+        let mut lines = Vec::new();
+        let mut steps = 0;
+        let mut stopped = false;
+        let mut stdout = BufReader::new(sed.stdout.take().unwrap());
+        let mut line = Vec::new();
+        while stdout.read_until(b'\n', &mut line).map_err(failure)? > 0 {
+            // With -z, output ends with null instead of newline and debug output follows
+            for part in line.strip_suffix(b"\n").unwrap_or(&line).split(|b| *b == 0) {
+                let part = String::from_utf8_lossy(part).into_owned();
+                if part.starts_with("COMMAND:") {
+                    steps += 1;
+                }
+                lines.push(part);
+            }
+            line.clear();
+            if steps > max_steps {
+                // It might have just finished by itself
+                let _ = sed.kill();
+                stopped = true;
+                break;
+            }
+        }
+        let output = sed.wait_with_output().map_err(failure)?;
+        if !stopped && !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr.lines().next().unwrap_or("sed failed");
+            return Err(message
+                .split_once(", char ")
+                .and_then(|(_, rest)| rest.split_once(": "))
+                .map(|(_, message)| message)
+                .unwrap_or(message)
+                .to_owned());
+        }
+        Ok((lines, stopped))
+    }
+}
+
+/// Collect commands executed after prelude of [Evaluator::run_commands] or [Evaluator::what_if],
+/// with buffers after each of them and output they printed.
+///
+/// Commands are found in `source_code`, the program as sed prints it, if they are part of it.
+/// Unless `whole_input` is true, this stops at the end of the first cycle.
+fn parse_branch(
+    debug_output: &[String],
+    pattern_buffer: &str,
+    hold_buffer: &str,
+    source_code: &[String],
+    whole_input: bool,
+) -> Vec<BranchStep> {
+    let mut steps: Vec<BranchStep> = Vec::new();
+    let mut pattern_buffer = pattern_buffer.to_owned();
+    let mut hold_buffer = hold_buffer.to_owned();
+    // Prelude runs again whenever a cycle starts or `D` restarts it
+    let mut in_prelude = true;
+    let mut restarting = false;
+    let mut lines = debug_output
+        .iter()
+        .skip_while(|line| !line.starts_with("INPUT:"));
+    while let Some(line) = lines.next() {
+        if let Some(command) = line.strip_prefix("COMMAND:") {
+            let command = command.trim();
+            if restarting {
+                in_prelude = true;
+                restarting = false;
+            }
+            let label = command.strip_prefix(':');
+            if in_prelude {
+                in_prelude = label != Some(START_LABEL) && label != Some(RESUME_LABEL);
+                continue;
+            }
+            if label == Some(RESUME_LABEL) {
+                continue;
+            }
+            restarting = Script::parse(command)
+                .ok()
+                .and_then(|script| script.commands.first().map(|command| command.name))
+                == Some('D');
+            // The same command might be on more lines, the one right after the previous is
+            // the most likely
+            let previous = steps.last().and_then(|step| step.line).map_or(0, |l| l + 1);
+            let line = (previous..source_code.len())
+                .chain(0..previous)
+                .find(|i| source_code[*i].trim() == command);
+            steps.push(BranchStep {
+                command: command.to_owned(),
+                line,
+                pattern_buffer: pattern_buffer.clone(),
+                hold_buffer: hold_buffer.clone(),
+                output: Vec::new(),
+            });
+            continue;
+        }
+        if let Some(pattern) = line.strip_prefix("PATTERN: ") {
+            pattern_buffer = pattern.to_owned();
+        } else if let Some(hold) = line.strip_prefix("HOLD:") {
+            hold_buffer = hold.trim_start().to_owned();
+        } else if let Some(register) = line.trim_start().strip_prefix("regex[") {
+            // Matched text is printed as is, so it might go over more lines
+            let length = parse_register(register).map_or(0, |(_, range)| range.len());
+            let mut printed = register.split_once('\'').map_or(0, |(_, text)| text.len());
+            while printed <= length {
+                match lines.next() {
+                    Some(line) => printed += line.len() + 1,
+                    None => break,
+                }
+            }
+            continue;
+        } else if line.starts_with("END-OF-CYCLE:") {
+            if !whole_input && !steps.is_empty() {
+                break;
+            }
+            continue;
+        } else if line.starts_with("INPUT:") {
+            in_prelude = true;
+            continue;
+        } else if line.starts_with("MATCHED REGEX REGISTERS") {
+            continue;
+        } else if let Some(step) = steps.last_mut() {
+            step.output.push(line.clone());
+            continue;
+        }
+        if let Some(step) = steps.last_mut().filter(|_| !in_prelude) {
+            step.pattern_buffer = pattern_buffer.clone();
+            step.hold_buffer = hold_buffer.clone();
+        }
+    }
+    steps
+}
+
+/// Escape pattern or hold space, as printed by sed, so it can be used as replacement of `s`.
+pub fn replacement(buffer: &str) -> String {
+    let mut replacement = String::new();
+    for c in String::from_utf8_lossy(&unescape_buffer(buffer)).chars() {
+        match c {
+            '\\' | '&' | '/' => replacement.extend(['\\', c]),
+            '\n' => replacement.push_str("\\n"),
+            c => replacement.push(c),
+        }
+    }
+    replacement
+}
+
+/// Split `regex/replacement/flags` on slashes which aren't escaped. There is always at least
+/// one part, the regex.
+fn split_query(query: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        // Anything after flags is left for sed to complain about
+        if c == '/' && parts.len() < 3 {
+            parts.push(String::new());
+            continue;
+        }
+        // UNWRAP: There is always at least one part
+        let part = parts.last_mut().unwrap();
+        part.push(c);
+        if c == '\\' {
+            part.extend(chars.next());
+        }
+    }
+    parts
+}
+
+/// Parse group and its byte range from register printed by sed, such as `1] = 4-6 'o '`.
+fn parse_register(register: &str) -> Option<(usize, Range<usize>)> {
+    let (group, range) = register.split_once("] = ")?;
+    let (start, rest) = range.split_once('-')?;
+    let end: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((group.parse().ok()?, start.parse().ok()?..end.parse().ok()?))
+}
+
+/// Turn pattern or hold space, as printed by sed in debug output, back into bytes.
+///
+/// Sed escapes backslash, common control characters and any other byte which isn't
+/// printable ASCII as `\oNNN`, sign-extended for bytes over 127.
+pub fn unescape_buffer(buffer: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(buffer.len());
+    let mut chars = buffer.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut encoded = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('a') => bytes.push(0x07),
+            Some('f') => bytes.push(0x0c),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('v') => bytes.push(0x0b),
+            Some('o') => {
+                let mut value: u32 = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) {
+                    value = value.wrapping_mul(8).wrapping_add(digit);
+                    chars.next();
+                }
+                bytes.push((value & 0xff) as u8);
+            }
+            Some(other) => {
+                let mut encoded = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut encoded).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    bytes
+}
+
+/// Escape bytes the way sed prints them, except valid UTF-8 is kept readable.
+///
+/// Also returns character offset of each byte in the escaped text, plus one for the end,
+/// so byte ranges reported by sed can be highlighted.
+fn escape_buffer(bytes: &[u8]) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(bytes.len() + 1);
+    let mut characters = 0;
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let escaped = match c {
+                '\\' => String::from("\\\\"),
+                '\x07' => String::from("\\a"),
+                '\x0c' => String::from("\\f"),
+                '\n' => String::from("\\n"),
+                '\r' => String::from("\\r"),
+                '\t' => String::from("\\t"),
+                '\x0b' => String::from("\\v"),
+                c if c.is_control() => format!("\\o{:03o}", c as u32),
+                c => c.to_string(),
+            };
+            // Bytes in the middle of a character start where the character does
+            offsets.extend(std::iter::repeat_n(characters, c.len_utf8()));
+            characters += escaped.chars().count();
+            text.push_str(&escaped);
+        }
+        for byte in chunk.invalid() {
+            offsets.push(characters);
+            let escaped = format!("\\o{:03o}", byte);
+            characters += escaped.len();
+            text.push_str(&escaped);
+        }
+    }
+    offsets.push(characters);
+    (text, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescape_buffer_reads_sed_escapes() {
+        assert_eq!(unescape_buffer("a\\\\b\\tc\\n"), b"a\\b\tc\n");
+        assert_eq!(unescape_buffer("\\o001\\o177"), [1, 0o177]);
+        // Bytes over 127 are sign-extended
+        assert_eq!(unescape_buffer("\\o37777777751"), [0xe9]);
+        assert_eq!(unescape_buffer("žluť"), "žluť".as_bytes());
+        assert_eq!(unescape_buffer("trailing\\"), b"trailing\\");
+    }
+
+    #[test]
+    fn escape_buffer_keeps_utf8_readable() {
+        let (text, offsets) = escape_buffer("ž\t".as_bytes());
+        assert_eq!(text, "ž\\t");
+        assert_eq!(offsets, [0, 0, 1, 3]);
+        let (text, offsets) = escape_buffer(&[b'a', 0xe9, 1]);
+        assert_eq!(text, "a\\o351\\o001");
+        assert_eq!(offsets, [0, 1, 6, 11]);
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        let bytes = b"x\\y\x07\x0b\r\n\xff";
+        assert_eq!(unescape_buffer(&escape_buffer(bytes).0), bytes);
+    }
+
+    #[test]
+    fn split_query_on_unescaped_slashes() {
+        assert_eq!(split_query("a\\/b"), ["a\\/b"]);
+        assert_eq!(split_query("a/b\\/c/g"), ["a", "b\\/c", "g"]);
+        assert_eq!(split_query("a//"), ["a", "", ""]);
+        assert_eq!(split_query("a/b/g/w x"), ["a", "b", "g/w x"]);
+    }
+}
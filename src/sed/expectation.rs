@@ -0,0 +1,70 @@
+use crate::sed::debugger::Debugger;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Comparison of final sed output with output user expected, line by line.
+pub struct Expectation {
+    pub lines: Vec<ExpectedLine>,
+}
+impl Expectation {
+    pub fn new(expected_output: &[String], debugger: &Debugger) -> Self {
+        let actual_output = debugger.output_lines();
+        let lines = (0..expected_output.len().max(actual_output.len()))
+            .map(|i| {
+                let actual = actual_output.get(i);
+                ExpectedLine {
+                    expected: expected_output.get(i).cloned(),
+                    actual: actual.map(|(_, line)| String::from(*line)),
+                    state: actual.map(|(state, _)| *state),
+                    input_line: actual
+                        .and_then(|(state, _)| debugger.peek_at_state(*state))
+                        .map(|state| state.input_line),
+                }
+            })
+            .collect();
+        Expectation { lines }
+    }
+
+    /// Read expected output from file and compare output of the debugger with it.
+    pub fn read(path: &Path, debugger: &Debugger) -> Result<Self> {
+        let expected_output: Vec<String> = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read expected output {}", path.display()))?
+            .lines()
+            .map(String::from)
+            .collect();
+        Ok(Expectation::new(&expected_output, debugger))
+    }
+
+    /// Index of the first line that doesn't match, if there is any.
+    pub fn first_mismatch(&self) -> Option<usize> {
+        self.lines.iter().position(|line| !line.matches())
+    }
+
+    /// State that printed the first wrong line. If sed printed fewer lines than expected,
+    /// this is the last state, as that is where the missing line should have been printed.
+    pub fn first_mismatch_state(&self, debugger: &Debugger) -> Option<usize> {
+        self.first_mismatch().map(|i| {
+            self.lines[i]
+                .state
+                .unwrap_or_else(|| debugger.count_of_states().saturating_sub(1))
+        })
+    }
+}
+
+/// One line of expected and actual output.
+pub struct ExpectedLine {
+    /// Expected line, or None if sed printed more lines than expected
+    pub expected: Option<String>,
+    /// Line printed by sed, or None if sed printed fewer lines than expected
+    pub actual: Option<String>,
+    /// State (0-based) that printed the actual line
+    pub state: Option<usize>,
+    /// Line of input file sed was processing when it printed the actual line
+    pub input_line: Option<usize>,
+}
+impl ExpectedLine {
+    pub fn matches(&self) -> bool {
+        self.expected == self.actual
+    }
+}
@@ -0,0 +1,309 @@
+use crate::sed::lint::gnu_extensions;
+use crate::sed::regex::{unescape_command_regexes, RegexTree};
+use crate::sed::script::{Address, AddressPart, Argument, Command, Token};
+
+/// Describe sed command in plain English, as pairs of topic and sentence, such as
+/// `("Address", "Runs on every line.")`.
+///
+/// The command is parsed from the program, as sed prints it, so regexes are unescaped first,
+/// see [unescape_command_regexes]. `source` is the program the command was parsed from, one line
+/// each. If `extended` is true, regexes are ERE, as with `sed -E`.
+pub fn explain(command: &Command, source: &[&str], extended: bool) -> Vec<(&'static str, String)> {
+    let command = &unescape_command_regexes(command);
+    let mut explanation = Vec::new();
+    if !matches!(command.name, ':' | '}') {
+        explanation.push(("Address", describe_address(command.address.as_ref())));
+    }
+    explanation.push(("Command", describe_command(command)));
+    for regex in command.regexes() {
+        let tree = RegexTree::parse(&regex.pattern.text, extended);
+        explanation.push((
+            "Regex",
+            format!("/{}/ matches {}.", regex.pattern.text, tree.describe()),
+        ));
+        for flag in regex.flags.chars() {
+            explanation.push(("Flag", describe_flag(flag)));
+        }
+    }
+    if let Argument::Substitute {
+        replacement,
+        flags,
+        file,
+        ..
+    } = &command.argument
+    {
+        explanation.push(("Replacement", describe_replacement(replacement)));
+        for flag in describe_substitute_flags(flags, file.as_ref()) {
+            explanation.push(("Flag", flag));
+        }
+    }
+    let extensions: Vec<String> = gnu_extensions(command, source)
+        .into_iter()
+        .map(|(_, extension)| extension)
+        .collect();
+    explanation.push((
+        "GNU extensions",
+        if extensions.is_empty() {
+            String::from("None, this works in any POSIX sed.")
+        } else {
+            format!("Uses {}.", extensions.join(", "))
+        },
+    ));
+    explanation
+}
+
+fn describe_address(address: Option<&Address>) -> String {
+    let address = match address {
+        Some(address) => address,
+        None => return String::from("Runs on every line."),
+    };
+    let selected = match &address.second {
+        None => describe_address_part(&address.first),
+        Some(second) => {
+            let first = match address.first {
+                // Unlike `1,/regex/`, this can end the range right on the first line
+                AddressPart::Line(0) => String::from("the start of input"),
+                ref first => describe_address_part(first),
+            };
+            let end = match second {
+                AddressPart::Relative(count) => format!("{} more lines", count),
+                AddressPart::Multiple(multiple) => {
+                    format!("the next line whose number is a multiple of {}", multiple)
+                }
+                AddressPart::Line(line) => format!(
+                    "line {} (or just the first line, if it's after line {})",
+                    line, line
+                ),
+                second => describe_address_part(second),
+            };
+            format!("ranges from {} to {}, inclusive", first, end)
+        }
+    };
+    if address.negated {
+        format!("Runs on all lines except {}.", selected)
+    } else {
+        format!("Runs on {}.", selected)
+    }
+}
+
+fn describe_address_part(part: &AddressPart) -> String {
+    match part {
+        AddressPart::Line(line) => format!("line {}", line),
+        AddressPart::Last => String::from("the last line"),
+        AddressPart::Step { first, step } => {
+            format!("every {}. line, starting with line {}", step, first)
+        }
+        AddressPart::Regex(regex) if regex.pattern.text.is_empty() => {
+            String::from("lines matching the last regex used")
+        }
+        AddressPart::Regex(regex) => format!("lines matching /{}/", regex.pattern.text),
+        AddressPart::Relative(count) => format!("{} more lines", count),
+        AddressPart::Multiple(multiple) => format!("line number multiple of {}", multiple),
+    }
+}
+
+fn describe_command(command: &Command) -> String {
+    let word = match &command.argument {
+        Argument::Word(Some(word)) => Some(word.text.as_str()),
+        _ => None,
+    };
+    let label = || match command.label() {
+        Some(label) => format!("to label `{}`", label),
+        None => String::from("to the end of script, ending the cycle"),
+    };
+    match command.name {
+        '{' => String::from("Runs commands up to the matching `}` only on selected lines."),
+        '}' => String::from("Ends the block."),
+        ':' => format!(
+            "Defines label `{}`, which `b`, `t` and `T` can jump to.",
+            command.label().unwrap_or("")
+        ),
+        '=' => String::from("Prints number of the current input line."),
+        'a' => format!(
+            "Queues {} to be printed at the end of the cycle or when next line is read.",
+            describe_text(&command.argument)
+        ),
+        'i' => format!("Prints {} right away.", describe_text(&command.argument)),
+        'c' => format!(
+            "Deletes pattern space and prints {} instead (with a range, at its end), \
+             then starts next cycle.",
+            describe_text(&command.argument)
+        ),
+        'b' => format!("Jumps {}.", label()),
+        't' => format!(
+            "Jumps {} if there was a successful substitution since the last input line \
+             was read or the last `t` or `T` jumped.",
+            label()
+        ),
+        'T' => format!(
+            "Jumps {} if there was no successful substitution since the last input line \
+             was read or the last `t` or `T` jumped.",
+            label()
+        ),
+        'd' => String::from("Deletes pattern space and starts next cycle without printing."),
+        'D' => String::from(
+            "Deletes pattern space up to the first newline and restarts the cycle with the rest, \
+             without reading input. Without newline, acts like `d`.",
+        ),
+        'e' => match word {
+            Some(shell) => format!(
+                "Runs `{}` in shell and prints its output right away.",
+                shell
+            ),
+            None => String::from(
+                "Runs pattern space as shell command and replaces pattern space with its output.",
+            ),
+        },
+        'F' => String::from("Prints name of the current input file."),
+        'g' => String::from("Replaces pattern space with hold space."),
+        'G' => String::from("Appends a newline and hold space to pattern space."),
+        'h' => String::from("Replaces hold space with pattern space."),
+        'H' => String::from("Appends a newline and pattern space to hold space."),
+        'l' => match word {
+            Some(length) => format!(
+                "Prints pattern space unambiguously, with escapes, wrapped at {} characters.",
+                length
+            ),
+            None => String::from("Prints pattern space unambiguously, with escapes."),
+        },
+        'L' => String::from("Fills and joins lines of pattern space (deprecated)."),
+        'n' => String::from(
+            "Prints pattern space (unless -n is used) and replaces it with next input line. \
+             Without more input, sed exits.",
+        ),
+        'N' => String::from(
+            "Appends a newline and next input line to pattern space. \
+             Without more input, sed prints pattern space and exits.",
+        ),
+        'p' => String::from("Prints pattern space."),
+        'P' => String::from("Prints pattern space up to the first newline."),
+        'q' => format!(
+            "Prints pattern space (unless -n is used) and exits{}.",
+            describe_exit_code(word)
+        ),
+        'Q' => format!("Exits without printing{}.", describe_exit_code(word)),
+        'r' => format!(
+            "Queues contents of file `{}` to be printed at the end of the cycle.",
+            word.unwrap_or("")
+        ),
+        'R' => format!(
+            "Queues next line of file `{}` to be printed at the end of the cycle.",
+            word.unwrap_or("")
+        ),
+        's' => String::from("Replaces text matching regex with replacement."),
+        'v' => String::from("Does nothing, but fails unless sed supports GNU extensions."),
+        'w' => format!("Writes pattern space to file `{}`.", word.unwrap_or("")),
+        'W' => format!(
+            "Writes pattern space up to the first newline to file `{}`.",
+            word.unwrap_or("")
+        ),
+        'x' => String::from("Exchanges pattern space and hold space."),
+        'y' => match &command.argument {
+            Argument::Transliterate { source, target, .. } => format!(
+                "Replaces each character of `{}` with the character at the same place in `{}`.",
+                source.text, target.text
+            ),
+            _ => String::from("Replaces characters."),
+        },
+        'z' => String::from("Empties pattern space."),
+        other => format!("Unknown command `{}`.", other),
+    }
+}
+
+fn describe_text(argument: &Argument) -> String {
+    match argument {
+        Argument::Text { text, .. } => format!("text \"{}\"", text.text.replace('\n', "\\n")),
+        _ => String::from("text"),
+    }
+}
+
+fn describe_exit_code(code: Option<&str>) -> String {
+    match code {
+        Some(code) => format!(" with exit code {}", code),
+        None => String::new(),
+    }
+}
+
+/// Describe flag of address regex.
+fn describe_flag(flag: char) -> String {
+    match flag {
+        'I' | 'i' => String::from("`I` makes the regex case-insensitive."),
+        'M' | 'm' => String::from(
+            "`M` is multiline mode: `^` and `$` match at newlines too, \\` and \\' match \
+             the start and end of pattern space.",
+        ),
+        other => format!("`{}` is unknown flag.", other),
+    }
+}
+
+fn describe_substitute_flags(flags: &Token, file: Option<&Token>) -> Vec<String> {
+    let mut descriptions = Vec::new();
+    let global = flags.text.contains('g');
+    let number: String = flags.text.chars().filter(char::is_ascii_digit).collect();
+    match (global, number.is_empty()) {
+        (true, true) => descriptions.push(String::from("`g` replaces all matches.")),
+        (true, false) => descriptions.push(format!(
+            "`{}g` replaces match number {} and all after it.",
+            number, number
+        )),
+        (false, false) => descriptions.push(format!(
+            "`{}` replaces only match number {}.",
+            number, number
+        )),
+        (false, true) => descriptions.push(String::from("Only the first match is replaced.")),
+    }
+    for flag in flags.text.chars() {
+        match flag {
+            'p' => descriptions.push(String::from(
+                "`p` prints pattern space if a replacement was made.",
+            )),
+            'e' => descriptions.push(String::from(
+                "`e` runs pattern space as shell command if a replacement was made, \
+                 and replaces pattern space with its output.",
+            )),
+            'i' | 'I' | 'm' | 'M' => descriptions.push(describe_flag(flag)),
+            _ => {}
+        }
+    }
+    if let Some(file) = file {
+        descriptions.push(format!(
+            "`w` writes pattern space to file `{}` if a replacement was made.",
+            file.text
+        ));
+    }
+    descriptions
+}
+
+/// Describe special parts of replacement, such as `&` or `\1`.
+fn describe_replacement(replacement: &Token) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut chars = replacement.text.chars();
+    while let Some(c) = chars.next() {
+        let part = match c {
+            '&' => String::from("`&` is the whole match"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    format!("`\\{}` is text matched by group {}", digit, digit)
+                }
+                Some('n') => String::from("`\\n` is a newline"),
+                Some('t') => String::from("`\\t` is a tab"),
+                Some('L') => String::from("`\\L` turns the rest to lowercase"),
+                Some('U') => String::from("`\\U` turns the rest to uppercase"),
+                Some('l') => String::from("`\\l` turns the next character to lowercase"),
+                Some('u') => String::from("`\\u` turns the next character to uppercase"),
+                Some('E') => String::from("`\\E` stops case conversion"),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if !parts.contains(&part) {
+            parts.push(part);
+        }
+    }
+    let text = format!("\"{}\"", replacement.text.replace('\n', "\\n"));
+    if parts.is_empty() {
+        format!("{}, taken literally.", text)
+    } else {
+        format!("{}, where {}.", text, parts.join(", "))
+    }
+}
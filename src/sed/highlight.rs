@@ -0,0 +1,203 @@
+use crate::sed::script::{AddressPart, Argument, Position, Regex, Script, Token};
+
+/// What does a piece of sed script mean, so it can be colored accordingly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Address,
+    Command,
+    Label,
+    Regex,
+    /// Replacement of `s` and target of `y`
+    Replacement,
+    /// `&` and `\1` to `\9` in replacement
+    Reference,
+    /// Escaped character in regex or replacement, such as `\n` or `\/`
+    Escape,
+    /// Flags of `s` and address regexes, numeric arguments of commands
+    Flags,
+    /// Delimiters of regexes, `s` and `y`
+    Delimiter,
+    /// Text of `a`, `i` and `c`, file names and shell commands
+    Text,
+    Comment,
+}
+
+/// Piece of sed script with its meaning. Highlights can span more lines.
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub start: Position,
+    /// Position right after the highlight
+    pub end: Position,
+    pub kind: TokenKind,
+}
+
+/// Split the script into highlighted pieces. Highlights are ordered from the widest,
+/// such as the whole address, to the narrowest, such as regex within the address,
+/// so later ones should be drawn over the earlier ones.
+pub fn highlight(script: &Script) -> Vec<Highlight> {
+    let mut highlights = Vec::new();
+    let mut add = |start: Position, end: Position, kind: TokenKind| {
+        highlights.push(Highlight { start, end, kind });
+    };
+    for comment in &script.comments {
+        add(comment.start, comment.end, TokenKind::Comment);
+    }
+    for command in &script.commands {
+        if let Some(address) = &command.address {
+            add(address.start, address.end, TokenKind::Address);
+            let parts = std::iter::once(&address.first).chain(address.second.as_ref());
+            for part in parts {
+                if let AddressPart::Regex(regex) = part {
+                    // Custom delimiter is preceded by backslash
+                    let opening = if regex.delimiter == '/' { 1 } else { 2 };
+                    add(
+                        shift(regex.pattern.start, -opening),
+                        regex.pattern.start,
+                        TokenKind::Delimiter,
+                    );
+                    highlight_regex(&mut add, regex);
+                    let flags = shift(regex.pattern.end, 1);
+                    add(
+                        flags,
+                        shift(flags, regex.flags.chars().count() as isize),
+                        TokenKind::Flags,
+                    );
+                }
+            }
+        }
+        add(
+            command.name_position,
+            shift(command.name_position, 1),
+            TokenKind::Command,
+        );
+        match &command.argument {
+            Argument::Label(Some(label)) => add(label.start, label.end, TokenKind::Label),
+            Argument::Substitute {
+                regex,
+                replacement,
+                flags,
+                file,
+            } => {
+                add(
+                    shift(regex.pattern.start, -1),
+                    regex.pattern.start,
+                    TokenKind::Delimiter,
+                );
+                highlight_regex(&mut add, regex);
+                highlight_escapes(&mut add, replacement, TokenKind::Replacement);
+                add(
+                    replacement.end,
+                    shift(replacement.end, 1),
+                    TokenKind::Delimiter,
+                );
+                add(flags.start, flags.end, TokenKind::Flags);
+                if let Some(file) = file {
+                    // Flag `w` is followed by file name
+                    add(flags.end, shift(flags.end, 1), TokenKind::Flags);
+                    add(file.start, file.end, TokenKind::Text);
+                }
+            }
+            Argument::Transliterate { source, target, .. } => {
+                add(shift(source.start, -1), source.start, TokenKind::Delimiter);
+                highlight_escapes(&mut add, source, TokenKind::Regex);
+                add(source.end, shift(source.end, 1), TokenKind::Delimiter);
+                highlight_escapes(&mut add, target, TokenKind::Replacement);
+                add(target.end, shift(target.end, 1), TokenKind::Delimiter);
+            }
+            Argument::Text { text, .. } => add(text.start, text.end, TokenKind::Text),
+            Argument::Word(Some(word)) => {
+                let kind = if matches!(command.name, 'l' | 'L' | 'q' | 'Q') {
+                    TokenKind::Flags
+                } else {
+                    TokenKind::Text
+                };
+                add(word.start, word.end, kind);
+            }
+            _ => {}
+        }
+    }
+    highlights
+}
+
+/// Highlight regex together with its closing delimiter.
+fn highlight_regex(add: &mut impl FnMut(Position, Position, TokenKind), regex: &Regex) {
+    highlight_escapes(add, &regex.pattern, TokenKind::Regex);
+    add(
+        regex.pattern.end,
+        shift(regex.pattern.end, 1),
+        TokenKind::Delimiter,
+    );
+}
+
+/// Highlight token and escaped characters in it. In replacement, references to groups are
+/// highlighted as well.
+fn highlight_escapes(
+    add: &mut impl FnMut(Position, Position, TokenKind),
+    token: &Token,
+    kind: TokenKind,
+) {
+    add(token.start, token.end, kind);
+    let mut position = token.start;
+    let mut chars = token.text.chars();
+    while let Some(c) = chars.next() {
+        let start = position;
+        position = advance(position, c);
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                position = advance(position, escaped);
+                let escape_kind = if kind == TokenKind::Replacement && escaped.is_ascii_digit() {
+                    TokenKind::Reference
+                } else {
+                    TokenKind::Escape
+                };
+                add(start, position, escape_kind);
+            }
+        } else if c == '&' && kind == TokenKind::Replacement {
+            add(start, position, TokenKind::Reference);
+        }
+    }
+}
+
+/// Position after the character.
+fn advance(position: Position, c: char) -> Position {
+    if c == '\n' {
+        Position {
+            line: position.line + 1,
+            column: 0,
+        }
+    } else {
+        shift(position, 1)
+    }
+}
+
+/// Move position by some columns on the same line.
+fn shift(position: Position, columns: isize) -> Position {
+    Position {
+        line: position.line,
+        column: (position.column as isize + columns).max(0) as usize,
+    }
+}
+
+/// Kind of each character of the line (0-based) of highlighted script.
+pub fn line_kinds(highlights: &[Highlight], line: usize, length: usize) -> Vec<Option<TokenKind>> {
+    let mut kinds = vec![None; length];
+    for highlight in highlights {
+        if highlight.start.line > line || highlight.end.line < line {
+            continue;
+        }
+        let start = if highlight.start.line == line {
+            highlight.start.column
+        } else {
+            0
+        };
+        let end = if highlight.end.line == line {
+            highlight.end.column.min(length)
+        } else {
+            length
+        };
+        for kind in kinds.iter_mut().take(end).skip(start) {
+            *kind = Some(highlight.kind);
+        }
+    }
+    kinds
+}
@@ -0,0 +1,434 @@
+use crate::sed::script::{AddressPart, Argument, Command, Position, Regex, Script};
+use std::fmt;
+
+/// Problem found in sed script without running it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub position: Position,
+    pub message: String,
+    /// Index of the command the problem is in, unless the script failed to parse
+    pub command: Option<usize>,
+    /// Line of the program as sed prints it (0-based), see [locate_in_program]
+    pub program_line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Commands that exist only in GNU sed.
+const GNU_COMMANDS: &str = "eFLQRTvWz";
+/// Escapes in regexes that exist only in GNU sed.
+const GNU_REGEX_ESCAPES: &str = "+?|wWsSbB<>'`tcdox";
+
+/// Check sed script for mistakes. If `posix` is true, GNU extensions are reported as well.
+///
+/// Returns the script too, unless it failed to parse. Diagnostics are ordered by position.
+pub fn lint(source: &str, posix: bool) -> (Option<Script>, Vec<Diagnostic>) {
+    let script = match Script::parse(source) {
+        Ok(script) => script,
+        Err(error) => {
+            let diagnostic = Diagnostic {
+                severity: Severity::Error,
+                position: error.position,
+                message: error.message,
+                command: None,
+                program_line: None,
+            };
+            return (None, vec![diagnostic]);
+        }
+    };
+    let mut linter = Linter {
+        source: source.lines().collect(),
+        script: &script,
+        diagnostics: Vec::new(),
+    };
+    linter.check_labels();
+    linter.check_unreachable_code();
+    linter.check_tests();
+    if posix {
+        linter.check_gnu_extensions();
+    }
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.position);
+    (Some(script), diagnostics)
+}
+
+/// Find line of the program, as desed shows it, for each diagnostic.
+pub fn locate_in_program(
+    diagnostics: &mut [Diagnostic],
+    script: &Script,
+    program_source: &[String],
+) {
+    let program_lines = script.program_lines(program_source);
+    for diagnostic in diagnostics {
+        diagnostic.program_line = diagnostic
+            .command
+            .and_then(|command| program_lines.get(command).copied().flatten());
+    }
+}
+
+struct Linter<'a> {
+    source: Vec<&'a str>,
+    script: &'a Script,
+    diagnostics: Vec<Diagnostic>,
+}
+impl<'a> Linter<'a> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        command: usize,
+        position: Position,
+        message: impl Into<String>,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            position,
+            message: message.into(),
+            command: Some(command),
+            program_line: None,
+        });
+    }
+
+    fn check_labels(&mut self) {
+        let labels = self.script.labels();
+        for (i, (label, command)) in labels.iter().enumerate() {
+            if labels[..i].iter().any(|(other, _)| other == label) {
+                let position = self.script.commands[*command].start;
+                self.report(
+                    Severity::Error,
+                    *command,
+                    position,
+                    format!("duplicate label `{}`", label),
+                );
+            }
+        }
+        for (i, command) in self.script.commands.iter().enumerate() {
+            if !command.is_branch() {
+                continue;
+            }
+            if let Argument::Label(Some(label)) = &command.argument {
+                if !labels.iter().any(|(defined, _)| *defined == label.text) {
+                    self.report(
+                        Severity::Error,
+                        i,
+                        label.start,
+                        format!("can't find label for jump to `{}`", label.text),
+                    );
+                }
+            }
+        }
+        for (label, command) in labels {
+            let used = self
+                .script
+                .commands
+                .iter()
+                .any(|other| other.is_branch() && other.label() == Some(label));
+            if !used {
+                let position = self.script.commands[command].start;
+                self.report(
+                    Severity::Warning,
+                    command,
+                    position,
+                    format!("label `{}` is never jumped to", label),
+                );
+            }
+        }
+    }
+
+    /// Commands after unconditional `b` can't run, up to the next label to jump to or
+    /// the end of the block `b` is in.
+    fn check_unreachable_code(&mut self) {
+        let commands = &self.script.commands;
+        let mut i = 0;
+        while i < commands.len() {
+            let command = &commands[i];
+            i += 1;
+            if command.name != 'b' || command.address.is_some() {
+                continue;
+            }
+            let message = match command.label() {
+                Some(label) => format!("unreachable code, `b {}` above always jumps away", label),
+                None => String::from("unreachable code, `b` above always jumps away"),
+            };
+            // Blocks opened after `b` are skipped as a whole, labels inside them are not
+            let mut depth = 0;
+            while let Some(next) = commands.get(i) {
+                match next.name {
+                    ':' => break,
+                    '}' if depth == 0 => break,
+                    '}' => depth -= 1,
+                    '{' => depth += 1,
+                    _ => {}
+                }
+                if next.name != '}' {
+                    self.report(Severity::Warning, i, next.start, message.clone());
+                }
+                i += 1;
+            }
+        }
+    }
+
+    /// `t` and `T` depend on substitutions, so without any `s` before them, they never change anything.
+    fn check_tests(&mut self) {
+        let commands = &self.script.commands;
+        for (i, command) in commands.iter().enumerate() {
+            if matches!(command.name, 't' | 'T') && !commands[..i].iter().any(|c| c.name == 's') {
+                let message = format!(
+                    "`{}` has no `s` command before it, so it always {}",
+                    command.name,
+                    if command.name == 't' {
+                        "falls through"
+                    } else {
+                        "jumps"
+                    }
+                );
+                self.report(Severity::Warning, i, command.name_position, message);
+            }
+        }
+    }
+
+    fn check_gnu_extensions(&mut self) {
+        for (i, command) in self.script.commands.iter().enumerate() {
+            for (position, message) in gnu_extensions(command, &self.source) {
+                self.report(
+                    Severity::Warning,
+                    i,
+                    position,
+                    format!("{} is a GNU extension", message),
+                );
+            }
+        }
+    }
+}
+
+/// Find GNU extensions used in the command, with their position and description.
+/// `source` is the script the command was parsed from, one line each.
+pub fn gnu_extensions(command: &Command, source: &[&str]) -> Vec<(Position, String)> {
+    let mut extensions = Vec::new();
+    let mut regexes: Vec<&Regex> = Vec::new();
+    if let Some(address) = &command.address {
+        let parts = std::iter::once(&address.first).chain(address.second.as_ref());
+        for (i, part) in parts.enumerate() {
+            match part {
+                AddressPart::Line(0) if i == 0 => {
+                    extensions.push((address.start, String::from("address `0,/regex/`")))
+                }
+                AddressPart::Step { .. } => {
+                    extensions.push((address.start, String::from("address `first~step`")))
+                }
+                AddressPart::Relative(_) => {
+                    extensions.push((address.start, String::from("address `addr,+N`")))
+                }
+                AddressPart::Multiple(_) => {
+                    extensions.push((address.start, String::from("address `addr,~N`")))
+                }
+                AddressPart::Regex(regex) => {
+                    if !regex.flags.is_empty() {
+                        extensions
+                            .push((regex.pattern.end, format!("address flag `{}`", regex.flags)));
+                    }
+                    regexes.push(regex);
+                }
+                _ => {}
+            }
+        }
+    }
+    if GNU_COMMANDS.contains(command.name) {
+        extensions.push((command.name_position, format!("command `{}`", command.name)));
+    }
+    match &command.argument {
+        Argument::Label(Some(label)) if char_at(source, label.end) == Some(';') => {
+            extensions.push((label.end, String::from("ending label with `;`")));
+        }
+        Argument::Substitute { regex, flags, .. } => {
+            regexes.push(regex);
+            for (i, flag) in flags.text.chars().enumerate() {
+                if "eiImM".contains(flag) {
+                    let position = Position {
+                        line: flags.start.line,
+                        column: flags.start.column + i,
+                    };
+                    extensions.push((position, format!("flag `{}` of `s`", flag)));
+                }
+            }
+        }
+        Argument::Text {
+            text,
+            one_liner: true,
+        } => {
+            extensions.push((
+                text.start,
+                format!("text on the same line as `{}`", command.name),
+            ));
+        }
+        Argument::Word(Some(word)) if matches!(command.name, 'l' | 'q') => {
+            extensions.push((word.start, format!("argument of `{}`", command.name)));
+        }
+        _ => {}
+    }
+    for regex in regexes {
+        if let Some((offset, escape)) = gnu_regex_escape(&regex.pattern.text) {
+            let position = Position {
+                line: regex.pattern.start.line,
+                column: regex.pattern.start.column + offset,
+            };
+            extensions.push((position, format!("regex escape `\\{}`", escape)));
+        }
+    }
+    extensions
+}
+
+fn char_at(source: &[&str], position: Position) -> Option<char> {
+    source
+        .get(position.line)
+        .and_then(|line| line.chars().nth(position.column))
+}
+
+/// Find the first GNU escape in regex, with its offset in characters.
+/// Bracket expressions are skipped, backslash is literal there.
+fn gnu_regex_escape(pattern: &str) -> Option<(usize, char)> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                let escaped = *chars.get(i + 1)?;
+                if GNU_REGEX_ESCAPES.contains(escaped) {
+                    return Some((i, escaped));
+                }
+                i += 2;
+            }
+            '[' => {
+                i += 1;
+                if chars.get(i) == Some(&'^') {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&']') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i] != ']' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lint the script and describe each diagnostic as `line:column severity: message`.
+    fn check(source: &str, posix: bool) -> Vec<String> {
+        lint(source, posix)
+            .1
+            .iter()
+            .map(|diagnostic| {
+                format!(
+                    "{}:{} {}: {}",
+                    diagnostic.position.line + 1,
+                    diagnostic.position.column + 1,
+                    diagnostic.severity,
+                    diagnostic.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean_script_has_no_diagnostics() {
+        assert!(check(":a\ns/x/y/\nta", true).is_empty());
+    }
+
+    #[test]
+    fn syntax_error_is_the_only_diagnostic() {
+        let (script, diagnostics) = lint("p\ns/x/y", false);
+        assert!(script.is_none());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].command, None);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            check(":a\n:a\n:unused\nb a\nb missing", false),
+            [
+                "2:1 error: duplicate label `a`",
+                "3:1 warning: label `unused` is never jumped to",
+                "5:1 warning: unreachable code, `b a` above always jumps away",
+                "5:3 error: can't find label for jump to `missing`",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_after_unconditional_branch_is_unreachable() {
+        assert_eq!(
+            check("b\np\np", false),
+            [
+                "2:1 warning: unreachable code, `b` above always jumps away",
+                "3:1 warning: unreachable code, `b` above always jumps away",
+            ]
+        );
+        assert!(check("/x/b\np\n$!{b\n}\np", false).is_empty());
+    }
+
+    #[test]
+    fn unreachable_code_ends_at_label_or_block_end() {
+        assert_eq!(
+            check("/x/{b\np\n}\np\nb a\n/y/{\np\n}\n:a\np", false),
+            [
+                "2:1 warning: unreachable code, `b` above always jumps away",
+                "6:1 warning: unreachable code, `b a` above always jumps away",
+                "7:1 warning: unreachable code, `b a` above always jumps away",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_without_substitution() {
+        assert_eq!(
+            check("T\ns/x/y/\nt", false),
+            ["1:1 warning: `T` has no `s` command before it, so it always jumps"]
+        );
+    }
+
+    #[test]
+    fn gnu_extensions_only_in_posix_mode() {
+        let source = "0,/x/I s/a\\+/b/gI\n1a text\nF";
+        assert!(check(source, false).is_empty());
+        assert_eq!(
+            check(source, true),
+            [
+                "1:1 warning: address `0,/regex/` is a GNU extension",
+                "1:5 warning: address flag `I` is a GNU extension",
+                "1:11 warning: regex escape `\\+` is a GNU extension",
+                "1:17 warning: flag `I` of `s` is a GNU extension",
+                "2:4 warning: text on the same line as `a` is a GNU extension",
+                "3:1 warning: command `F` is a GNU extension",
+            ]
+        );
+    }
+
+    #[test]
+    fn backslash_in_bracket_expression_is_not_escape() {
+        assert_eq!(gnu_regex_escape("[\\+]x"), None);
+        assert_eq!(gnu_regex_escape("[]\\+]\\|"), Some((5, '|')));
+    }
+}
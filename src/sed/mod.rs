@@ -1,2 +1,14 @@
 mod communication;
+pub mod comparison;
+pub mod control_flow;
+pub mod coverage;
 pub mod debugger;
+pub mod evaluation;
+pub mod expectation;
+pub mod explain;
+pub mod highlight;
+pub mod lint;
+pub mod profile;
+pub mod regex;
+pub mod script;
+pub mod source_map;
@@ -0,0 +1,164 @@
+use crate::sed::coverage::Coverage;
+use crate::sed::debugger::Debugger;
+use crate::sed::script::{Command, Script};
+
+/// Where does the execution spend its steps.
+pub struct Profile {
+    /// Lines of the program, the most executed first
+    pub lines: Vec<LineCost>,
+    /// Loops formed by a label and branches jumping back to it, the most expensive first
+    pub loops: Vec<LoopCost>,
+    /// Cycles, the most expensive first
+    pub cycles: Vec<CycleCost>,
+}
+impl Profile {
+    pub fn new(debugger: &Debugger) -> Self {
+        let coverage = Coverage::new(debugger);
+        let mut lines: Vec<LineCost> = (0..coverage.total_lines())
+            .filter_map(|line| coverage.count(line).map(|count| LineCost { line, count }))
+            .filter(|cost| cost.count > 0)
+            .collect();
+        lines.sort_by(|a, b| b.count.cmp(&a.count).then(a.line.cmp(&b.line)));
+
+        let mut loops: Vec<LoopCost> = find_loops(&debugger.source_code)
+            .into_iter()
+            .map(|(label, start, end)| {
+                let mut cost = LoopCost {
+                    label,
+                    start,
+                    end,
+                    iterations: 0,
+                    steps: 0,
+                };
+                let mut previous_line: Option<usize> = None;
+                for state in
+                    (0..debugger.count_of_states()).filter_map(|i| debugger.peek_at_state(i))
+                {
+                    let line = state.current_line;
+                    if start <= line && line <= end {
+                        cost.steps += 1;
+                    }
+                    // Reaching the label from inside the loop means a branch jumped back
+                    if line == start && matches!(previous_line, Some(p) if start < p && p <= end) {
+                        cost.iterations += 1;
+                    }
+                    previous_line = Some(line);
+                }
+                cost
+            })
+            .collect();
+        loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.start.cmp(&b.start)));
+
+        let mut cycles: Vec<CycleCost> = debugger
+            .cycles()
+            .into_iter()
+            .map(|(input_line, states)| CycleCost {
+                input_line,
+                first_state: states[0],
+                steps: states.len(),
+            })
+            .collect();
+        cycles.sort_by(|a, b| {
+            b.steps
+                .cmp(&a.steps)
+                .then(a.first_state.cmp(&b.first_state))
+        });
+
+        Profile {
+            lines,
+            loops,
+            cycles,
+        }
+    }
+
+    /// Total number of steps over all cycles.
+    pub fn total_steps(&self) -> usize {
+        self.cycles.iter().map(|cycle| cycle.steps).sum()
+    }
+}
+
+/// How many times was a line of the program executed.
+pub struct LineCost {
+    /// Line (0-based) of the program
+    pub line: usize,
+    pub count: usize,
+}
+
+/// Cost of a loop, which starts at a label and ends with the last branch back to it.
+pub struct LoopCost {
+    pub label: String,
+    /// Line (0-based) of the label
+    pub start: usize,
+    /// Line (0-based) of the last branch jumping back to the label
+    pub end: usize,
+    /// How many times did a branch jump back to the label
+    pub iterations: usize,
+    /// How many commands inside the loop were executed, including nested loops
+    pub steps: usize,
+}
+
+/// Cost of processing one input line.
+pub struct CycleCost {
+    pub input_line: usize,
+    /// First state (0-based) of the cycle
+    pub first_state: usize,
+    /// Number of states of the cycle
+    pub steps: usize,
+}
+
+/// Find labels with branches (`b`, `t` or `T`) below them jumping back up.
+///
+/// Returns label name with lines (0-based) of the label and of the last such branch.
+fn find_loops(source_code: &[String]) -> Vec<(String, usize, usize)> {
+    let commands: Vec<Option<Command>> = Script::parse_program(source_code)
+        .into_iter()
+        .map(|script| script.and_then(|script| script.commands.into_iter().next()))
+        .collect();
+    commands
+        .iter()
+        .enumerate()
+        .filter_map(|(start, command)| {
+            let label = command.as_ref().filter(|c| c.name == ':')?.label()?;
+            let end = commands
+                .iter()
+                .enumerate()
+                .skip(start + 1)
+                .rev()
+                .find(|(_, command)| {
+                    command
+                        .as_ref()
+                        .filter(|c| matches!(c.name, 'b' | 't' | 'T'))
+                        .and_then(|c| c.label())
+                        == Some(label)
+                })
+                .map(|(i, _)| i)?;
+            Some((String::from(label), start, end))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| String::from(*line)).collect()
+    }
+
+    #[test]
+    fn loops_end_with_addressed_branches() {
+        let loops = find_loops(&program(&[
+            ":a", "s/x/y/", "/y/ t a", ":b", "$! N", "$! b b", "b a",
+        ]));
+        assert_eq!(
+            loops,
+            [(String::from("a"), 0, 6), (String::from("b"), 3, 5)]
+        );
+    }
+
+    #[test]
+    fn appended_text_is_not_a_label() {
+        let loops = find_loops(&program(&["1 a\\foo", ":a", "", ":a", "b a"]));
+        assert_eq!(loops, [(String::from("a"), 3, 4)]);
+    }
+}
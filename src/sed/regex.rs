@@ -0,0 +1,488 @@
+use crate::sed::script::{AddressPart, Argument, Command, Regex};
+
+/// Regex of sed parsed into a tree, either basic (BRE) or extended (ERE) syntax,
+/// GNU extensions included.
+///
+/// Parsing is lenient: sed refuses invalid regexes on its own, so anything that doesn't
+/// make sense here is taken literally.
+#[derive(Debug, Clone)]
+pub struct RegexTree {
+    /// Alternatives of the whole regex, usually just one
+    pub alternatives: Vec<Vec<Element>>,
+    /// Number of capture groups
+    pub groups: usize,
+}
+
+/// Piece of regex, optionally repeated.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub node: Node,
+    pub quantifier: Option<Quantifier>,
+    /// Offset of the first character, in characters of the pattern
+    pub start: usize,
+    /// Offset right after the element, including quantifier
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// Character matched literally, escapes such as `\n` are decoded
+    Literal(char),
+    /// `.`
+    Any,
+    /// Bracket expression, such as `[^a-z]`. Items are written as between the brackets.
+    Bracket { negated: bool, items: String },
+    /// `^`
+    Start,
+    /// `$`
+    End,
+    /// Special escape, such as `\w`, `\b` or `\<`
+    Escape(char),
+    /// Capture group, numbered from 1 in order of opening parentheses
+    Group {
+        number: usize,
+        alternatives: Vec<Vec<Element>>,
+    },
+    /// `\1` to `\9`
+    BackReference(usize),
+}
+
+/// How many times is an element repeated, such as `*` or `\{2,3\}`.
+#[derive(Debug, Clone)]
+pub struct Quantifier {
+    pub min: usize,
+    /// None if unbounded
+    pub max: Option<usize>,
+}
+
+impl RegexTree {
+    /// Parse regex as written between delimiters. If `extended` is true, the regex is ERE,
+    /// as with `sed -E`.
+    pub fn parse(pattern: &str, extended: bool) -> RegexTree {
+        let mut parser = RegexParser {
+            chars: pattern.chars().collect(),
+            index: 0,
+            extended,
+            groups: 0,
+        };
+        let alternatives = parser.parse_alternatives(false);
+        RegexTree {
+            alternatives,
+            groups: parser.groups,
+        }
+    }
+
+    /// Describe what the regex matches in plain English.
+    pub fn describe(&self) -> String {
+        if self.alternatives.iter().all(Vec::is_empty) {
+            return String::from("the last regex used");
+        }
+        describe_alternatives(&self.alternatives)
+    }
+}
+
+/// Turn regex as sed prints it in the program back into regex as it would be written.
+///
+/// Sed prints backslashes of regexes doubled and slashes escaped, so `/a\(b\)\//`
+/// in the program is `/a\(b\)//` in the script.
+pub fn unescape_program_regex(pattern: &str) -> String {
+    let mut unescaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('\\' | '/'))) => {
+                unescaped.push(escaped);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Copy of the command with regexes as they would be written in the script.
+pub fn unescape_command_regexes(command: &Command) -> Command {
+    let mut command = command.clone();
+    let unescape = |regex: &mut Regex| {
+        regex.pattern.text = unescape_program_regex(&regex.pattern.text);
+    };
+    if let Some(address) = &mut command.address {
+        for part in std::iter::once(&mut address.first).chain(address.second.as_mut()) {
+            if let AddressPart::Regex(regex) = part {
+                unescape(regex);
+            }
+        }
+    }
+    if let Argument::Substitute { regex, .. } = &mut command.argument {
+        unescape(regex);
+    }
+    command
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    index: usize,
+    extended: bool,
+    groups: usize,
+}
+impl RegexParser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    /// Parse alternatives up to the end of regex, or up to the closing parenthesis if `in_group`.
+    fn parse_alternatives(&mut self, in_group: bool) -> Vec<Vec<Element>> {
+        let mut alternatives: Vec<Vec<Element>> = vec![Vec::new()];
+        while self.index < self.chars.len() {
+            if in_group && self.at_group_end() {
+                break;
+            }
+            if self.at_alternation() {
+                self.index += if self.extended { 1 } else { 2 };
+                alternatives.push(Vec::new());
+                continue;
+            }
+            // UNWRAP: There is always at least one alternative
+            let elements = alternatives.last_mut().unwrap();
+            let start = self.index;
+            let at_start = elements.is_empty()
+                || (!self.extended
+                    && elements.len() == 1
+                    && matches!(elements[0].node, Node::Start));
+            let node = self.parse_node(at_start, in_group);
+            let quantifier = if matches!(node, Node::Start) && !self.extended {
+                None
+            } else {
+                self.parse_quantifier()
+            };
+            elements.push(Element {
+                node,
+                quantifier,
+                start,
+                end: self.index,
+            });
+        }
+        alternatives
+    }
+
+    fn at_group_end(&self) -> bool {
+        if self.extended {
+            self.peek(0) == Some(')')
+        } else {
+            self.peek(0) == Some('\\') && self.peek(1) == Some(')')
+        }
+    }
+
+    fn at_alternation(&self) -> bool {
+        if self.extended {
+            self.peek(0) == Some('|')
+        } else {
+            self.peek(0) == Some('\\') && self.peek(1) == Some('|')
+        }
+    }
+
+    /// Is the regex at its end, or at the end of group or alternative?
+    fn at_end(&self, in_group: bool) -> bool {
+        self.index >= self.chars.len() || (in_group && self.at_group_end()) || self.at_alternation()
+    }
+
+    fn parse_node(&mut self, at_start: bool, in_group: bool) -> Node {
+        let c = self.chars[self.index];
+        self.index += 1;
+        match c {
+            '.' => Node::Any,
+            '[' => self.parse_bracket(),
+            // In BRE, anchors are special only at the start or end of regex
+            '^' if self.extended || at_start => Node::Start,
+            '$' if self.extended || self.at_end(in_group) => Node::End,
+            '*' if at_start => Node::Literal('*'),
+            '(' if self.extended => self.parse_group(),
+            '\\' => self.parse_escape(),
+            c => Node::Literal(c),
+        }
+    }
+
+    fn parse_group(&mut self) -> Node {
+        self.groups += 1;
+        let number = self.groups;
+        let alternatives = self.parse_alternatives(true);
+        // Skip the closing parenthesis, if there is any
+        if self.at_group_end() {
+            self.index += if self.extended { 1 } else { 2 };
+        }
+        Node::Group {
+            number,
+            alternatives,
+        }
+    }
+
+    fn parse_escape(&mut self) -> Node {
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return Node::Literal('\\'),
+        };
+        self.index += 1;
+        match c {
+            '(' if !self.extended => self.parse_group(),
+            '1'..='9' => Node::BackReference(c as usize - '0' as usize),
+            'w' | 'W' | 's' | 'S' | 'b' | 'B' | '<' | '>' | '`' | '\'' => Node::Escape(c),
+            'n' => Node::Literal('\n'),
+            't' => Node::Literal('\t'),
+            'f' => Node::Literal('\x0c'),
+            'v' => Node::Literal('\x0b'),
+            'a' => Node::Literal('\x07'),
+            'r' => Node::Literal('\r'),
+            'c' => match self.peek(0) {
+                Some(control) => {
+                    self.index += 1;
+                    Node::Literal(((control.to_ascii_uppercase() as u8) ^ 0x40) as char)
+                }
+                None => Node::Literal('c'),
+            },
+            'd' => self.parse_code(10, 3),
+            'o' => self.parse_code(8, 3),
+            'x' => self.parse_code(16, 2),
+            c => Node::Literal(c),
+        }
+    }
+
+    /// Parse character given by its code, such as `\x41`.
+    fn parse_code(&mut self, radix: u32, length: usize) -> Node {
+        let digits: String = self.chars[self.index..]
+            .iter()
+            .take(length)
+            .take_while(|c| c.is_digit(radix))
+            .collect();
+        self.index += digits.chars().count();
+        match u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) => Node::Literal(c),
+            None => Node::Literal(self.chars[self.index - digits.len() - 1]),
+        }
+    }
+
+    fn parse_bracket(&mut self) -> Node {
+        let negated = self.peek(0) == Some('^');
+        if negated {
+            self.index += 1;
+        }
+        let start = self.index;
+        // Closing bracket right after opening one is literal
+        if self.peek(0) == Some(']') {
+            self.index += 1;
+        }
+        while let Some(c) = self.peek(0) {
+            if c == ']' {
+                break;
+            }
+            // Classes such as [:alpha:] contain brackets of their own
+            if c == '[' && matches!(self.peek(1), Some(':') | Some('=') | Some('.')) {
+                // UNWRAP: Just matched it above
+                let kind = self.peek(1).unwrap();
+                self.index += 2;
+                while self.index < self.chars.len()
+                    && !(self.peek(0) == Some(kind) && self.peek(1) == Some(']'))
+                {
+                    self.index += 1;
+                }
+                self.index += 2;
+                continue;
+            }
+            self.index += 1;
+        }
+        let end = self.index.min(self.chars.len());
+        let items = self.chars[start..end].iter().collect();
+        // Skip the closing bracket
+        self.index = (self.index + 1).min(self.chars.len());
+        Node::Bracket { negated, items }
+    }
+
+    fn parse_quantifier(&mut self) -> Option<Quantifier> {
+        let (min, max) = match (self.peek(0), self.peek(1)) {
+            (Some('*'), _) => {
+                self.index += 1;
+                (0, None)
+            }
+            (Some('+'), _) if self.extended => {
+                self.index += 1;
+                (1, None)
+            }
+            (Some('?'), _) if self.extended => {
+                self.index += 1;
+                (0, Some(1))
+            }
+            (Some('\\'), Some('+')) if !self.extended => {
+                self.index += 2;
+                (1, None)
+            }
+            (Some('\\'), Some('?')) if !self.extended => {
+                self.index += 2;
+                (0, Some(1))
+            }
+            (Some('{'), _) if self.extended => {
+                self.index += 1;
+                self.parse_interval()?
+            }
+            (Some('\\'), Some('{')) if !self.extended => {
+                self.index += 2;
+                self.parse_interval()?
+            }
+            _ => return None,
+        };
+        Some(Quantifier { min, max })
+    }
+
+    /// Parse `m`, `m,`, `m,n` or `,n` of interval, and its closing brace.
+    fn parse_interval(&mut self) -> Option<(usize, Option<usize>)> {
+        let number = |parser: &mut RegexParser| -> Option<usize> {
+            let digits: String = parser.chars[parser.index..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            parser.index += digits.len();
+            digits.parse().ok()
+        };
+        let min = number(self);
+        let max = if self.peek(0) == Some(',') {
+            self.index += 1;
+            number(self)
+        } else {
+            Some(min?)
+        };
+        let closing = if self.extended { 1 } else { 2 };
+        self.index = (self.index + closing).min(self.chars.len());
+        Some((min.unwrap_or(0), max))
+    }
+}
+
+fn describe_alternatives(alternatives: &[Vec<Element>]) -> String {
+    if alternatives.len() == 1 {
+        return describe_sequence(&alternatives[0]);
+    }
+    let described: Vec<String> = alternatives
+        .iter()
+        .map(|elements| describe_sequence(elements))
+        .collect();
+    format!("either {}", described.join(", or "))
+}
+
+/// Describe elements matched one after another. Runs of literal characters are joined
+/// into quoted text.
+fn describe_sequence(elements: &[Element]) -> String {
+    if elements.is_empty() {
+        return String::from("nothing");
+    }
+    let mut parts: Vec<String> = Vec::new();
+    let mut text = String::new();
+    for element in elements {
+        if let (Node::Literal(c), None) = (&element.node, &element.quantifier) {
+            text.push(*c);
+            continue;
+        }
+        if !text.is_empty() {
+            parts.push(quote(&text));
+            text.clear();
+        }
+        parts.push(describe_element(element));
+    }
+    if !text.is_empty() {
+        parts.push(quote(&text));
+    }
+    parts.join(", then ")
+}
+
+/// Describe element, together with its quantifier.
+pub fn describe_element(element: &Element) -> String {
+    let node = describe_node(&element.node);
+    match &element.quantifier {
+        Some(quantifier) => format!("{} {}", node, describe_quantifier(quantifier)),
+        None => node,
+    }
+}
+
+/// Describe a single node, without its quantifier.
+fn describe_node(node: &Node) -> String {
+    match node {
+        Node::Literal(c) => quote(&c.to_string()),
+        Node::Any => String::from("any character"),
+        Node::Bracket { negated, items } => match (class_name(items), negated) {
+            (Some(name), false) => format!("{} ({})", with_article(name), items),
+            (Some(name), true) => format!("a character that is not {} ({})", name, items),
+            (None, false) => format!("one of [{}]", items),
+            (None, true) => format!("any character except [{}]", items),
+        },
+        Node::Start => String::from("the start"),
+        Node::End => String::from("the end"),
+        Node::Escape(c) => String::from(match c {
+            'w' => "a word character",
+            'W' => "a non-word character",
+            's' => "a whitespace character",
+            'S' => "a non-whitespace character",
+            'b' => "a word boundary",
+            'B' => "a place that is not a word boundary",
+            '<' => "the start of a word",
+            '>' => "the end of a word",
+            '`' => "the start of pattern space",
+            _ => "the end of pattern space",
+        }),
+        Node::Group {
+            number,
+            alternatives,
+        } => format!("group {} ({})", number, describe_alternatives(alternatives)),
+        Node::BackReference(number) => format!("the same text as group {}", number),
+    }
+}
+
+/// Describe how many times is an element repeated.
+pub fn describe_quantifier(quantifier: &Quantifier) -> String {
+    match (quantifier.min, quantifier.max) {
+        (0, None) => String::from("repeated any number of times"),
+        (1, None) => String::from("repeated at least once"),
+        (0, Some(1)) => String::from("optionally"),
+        (min, None) => format!("repeated at least {} times", min),
+        (min, Some(max)) if min == max => format!("repeated exactly {} times", min),
+        (min, Some(max)) => format!("repeated {} to {} times", min, max),
+    }
+}
+
+/// Name of bracket expression that is just one character class, such as `[:digit:]`.
+fn class_name(items: &str) -> Option<&'static str> {
+    Some(match items {
+        "[:alpha:]" | "a-zA-Z" | "A-Za-z" => "letter",
+        "[:digit:]" | "0-9" => "digit",
+        "[:alnum:]" | "a-zA-Z0-9" | "A-Za-z0-9" => "letter or digit",
+        "[:upper:]" | "A-Z" => "uppercase letter",
+        "[:lower:]" | "a-z" => "lowercase letter",
+        "[:space:]" => "whitespace character",
+        "[:blank:]" => "space or tab",
+        "[:punct:]" => "punctuation character",
+        "[:xdigit:]" => "hexadecimal digit",
+        "[:cntrl:]" => "control character",
+        "[:print:]" => "printable character",
+        "[:graph:]" => "visible character",
+        _ => return None,
+    })
+}
+
+fn with_article(name: &str) -> String {
+    match name.chars().next() {
+        Some('a' | 'e' | 'i' | 'o' | 'u') => format!("an {}", name),
+        _ => format!("a {}", name),
+    }
+}
+
+/// Quote text, showing invisible characters as escapes.
+pub fn quote(text: &str) -> String {
+    let escaped: String = text
+        .chars()
+        .map(|c| match c {
+            '\n' => String::from("\\n"),
+            '\t' => String::from("\\t"),
+            c if c.is_control() => format!("\\x{:02x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect();
+    format!("\"{}\"", escaped)
+}
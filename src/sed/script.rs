@@ -0,0 +1,867 @@
+use std::fmt;
+
+/// Position in sed script. Both line and column are 0-based, column is counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Piece of sed script, as it's written in the script (escapes are kept).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub start: Position,
+    /// Position right after the token
+    pub end: Position,
+}
+
+/// Sed script parsed into commands, in order of appearance.
+///
+/// This follows GNU sed syntax, GNU extensions included.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    pub commands: Vec<Command>,
+    /// Comments, including the leading `#`
+    pub comments: Vec<Token>,
+}
+
+/// One sed command, together with its address.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub address: Option<Address>,
+    /// Command letter, such as `s` or `b`. Blocks are `{` and `}`, labels are `:`.
+    pub name: char,
+    pub argument: Argument,
+    /// Where the command starts, including its address
+    pub start: Position,
+    /// Where the command letter is
+    pub name_position: Position,
+    /// Position right after the command, including its argument
+    pub end: Position,
+}
+
+/// Everything after command letter.
+#[derive(Debug, Clone)]
+pub enum Argument {
+    None,
+    /// Label of `:`, `b`, `t` and `T`. Branches without label jump to end of script.
+    Label(Option<Token>),
+    /// `s/regex/replacement/flags`
+    Substitute {
+        regex: Regex,
+        replacement: Token,
+        /// Flags, without file name of `w` flag
+        flags: Token,
+        /// File name of `w` flag
+        file: Option<Token>,
+    },
+    /// `y/source/target/`
+    Transliterate {
+        source: Token,
+        target: Token,
+    },
+    /// Text of `a`, `i` and `c`. If `one_liner` is true, the text was on the same line
+    /// as the command, which is GNU extension.
+    Text {
+        text: Token,
+        one_liner: bool,
+    },
+    /// File name of `r`, `R`, `w` and `W`, exit code of `q` and `Q`, line length of `l` and `L`,
+    /// shell command of `e` or version of `v`
+    Word(Option<Token>),
+}
+
+/// Regex, either in address or in `s` command.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    /// Regex between delimiters, as written. Empty regex means the last regex used.
+    pub pattern: Token,
+    pub delimiter: char,
+    /// Flags of address regex (`I` and `M`). Regexes of `s` have flags in the command.
+    pub flags: String,
+}
+
+/// Address selecting lines a command applies to.
+#[derive(Debug, Clone)]
+pub struct Address {
+    pub first: AddressPart,
+    /// End of range, if the address is a range
+    pub second: Option<AddressPart>,
+    /// Address followed by `!` applies to lines that do not match
+    pub negated: bool,
+    pub start: Position,
+    /// Position right after the address, including `!`
+    pub end: Position,
+}
+
+#[derive(Debug, Clone)]
+pub enum AddressPart {
+    /// Line number. 0 is valid only as `0,/regex/`.
+    Line(usize),
+    /// `$`
+    Last,
+    /// `first~step`
+    Step {
+        first: usize,
+        step: usize,
+    },
+    Regex(Regex),
+    /// `+N`, only as end of range
+    Relative(usize),
+    /// `~N`, only as end of range
+    Multiple(usize),
+}
+
+/// Syntax error in sed script.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub position: Position,
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.position.line + 1,
+            self.position.column + 1,
+            self.message
+        )
+    }
+}
+
+impl Script {
+    /// Parse sed script. Parsing stops at the first error, just like sed does.
+    pub fn parse(source: &str) -> Result<Script, ParseError> {
+        Parser::new(source).parse()
+    }
+
+    /// Labels defined in the script, with index of the command that defines them.
+    pub fn labels(&self) -> Vec<(&str, usize)> {
+        self.commands
+            .iter()
+            .enumerate()
+            .filter(|(_, command)| command.name == ':')
+            .filter_map(|(i, command)| command.label().map(|label| (label, i)))
+            .collect()
+    }
+
+    /// Find line of the program, as sed prints it with `--debug`, for each command.
+    ///
+    /// Sed prints one command per line, but text of `a`, `i` and `c` or newlines
+    /// in `s` spread over more lines. So each line of the program is parsed alone,
+    /// and lines with the same command letter are paired with commands in order.
+    pub fn program_lines(&self, program_source: &[String]) -> Vec<Option<usize>> {
+        let program_names: Vec<Option<char>> = Script::parse_program(program_source)
+            .iter()
+            .map(|line| {
+                line.as_ref()
+                    .and_then(|script| script.commands.first().map(|command| command.name))
+            })
+            .collect();
+        let mut next_line = 0;
+        self.commands
+            .iter()
+            .map(|command| {
+                let line = (next_line..program_names.len())
+                    .find(|line| program_names[*line] == Some(command.name))?;
+                next_line = line + 1;
+                Some(line)
+            })
+            .collect()
+    }
+
+    /// Parse each line of the program, as sed prints it with `--debug`. Lines that are not
+    /// commands on their own are None.
+    ///
+    /// Sed prints text of `a`, `i` and `c` on the line of the command and the lines after it,
+    /// up to an empty line, so those lines are skipped even if they look like commands.
+    pub fn parse_program(program_source: &[String]) -> Vec<Option<Script>> {
+        let mut in_text = false;
+        program_source
+            .iter()
+            .map(|line| {
+                if in_text {
+                    in_text = !line.is_empty();
+                    return None;
+                }
+                let script = Script::parse_program_line(line)?;
+                in_text = script
+                    .commands
+                    .first()
+                    .map(|command| command.name)
+                    .filter(|name| matches!(name, 'a' | 'i' | 'c'))
+                    .is_some();
+                Some(script)
+            })
+            .collect()
+    }
+
+    /// Find `{...}` blocks of the program parsed by [Script::parse_program], as lines (0-based)
+    /// of the opening and the closing brace, ordered by the opening one.
+    pub fn program_blocks(program: &[Option<Script>]) -> Vec<(usize, usize)> {
+        let mut open: Vec<usize> = Vec::new();
+        let mut blocks = Vec::new();
+        for (line, script) in program.iter().enumerate() {
+            let name = script
+                .as_ref()
+                .and_then(|script| script.commands.first())
+                .map(|command| command.name);
+            match name {
+                Some('{') => open.push(line),
+                Some('}') => {
+                    // Sed refuses to run scripts with unbalanced braces
+                    if let Some(start) = open.pop() {
+                        blocks.push((start, line));
+                    }
+                }
+                _ => {}
+            }
+        }
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Parse one line of the program, as sed prints it with `--debug`. Returns None if the line
+    /// is not a command on its own, such as continuation of text of `a`.
+    fn parse_program_line(line: &str) -> Option<Script> {
+        // Braces of a block are on separate lines, so they can't be parsed alone
+        if line.trim() == "}" {
+            let start = Position {
+                line: 0,
+                column: line.chars().take_while(|c| c.is_whitespace()).count(),
+            };
+            let end = Position {
+                column: start.column + 1,
+                ..start
+            };
+            return Some(Script {
+                commands: vec![Command {
+                    address: None,
+                    name: '}',
+                    argument: Argument::None,
+                    start,
+                    name_position: start,
+                    end,
+                }],
+                comments: Vec::new(),
+            });
+        }
+        if line.trim_end().ends_with('{') {
+            let mut script = Script::parse(&format!("{}}}", line)).ok()?;
+            script.commands.pop();
+            return Some(script);
+        }
+        Script::parse(line).ok()
+    }
+}
+
+impl Command {
+    /// Label the command defines or branches to, if any.
+    pub fn label(&self) -> Option<&str> {
+        match &self.argument {
+            Argument::Label(Some(label)) => Some(&label.text),
+            _ => None,
+        }
+    }
+
+    /// Regexes of the command, those of address first, then the one of `s`.
+    pub fn regexes(&self) -> Vec<&Regex> {
+        let mut regexes = Vec::new();
+        if let Some(address) = &self.address {
+            for part in std::iter::once(&address.first).chain(address.second.as_ref()) {
+                if let AddressPart::Regex(regex) = part {
+                    regexes.push(regex);
+                }
+            }
+        }
+        if let Argument::Substitute { regex, .. } = &self.argument {
+            regexes.push(regex);
+        }
+        regexes
+    }
+
+    /// Is this `b`, `t` or `T`?
+    pub fn is_branch(&self) -> bool {
+        matches!(self.name, 'b' | 't' | 'T')
+    }
+}
+
+/// Commands that take no argument at all.
+const SIMPLE_COMMANDS: &str = "=dDgGhHnNpPxzF";
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    position: Position,
+    /// Position of the last character read
+    last: Position,
+}
+impl Parser {
+    fn new(source: &str) -> Self {
+        Parser {
+            chars: source.chars().collect(),
+            index: 0,
+            position: Position::default(),
+            last: Position::default(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        self.last = self.position;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 0;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: message.into(),
+            position: self.position,
+        })
+    }
+
+    /// Report error at the last character read, such as newline that ended command too soon.
+    fn unterminated<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: String::from(message),
+            position: self.last,
+        })
+    }
+
+    /// Skip spaces and tabs, but not newlines.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.next();
+        }
+    }
+
+    /// Read characters while the condition holds.
+    fn read_while(&mut self, condition: impl Fn(char) -> bool) -> Token {
+        let start = self.position;
+        let mut text = String::new();
+        while let Some(c) = self.peek().filter(|c| condition(*c)) {
+            text.push(c);
+            self.next();
+        }
+        Token {
+            text,
+            start,
+            end: self.position,
+        }
+    }
+
+    fn read_number(&mut self) -> Option<usize> {
+        let digits = self.read_while(|c| c.is_ascii_digit());
+        digits.text.parse().ok()
+    }
+
+    fn parse(mut self) -> Result<Script, ParseError> {
+        let mut script = Script::default();
+        let mut depth = 0;
+        loop {
+            while matches!(self.peek(), Some(c) if c.is_whitespace() || c == ';') {
+                self.next();
+            }
+            let start = self.position;
+            match self.peek() {
+                None => break,
+                Some('#') => {
+                    script.comments.push(self.read_while(|c| c != '\n'));
+                    continue;
+                }
+                _ => {}
+            }
+            let address = self.parse_address()?;
+            self.skip_spaces();
+            let name_position = self.position;
+            let name = match self.next() {
+                Some(name) => name,
+                None => return self.error("missing command"),
+            };
+            if address.is_some() && matches!(name, '}' | ':') {
+                return Err(ParseError {
+                    message: format!("`{}` doesn't want any addresses", name),
+                    position: name_position,
+                });
+            }
+            match name {
+                '{' => depth += 1,
+                '}' => {
+                    if depth == 0 {
+                        return Err(ParseError {
+                            message: String::from("unexpected `}`"),
+                            position: name_position,
+                        });
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+            let argument = match name {
+                '{' | '}' => Argument::None,
+                c if SIMPLE_COMMANDS.contains(c) => Argument::None,
+                ':' => {
+                    self.skip_spaces();
+                    let label = self.read_label(|c| c != ';' && c != '\n');
+                    if label.is_none() {
+                        return self.error("`:` lacks a label");
+                    }
+                    Argument::Label(label)
+                }
+                'b' | 't' | 'T' => {
+                    self.skip_spaces();
+                    Argument::Label(self.read_label(|c| c != ';' && c != '}' && !c.is_whitespace()))
+                }
+                's' => self.parse_substitute()?,
+                'y' => self.parse_transliterate()?,
+                'a' | 'i' | 'c' => self.parse_text()?,
+                'r' | 'R' | 'w' | 'W' => {
+                    self.skip_spaces();
+                    let file = self.read_while(|c| c != '\n');
+                    if file.text.is_empty() {
+                        return self.error("missing filename in r/R/w/W commands");
+                    }
+                    Argument::Word(Some(file))
+                }
+                'l' | 'L' | 'q' | 'Q' => {
+                    self.skip_spaces();
+                    let number = self.read_while(|c| c.is_ascii_digit());
+                    Argument::Word(Some(number).filter(|n| !n.text.is_empty()))
+                }
+                'e' => {
+                    self.skip_spaces();
+                    let command = self.read_while(|c| c != '\n');
+                    Argument::Word(Some(command).filter(|c| !c.text.is_empty()))
+                }
+                'v' => {
+                    self.skip_spaces();
+                    let version = self.read_while(|c| c != ';' && c != '\n' && c != '}');
+                    Argument::Word(Some(version).filter(|v| !v.text.trim().is_empty()))
+                }
+                c => {
+                    return Err(ParseError {
+                        message: format!("unknown command: `{}`", c),
+                        position: name_position,
+                    })
+                }
+            };
+            let end = self.position;
+            // Only separators may follow a command
+            self.skip_spaces();
+            if !matches!(
+                self.peek(),
+                None | Some(';') | Some('\n') | Some('}') | Some('#')
+            ) && name != '{'
+            {
+                return self.error("extra characters after command");
+            }
+            script.commands.push(Command {
+                address,
+                name,
+                argument,
+                start,
+                name_position,
+                end,
+            });
+        }
+        if depth > 0 {
+            return self.error("unmatched `{`");
+        }
+        Ok(script)
+    }
+
+    /// Read label, trimming trailing whitespace. Returns None if it's empty.
+    fn read_label(&mut self, condition: impl Fn(char) -> bool) -> Option<Token> {
+        let mut label = self.read_while(condition);
+        let trimmed = label.text.trim_end();
+        if trimmed.is_empty() {
+            return None;
+        }
+        label.end.column -= label.text.chars().count() - trimmed.chars().count();
+        label.text.truncate(trimmed.len());
+        Some(label)
+    }
+
+    fn parse_address(&mut self) -> Result<Option<Address>, ParseError> {
+        let start = self.position;
+        let first = match self.parse_address_part()? {
+            Some(first) => first,
+            None => {
+                if self.peek() == Some(',') {
+                    return self.error("unexpected `,`");
+                }
+                return Ok(None);
+            }
+        };
+        self.skip_spaces();
+        let second = if self.peek() == Some(',') {
+            self.next();
+            self.skip_spaces();
+            let second = match self.peek() {
+                Some('+') => {
+                    self.next();
+                    self.read_number().map(AddressPart::Relative)
+                }
+                Some('~') => {
+                    self.next();
+                    self.read_number().map(AddressPart::Multiple)
+                }
+                _ => self.parse_address_part()?,
+            };
+            match second {
+                Some(second) => Some(second),
+                None => return self.error("unexpected `,`"),
+            }
+        } else {
+            None
+        };
+        self.skip_spaces();
+        let mut negated = false;
+        while self.peek() == Some('!') {
+            if negated {
+                return self.error("multiple `!`s");
+            }
+            negated = true;
+            self.next();
+            self.skip_spaces();
+        }
+        Ok(Some(Address {
+            first,
+            second,
+            negated,
+            start,
+            end: self.position,
+        }))
+    }
+
+    fn parse_address_part(&mut self) -> Result<Option<AddressPart>, ParseError> {
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() => {
+                // UNWRAP: There is at least one digit
+                let first = self.read_number().unwrap_or(0);
+                if self.peek() == Some('~') {
+                    self.next();
+                    let step = self.read_number().unwrap_or(0);
+                    Ok(Some(AddressPart::Step { first, step }))
+                } else {
+                    Ok(Some(AddressPart::Line(first)))
+                }
+            }
+            Some('$') => {
+                self.next();
+                Ok(Some(AddressPart::Last))
+            }
+            Some('/') | Some('\\') => {
+                if self.next() == Some('\\') {
+                    match self.next() {
+                        Some(c) if c != '\n' && c != '\\' => self.parse_address_regex(c).map(Some),
+                        _ => self.error("expected regex delimiter after `\\`"),
+                    }
+                } else {
+                    self.parse_address_regex('/').map(Some)
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_address_regex(&mut self, delimiter: char) -> Result<AddressPart, ParseError> {
+        let pattern = self.read_delimited(delimiter, true, "unterminated address regex")?;
+        let flags = self.read_while(|c| c == 'I' || c == 'M');
+        Ok(AddressPart::Regex(Regex {
+            pattern,
+            delimiter,
+            flags: flags.text,
+        }))
+    }
+
+    /// Read text up to the delimiter and skip the delimiter. Escaped characters never end the text.
+    /// In regexes, delimiter inside bracket expression doesn't end it either.
+    fn read_delimited(
+        &mut self,
+        delimiter: char,
+        is_regex: bool,
+        error: &str,
+    ) -> Result<Token, ParseError> {
+        let start = self.position;
+        let mut text = String::new();
+        loop {
+            let end = self.position;
+            match self.next() {
+                None | Some('\n') => return self.unterminated(error),
+                Some(c) if c == delimiter => {
+                    return Ok(Token { text, start, end });
+                }
+                Some('\\') => {
+                    text.push('\\');
+                    match self.next() {
+                        Some(c) => text.push(c),
+                        None => return self.unterminated(error),
+                    }
+                }
+                Some('[') if is_regex => {
+                    text.push('[');
+                    self.read_bracket_expression(&mut text, error)?;
+                }
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    /// Read rest of bracket expression, after the opening `[`.
+    fn read_bracket_expression(
+        &mut self,
+        text: &mut String,
+        error: &str,
+    ) -> Result<(), ParseError> {
+        // `]` right after `[` or `[^` is a literal
+        if self.peek() == Some('^') {
+            text.push('^');
+            self.next();
+        }
+        if self.peek() == Some(']') {
+            text.push(']');
+            self.next();
+        }
+        loop {
+            match self.next() {
+                None | Some('\n') => return self.unterminated(error),
+                Some(']') => {
+                    text.push(']');
+                    return Ok(());
+                }
+                // Character classes, such as [:alpha:], might contain `]`
+                Some('[') if matches!(self.peek(), Some(':') | Some('.') | Some('=')) => {
+                    // UNWRAP: We have just peeked at it
+                    let kind = self.next().unwrap();
+                    text.push('[');
+                    text.push(kind);
+                    loop {
+                        match self.next() {
+                            None | Some('\n') => return self.unterminated(error),
+                            Some(c) => {
+                                text.push(c);
+                                if c == kind && self.peek() == Some(']') {
+                                    text.push(']');
+                                    self.next();
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn parse_substitute(&mut self) -> Result<Argument, ParseError> {
+        let error = "unterminated `s` command";
+        let delimiter = match self.next() {
+            Some(c) if c != '\n' && c != '\\' => c,
+            _ => return self.unterminated(error),
+        };
+        let pattern = self.read_delimited(delimiter, true, error)?;
+        let replacement = self.read_delimited_multiline(delimiter, error)?;
+        let flags = self.read_while(|c| "gpiImMe".contains(c) || c.is_ascii_digit());
+        let file = if self.peek() == Some('w') {
+            self.next();
+            self.skip_spaces();
+            let file = self.read_while(|c| c != '\n');
+            if file.text.is_empty() {
+                return self.error("missing filename in r/R/w/W commands");
+            }
+            Some(file)
+        } else {
+            None
+        };
+        Ok(Argument::Substitute {
+            regex: Regex {
+                pattern,
+                delimiter,
+                flags: String::new(),
+            },
+            replacement,
+            flags,
+            file,
+        })
+    }
+
+    /// Like [Parser::read_delimited], but escaped newlines are allowed.
+    fn read_delimited_multiline(
+        &mut self,
+        delimiter: char,
+        error: &str,
+    ) -> Result<Token, ParseError> {
+        let start = self.position;
+        let mut text = String::new();
+        loop {
+            let end = self.position;
+            match self.next() {
+                None | Some('\n') => return self.unterminated(error),
+                Some(c) if c == delimiter => return Ok(Token { text, start, end }),
+                Some('\\') => {
+                    text.push('\\');
+                    match self.next() {
+                        Some(c) => text.push(c),
+                        None => return self.unterminated(error),
+                    }
+                }
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn parse_transliterate(&mut self) -> Result<Argument, ParseError> {
+        let error = "unterminated `y` command";
+        let delimiter = match self.next() {
+            Some(c) if c != '\n' && c != '\\' => c,
+            _ => return self.unterminated(error),
+        };
+        let source = self.read_delimited_multiline(delimiter, error)?;
+        let target = self.read_delimited_multiline(delimiter, error)?;
+        Ok(Argument::Transliterate { source, target })
+    }
+
+    /// Parse text of `a`, `i` or `c`, either `a\` followed by lines of text,
+    /// or GNU one-liner `a text`. Lines ending with `\` continue on the next line.
+    fn parse_text(&mut self) -> Result<Argument, ParseError> {
+        self.skip_spaces();
+        let mut one_liner = true;
+        if self.peek() == Some('\\') {
+            self.next();
+            self.skip_spaces();
+            if self.peek() == Some('\n') {
+                self.next();
+                one_liner = false;
+            }
+        }
+        let start = self.position;
+        let mut text = String::new();
+        let mut end = self.position;
+        loop {
+            match self.peek() {
+                None | Some('\n') => break,
+                Some('\\') => {
+                    self.next();
+                    match self.next() {
+                        Some(c) => {
+                            text.push('\\');
+                            text.push(c);
+                        }
+                        None => break,
+                    }
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.next();
+                }
+            }
+            end = self.position;
+        }
+        if text.is_empty() && one_liner {
+            return self.error("expected `\\` after `a`, `c` or `i`");
+        }
+        Ok(Argument::Text {
+            text: Token { text, start, end },
+            one_liner,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(script: &Script) -> String {
+        script.commands.iter().map(|command| command.name).collect()
+    }
+
+    #[test]
+    fn substitute_with_custom_delimiter() {
+        let script = Script::parse("s|a/b|c\\|d|g;s,x,y,").unwrap();
+        assert_eq!(names(&script), "ss");
+        match &script.commands[0].argument {
+            Argument::Substitute {
+                regex,
+                replacement,
+                flags,
+                file,
+            } => {
+                assert_eq!(regex.delimiter, '|');
+                assert_eq!(regex.pattern.text, "a/b");
+                assert_eq!(replacement.text, "c\\|d");
+                assert_eq!(flags.text, "g");
+                assert!(file.is_none());
+            }
+            argument => panic!("unexpected argument {:?}", argument),
+        }
+        assert_eq!(
+            script.commands[1].start,
+            Position {
+                line: 0,
+                column: 13
+            }
+        );
+    }
+
+    #[test]
+    fn text_continues_over_escaped_newlines() {
+        let script = Script::parse("1a\\\nfirst\\\nsecond\ni\\\n  third\n$c last\np").unwrap();
+        assert_eq!(names(&script), "aicp");
+        let texts: Vec<(String, bool)> = script
+            .commands
+            .iter()
+            .filter_map(|command| match &command.argument {
+                Argument::Text { text, one_liner } => Some((text.text.clone(), *one_liner)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                (String::from("first\\\nsecond"), false),
+                (String::from("  third"), false),
+                (String::from("last"), true),
+            ]
+        );
+        assert_eq!(script.commands[3].start, Position { line: 6, column: 0 });
+    }
+
+    #[test]
+    fn braces_must_be_balanced() {
+        assert_eq!(Script::parse("/x/{p").unwrap_err().message, "unmatched `{`");
+        let error = Script::parse("p\n}").unwrap_err();
+        assert_eq!(error.message, "unexpected `}`");
+        assert_eq!(error.position, Position { line: 1, column: 0 });
+    }
+
+    #[test]
+    fn addresses_and_labels() {
+        let script = Script::parse(":a\n$!N\n/x/I,+2!ba\n0~3{s/^//}").unwrap();
+        assert_eq!(names(&script), ":Nb{s}");
+        assert_eq!(script.labels(), [("a", 0)]);
+        let address = script.commands[2].address.as_ref().unwrap();
+        assert!(address.negated);
+        assert!(matches!(&address.first, AddressPart::Regex(regex) if regex.flags == "I"));
+        assert!(matches!(address.second, Some(AddressPart::Relative(2))));
+        assert_eq!(script.commands[2].label(), Some("a"));
+        let address = script.commands[3].address.as_ref().unwrap();
+        assert!(matches!(
+            address.first,
+            AddressPart::Step { first: 0, step: 3 }
+        ));
+    }
+}
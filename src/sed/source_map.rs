@@ -0,0 +1,61 @@
+use crate::sed::highlight::{highlight, Highlight};
+use crate::sed::script::{Position, Script};
+
+/// Where are commands of the program, as sed prints it, in the original script file.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    /// Lines of the original script
+    pub lines: Vec<String>,
+    /// Syntax of the original script
+    pub highlights: Vec<Highlight>,
+    /// Start and end of the command on each line (0-based) of the program, if it's a command
+    locations: Vec<Option<(Position, Position)>>,
+}
+impl SourceMap {
+    pub fn new(source: &str, script: &Script, program_source: &[String]) -> Self {
+        let mut locations = vec![None; program_source.len()];
+        for (command, line) in script
+            .commands
+            .iter()
+            .zip(script.program_lines(program_source))
+        {
+            if let Some(line) = line {
+                locations[line] = Some((command.start, command.end));
+            }
+        }
+        SourceMap {
+            lines: source.lines().map(String::from).collect(),
+            highlights: highlight(script),
+            locations,
+        }
+    }
+
+    /// Where does the command on given line of the program start and end in the original script.
+    pub fn location(&self, program_line: usize) -> Option<(Position, Position)> {
+        self.locations.get(program_line).copied().flatten()
+    }
+
+    /// Original script with label defined right before the command on given line of the program,
+    /// so execution can jump right into the middle of the script.
+    pub fn with_label(&self, program_line: usize, label: &str) -> Option<String> {
+        let (start, _) = self.location(program_line)?;
+        let mut lines = self.lines.clone();
+        let line = lines.get_mut(start.line)?;
+        let offset = line
+            .char_indices()
+            .nth(start.column)
+            .map_or(line.len(), |(offset, _)| offset);
+        line.insert_str(offset, &format!(":{}\n", label));
+        Some(lines.join("\n"))
+    }
+
+    /// Lines of the program with commands starting on given line (0-based) of the original script.
+    pub fn program_lines_on(&self, line: usize) -> Vec<usize> {
+        self.locations
+            .iter()
+            .enumerate()
+            .filter(|(_, location)| matches!(location, Some((start, _)) if start.line == line))
+            .map(|(program_line, _)| program_line)
+            .collect()
+    }
+}
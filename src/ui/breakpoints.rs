@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+/// One breakpoint placed on a line of sed source code.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    /// Line (0-based) the breakpoint is placed on.
+    pub line: usize,
+    /// Disabled breakpoints are remembered, but execution never stops on them.
+    pub enabled: bool,
+    /// Hits up to this one, counted from the start of execution, don't stop execution.
+    pub ignore_until: usize,
+}
+impl Breakpoint {
+    pub fn new(line: usize) -> Self {
+        Breakpoint {
+            line,
+            enabled: true,
+            ignore_until: 0,
+        }
+    }
+}
+
+/// Collection of all breakpoints user has placed, ordered by line.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    items: BTreeMap<usize, Breakpoint>,
+}
+impl Breakpoints {
+    /// Place breakpoint on given line, or remove it if there already is one.
+    pub fn toggle(&mut self, line: usize) {
+        if self.items.remove(&line).is_none() {
+            self.items.insert(line, Breakpoint::new(line));
+        }
+    }
+
+    /// Place given breakpoint, replacing any breakpoint on the same line.
+    pub fn insert(&mut self, breakpoint: Breakpoint) {
+        self.items.insert(breakpoint.line, breakpoint);
+    }
+
+    /// Move all breakpoints to new lines, for example after the source code was changed.
+    /// Breakpoints whose line is gone (`new_line` returns None) are removed.
+    pub fn relocate(&mut self, new_line: impl Fn(usize) -> Option<usize>) {
+        let items = std::mem::take(&mut self.items);
+        for (_, mut bp) in items {
+            if let Some(line) = new_line(bp.line) {
+                bp.line = line;
+                self.insert(bp);
+            }
+        }
+    }
+
+    /// Remove breakpoint from given line, if there is any.
+    pub fn remove(&mut self, line: usize) {
+        self.items.remove(&line);
+    }
+
+    /// Is there a breakpoint (enabled or not) on given line?
+    pub fn contains(&self, line: usize) -> bool {
+        self.items.contains_key(&line)
+    }
+
+    /// Is there an enabled breakpoint on given line?
+    pub fn is_enabled(&self, line: usize) -> bool {
+        self.items.get(&line).map(|bp| bp.enabled).unwrap_or(false)
+    }
+
+    /// Should execution stop when it reaches given line for given time (1-based)?
+    ///
+    /// Disabled breakpoints and ignored hits don't stop it.
+    pub fn stops(&self, line: usize, hit: usize) -> bool {
+        self.items
+            .get(&line)
+            .map(|bp| bp.enabled && hit > bp.ignore_until)
+            .unwrap_or(false)
+    }
+
+    /// Get n-th breakpoint, ordered by line.
+    pub fn nth(&self, index: usize) -> Option<&Breakpoint> {
+        self.items.values().nth(index)
+    }
+
+    /// Get n-th breakpoint mutably, ordered by line.
+    pub fn nth_mut(&mut self, index: usize) -> Option<&mut Breakpoint> {
+        self.items.values_mut().nth(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.items.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
@@ -0,0 +1,251 @@
+use crate::sed::highlight::{highlight, Highlight};
+use crate::sed::lint::{lint, Diagnostic};
+use crate::sed::script::Position;
+
+/// Sed script being edited inside the debugger.
+///
+/// The script is linted after each change, so problems show up while typing.
+pub struct Editor {
+    /// Lines of the script, without line breaks
+    pub lines: Vec<String>,
+    /// Position of the text cursor. Column counts characters, not bytes.
+    pub cursor: Position,
+    /// True if the script differs from the file it was loaded from
+    pub modified: bool,
+    /// Problems linter found in the script as it is now
+    pub diagnostics: Vec<Diagnostic>,
+    /// Syntax of the script, if it can be parsed
+    pub highlights: Vec<Highlight>,
+    /// Why sed refused the script when it was saved last time, with line (0-based)
+    /// sed complained about, if it said which. Cleared by any change.
+    pub error: Option<(Option<usize>, String)>,
+    /// Esc was pressed with unsaved changes, pressing it again right away discards them
+    pub confirm_discard: bool,
+    /// Report GNU extensions, as sed runs with `--posix`
+    posix: bool,
+}
+impl Editor {
+    /// Start editing given script with cursor at the start of given line (0-based).
+    pub fn new(source: &str, line: usize, posix: bool) -> Self {
+        let mut lines: Vec<String> = source.lines().map(String::from).collect();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let mut editor = Editor {
+            cursor: Position {
+                line: line.min(lines.len() - 1),
+                column: 0,
+            },
+            lines,
+            modified: false,
+            diagnostics: Vec::new(),
+            highlights: Vec::new(),
+            error: None,
+            confirm_discard: false,
+            posix,
+        };
+        editor.check();
+        editor
+    }
+
+    /// The whole script, as it should be saved.
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    /// Insert character at cursor and move past it.
+    pub fn insert(&mut self, c: char) {
+        let offset = self.byte_offset();
+        self.lines[self.cursor.line].insert(offset, c);
+        self.cursor.column += 1;
+        self.changed();
+    }
+
+    /// Split line at cursor, moving cursor to the start of the new line.
+    pub fn insert_line_break(&mut self) {
+        let offset = self.byte_offset();
+        let rest = self.lines[self.cursor.line].split_off(offset);
+        self.lines.insert(self.cursor.line + 1, rest);
+        self.cursor = Position {
+            line: self.cursor.line + 1,
+            column: 0,
+        };
+        self.changed();
+    }
+
+    /// Delete character before cursor. At the start of a line, join it with the previous one.
+    pub fn delete_backward(&mut self) {
+        if self.cursor.column > 0 {
+            self.cursor.column -= 1;
+            let offset = self.byte_offset();
+            self.lines[self.cursor.line].remove(offset);
+        } else if self.cursor.line > 0 {
+            let line = self.lines.remove(self.cursor.line);
+            self.cursor.line -= 1;
+            self.cursor.column = self.line_length();
+            self.lines[self.cursor.line].push_str(&line);
+        } else {
+            return;
+        }
+        self.changed();
+    }
+
+    /// Delete character under cursor. At the end of a line, join the next line to it.
+    pub fn delete_forward(&mut self) {
+        if self.cursor.column < self.line_length() {
+            let offset = self.byte_offset();
+            self.lines[self.cursor.line].remove(offset);
+        } else if self.cursor.line + 1 < self.lines.len() {
+            let line = self.lines.remove(self.cursor.line + 1);
+            self.lines[self.cursor.line].push_str(&line);
+        } else {
+            return;
+        }
+        self.changed();
+    }
+
+    /// Delete the whole line under cursor. The script always keeps at least one line.
+    pub fn delete_line(&mut self) {
+        if self.lines.len() == 1 {
+            self.lines[0].clear();
+        } else {
+            self.lines.remove(self.cursor.line);
+            self.cursor.line = self.cursor.line.min(self.lines.len() - 1);
+        }
+        self.cursor.column = self.cursor.column.min(self.line_length());
+        self.changed();
+    }
+
+    /// Move cursor by given number of lines, keeping the column if the line is long enough.
+    pub fn move_vertically(&mut self, offset: isize) {
+        let line = self.cursor.line as isize + offset;
+        self.cursor.line = line.clamp(0, self.lines.len() as isize - 1) as usize;
+        self.cursor.column = self.cursor.column.min(self.line_length());
+    }
+
+    /// Move cursor one character left, to the end of the previous line at the start of a line.
+    pub fn move_left(&mut self) {
+        if self.cursor.column > 0 {
+            self.cursor.column -= 1;
+        } else if self.cursor.line > 0 {
+            self.cursor.line -= 1;
+            self.cursor.column = self.line_length();
+        }
+    }
+
+    /// Move cursor one character right, to the start of the next line at the end of a line.
+    pub fn move_right(&mut self) {
+        if self.cursor.column < self.line_length() {
+            self.cursor.column += 1;
+        } else if self.cursor.line + 1 < self.lines.len() {
+            self.cursor.line += 1;
+            self.cursor.column = 0;
+        }
+    }
+
+    /// Move cursor to the start of its line.
+    pub fn move_home(&mut self) {
+        self.cursor.column = 0;
+    }
+
+    /// Move cursor to the end of its line.
+    pub fn move_end(&mut self) {
+        self.cursor.column = self.line_length();
+    }
+
+    /// Number of characters on the line with cursor.
+    fn line_length(&self) -> usize {
+        self.lines[self.cursor.line].chars().count()
+    }
+
+    /// Byte offset of cursor in its line.
+    fn byte_offset(&self) -> usize {
+        let line = &self.lines[self.cursor.line];
+        line.char_indices()
+            .nth(self.cursor.column)
+            .map(|(offset, _)| offset)
+            .unwrap_or(line.len())
+    }
+
+    /// Remember the script was changed and lint it again.
+    fn changed(&mut self) {
+        self.modified = true;
+        self.error = None;
+        self.check();
+    }
+
+    /// Lint the script and find its syntax, so it can be highlighted.
+    fn check(&mut self) {
+        let (script, diagnostics) = lint(&self.text(), self.posix);
+        self.highlights = script.as_ref().map(highlight).unwrap_or_default();
+        self.diagnostics = diagnostics;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let mut editor = Editor::new("s/ž/ř/\n", 0, false);
+        editor.cursor = at(0, 3);
+        assert_eq!(editor.byte_offset(), 4);
+        editor.insert('é');
+        editor.insert_line_break();
+        assert_eq!(editor.lines, ["s/žé", "/ř/"]);
+        editor.cursor = at(1, 2);
+        editor.delete_backward();
+        assert_eq!(editor.cursor, at(1, 1));
+        editor.delete_forward();
+        editor.insert('/');
+        assert_eq!(editor.lines, ["s/žé", "//"]);
+        editor.move_end();
+        assert_eq!(editor.byte_offset(), 2);
+        assert!(editor.modified);
+    }
+
+    #[test]
+    fn deleting_at_line_start_joins_lines() {
+        let mut editor = Editor::new("pž\nq\n", 1, false);
+        editor.delete_backward();
+        assert_eq!(editor.lines, ["pžq"]);
+        assert_eq!(editor.cursor, at(0, 2));
+        editor.move_home();
+        editor.delete_backward();
+        assert_eq!(editor.lines, ["pžq"]);
+        assert!(editor.modified);
+        assert_eq!(editor.text(), "pžq\n");
+    }
+
+    #[test]
+    fn deleting_last_line() {
+        let mut editor = Editor::new("p\nlong line\nq\n", 2, false);
+        editor.cursor.column = 1;
+        editor.delete_line();
+        assert_eq!(editor.lines, ["p", "long line"]);
+        assert_eq!(editor.cursor, at(1, 1));
+        editor.delete_line();
+        editor.delete_line();
+        assert_eq!(editor.lines, [""]);
+        assert_eq!(editor.cursor, at(0, 0));
+        assert_eq!(editor.text(), "\n");
+    }
+
+    #[test]
+    fn changes_are_linted() {
+        let mut editor = Editor::new("p\n", 0, false);
+        assert!(editor.diagnostics.is_empty());
+        editor.error = Some((Some(0), String::from("unknown command")));
+        editor.move_end();
+        editor.insert('x');
+        assert!(!editor.diagnostics.is_empty());
+        assert_eq!(editor.error, None);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::sed::debugger::Debugger;
+use crate::ui::session::Session;
 use anyhow::Result;
 
 /// This trait describes structure that takes care of
@@ -10,9 +12,19 @@ pub trait UiAgent {
 /// Used to indicate why did UiAgent stop
 pub enum ApplicationExitReason {
     /// User wants to exit the application
-    UserExit,
+    ///
+    /// Session: breakpoints and other settings that should be saved for next time
+    UserExit(Session),
     /// User wants to reload configuration.
     ///
-    /// usize: state ID that should be loaded again if possible
-    Reload(usize),
+    /// Session: breakpoints and other settings that should be kept after reload,
+    /// including the state that should be loaded again if possible
+    ///
+    /// Debugger: execution before the reload, so user can compare the two
+    Reload(Session, Debugger),
+    /// User wants to edit the script in $EDITOR, at given line (0-based) of the script.
+    /// The script is reloaded afterwards.
+    ///
+    /// Session and Debugger: the same as with reload
+    Edit(Session, usize, Debugger),
 }
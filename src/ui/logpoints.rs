@@ -0,0 +1,120 @@
+use crate::sed::debugger::{Debugger, DebuggingState};
+use std::collections::BTreeMap;
+
+/// Message templates attached to lines of sed source code.
+///
+/// Whenever execution reaches such line, the template is interpolated with
+/// current state and appended to the log. Execution never stops because of a logpoint.
+///
+/// Templates might contain following placeholders:
+///
+/// - `{input_line}`: line of input file that is being processed
+/// - `{line}`: line of sed script that is about to be executed
+/// - `{command}`: sed command on that line
+/// - `{pattern}`: pattern space
+/// - `{hold}`: hold space
+/// - `{state}`: number of the debugging state
+#[derive(Debug, Clone, Default)]
+pub struct Logpoints {
+    templates: BTreeMap<usize, String>,
+}
+impl Logpoints {
+    /// Attach template to given line. Empty template removes the logpoint instead.
+    pub fn set(&mut self, line: usize, template: String) {
+        if template.is_empty() {
+            self.templates.remove(&line);
+        } else {
+            self.templates.insert(line, template);
+        }
+    }
+
+    /// Move all logpoints to new lines, for example after the source code was changed.
+    /// Logpoints whose line is gone (`new_line` returns None) are removed.
+    pub fn relocate(&mut self, new_line: impl Fn(usize) -> Option<usize>) {
+        self.templates = std::mem::take(&mut self.templates)
+            .into_iter()
+            .filter_map(|(line, template)| Some((new_line(line)?, template)))
+            .collect();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&usize, &String)> {
+        self.templates.iter()
+    }
+
+    /// Get template attached to given line, if any.
+    pub fn get(&self, line: usize) -> Option<&String> {
+        self.templates.get(&line)
+    }
+
+    pub fn contains(&self, line: usize) -> bool {
+        self.templates.contains_key(&line)
+    }
+
+    /// Go through the whole trace and collect messages of all logpoints, in order of execution.
+    pub fn collect_messages(&self, debugger: &Debugger) -> Vec<LogMessage> {
+        if self.templates.is_empty() {
+            return Vec::new();
+        }
+        (0..debugger.count_of_states())
+            .filter_map(|i| debugger.peek_at_state(i).map(|state| (i, state)))
+            .filter_map(|(i, state)| {
+                self.templates
+                    .get(&state.current_line)
+                    .map(|template| LogMessage {
+                        state: i,
+                        text: interpolate(template, i, state, &debugger.source_code),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// One message produced by a logpoint.
+#[derive(Debug, Clone)]
+pub struct LogMessage {
+    /// Number of state (0-based) that produced the message
+    pub state: usize,
+    pub text: String,
+}
+
+/// Replace all known placeholders in template with values from given state.
+///
+/// Unknown placeholders are kept as they are.
+fn interpolate(
+    template: &str,
+    state_number: usize,
+    state: &DebuggingState,
+    source_code: &[String],
+) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[1..end] {
+            "input_line" => result.push_str(&state.input_line.to_string()),
+            "line" => result.push_str(&(state.current_line + 1).to_string()),
+            "command" => result.push_str(
+                source_code
+                    .get(state.current_line)
+                    .map(|s| s.as_str())
+                    .unwrap_or(""),
+            ),
+            "pattern" => result.push_str(&state.pattern_buffer),
+            "hold" => result.push_str(&state.hold_buffer),
+            "state" => result.push_str(&state_number.to_string()),
+            _ => {
+                result.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
@@ -1,2 +1,6 @@
+pub mod breakpoints;
+pub mod editor;
 pub mod generic;
+pub mod logpoints;
+pub mod session;
 pub mod tui;
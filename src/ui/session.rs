@@ -0,0 +1,337 @@
+use crate::diff::{line_mapping, line_moves};
+use crate::sed::debugger::StateAnchor;
+use crate::ui::breakpoints::{Breakpoint, Breakpoints};
+use crate::ui::logpoints::Logpoints;
+use crate::ui::tui::ToolPane;
+use anyhow::{Context, Result};
+use std::cmp::Ordering;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// First line of every session file. Files starting with anything else are ignored.
+const SESSION_HEADER: &str = "desed-session 1";
+
+/// Everything user set up while debugging a script, that should survive reloads
+/// and restarts of desed.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Source code the line numbers below refer to. When the source code changes,
+    /// this is used to move breakpoints to lines where they belong now.
+    pub source_code: Vec<String>,
+    /// Line (0-based) user had cursor on
+    pub cursor: usize,
+    /// State user was examining
+    pub anchor: StateAnchor,
+    pub breakpoints: Breakpoints,
+    pub logpoints: Logpoints,
+    /// Tool pane that was open
+    pub tool_pane: Option<ToolPane>,
+    /// Were execution counts shown next to source code
+    pub show_coverage: bool,
+    /// Was the original script shown instead of the program as sed prints it
+    pub show_original: bool,
+    /// Lines (0-based) opening `{...}` blocks that were folded
+    pub folds: Vec<usize>,
+}
+impl Session {
+    /// Move all line-based information to match new source code.
+    ///
+    /// Lines inserted or removed above a breakpoint (or cursor, or logpoint) move it
+    /// up or down, so it stays on the same command. Breakpoints, logpoints and folds
+    /// on removed lines are dropped.
+    pub fn relocate(&mut self, source_code: &[String]) {
+        // Fresh session has no source code, there is nothing to move
+        if self.source_code.is_empty() || self.source_code == source_code {
+            self.source_code = source_code.to_vec();
+            return;
+        }
+        let mapping = line_mapping(&self.source_code, source_code);
+        let moves = line_moves(&self.source_code, source_code);
+        // There is one more line after the source code which can be targeted
+        // as well, see Tui::draw_source_code.
+        let end = self.source_code.len();
+        let new_line = |line: usize| match line.cmp(&end) {
+            Ordering::Less => moves[line],
+            Ordering::Equal => Some(source_code.len()),
+            Ordering::Greater => None,
+        };
+        // Points on removed lines are dropped, so they don't pile up on lines around
+        self.breakpoints.relocate(new_line);
+        self.logpoints.relocate(new_line);
+        self.folds = self
+            .folds
+            .iter()
+            .filter_map(|line| new_line(*line))
+            .collect();
+        // Cursor has to go somewhere, the nearest line will do
+        self.cursor = mapping
+            .get(self.cursor)
+            .copied()
+            .unwrap_or(source_code.len());
+        self.source_code = source_code.to_vec();
+    }
+
+    /// Load session saved for given sed script, if there is any.
+    pub fn load(sed_script: &Path) -> Option<Session> {
+        let contents = fs::read_to_string(Session::path(sed_script)?).ok()?;
+        Session::parse(&contents)
+    }
+
+    /// Save session for given sed script, so it can be loaded next time desed opens it.
+    pub fn save(&self, sed_script: &Path) -> Result<()> {
+        let path = Session::path(sed_script)
+            .with_context(|| "Failed to find directory to save session into. Is $HOME set?")?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).with_context(|| {
+                format!("Failed to create session directory {}", directory.display())
+            })?;
+        }
+        fs::write(&path, self.serialize())
+            .with_context(|| format!("Failed to save session into {}", path.display()))
+    }
+
+    /// Where should session for given sed script be stored.
+    ///
+    /// This is `$XDG_STATE_HOME/desed/sessions/` (or `~/.local/state/desed/sessions/`),
+    /// with file named after absolute path of the script, slashes replaced with `%`.
+    fn path(sed_script: &Path) -> Option<PathBuf> {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+        let script = fs::canonicalize(sed_script).ok()?;
+        let file_name = script.to_string_lossy().replace(['/', '\\'], "%");
+        Some(state_home.join("desed").join("sessions").join(file_name))
+    }
+
+    /// Write session in simple line-based format:
+    ///
+    /// ```text
+    /// desed-session 1
+    /// cursor 4
+    /// state 12
+    /// input_line 2
+    /// cycle_step 5
+    /// command_occurrence 1
+    /// command s/a/b/
+    /// pane breakpoints
+    /// coverage
+    /// original
+    /// fold <line>
+    /// breakpoint <line> <enabled> <hits that are ignored>
+    /// logpoint <line> <template>
+    /// source <line of source code>
+    /// ```
+    fn serialize(&self) -> String {
+        let mut lines: Vec<String> = vec![
+            String::from(SESSION_HEADER),
+            format!("cursor {}", self.cursor),
+            format!("state {}", self.anchor.state),
+            format!("input_line {}", self.anchor.input_line),
+            format!("cycle_step {}", self.anchor.cycle_step),
+            format!("command_occurrence {}", self.anchor.command_occurrence),
+        ];
+        if let Some(command) = &self.anchor.command {
+            lines.push(format!("command {}", command));
+        }
+        if let Some(pane) = self.tool_pane {
+            lines.push(format!("pane {}", pane.name()));
+        }
+        if self.show_coverage {
+            lines.push(String::from("coverage"));
+        }
+        if self.show_original {
+            lines.push(String::from("original"));
+        }
+        for line in &self.folds {
+            lines.push(format!("fold {}", line));
+        }
+        for bp in self.breakpoints.iter() {
+            lines.push(format!(
+                "breakpoint {} {} {}",
+                bp.line, bp.enabled as u8, bp.ignore_until
+            ));
+        }
+        for (line, template) in self.logpoints.iter() {
+            lines.push(format!("logpoint {} {}", line, template));
+        }
+        for line in &self.source_code {
+            lines.push(format!("source {}", line));
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Read session written by [Session::serialize]. Unknown or malformed lines are skipped.
+    fn parse(contents: &str) -> Option<Session> {
+        let mut lines = contents.lines();
+        if lines.next()? != SESSION_HEADER {
+            return None;
+        }
+        let mut session = Session::default();
+        for line in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "cursor" => session.cursor = value.parse().unwrap_or(0),
+                "state" => session.anchor.state = value.parse().unwrap_or(0),
+                "input_line" => session.anchor.input_line = value.parse().unwrap_or(0),
+                "cycle_step" => session.anchor.cycle_step = value.parse().unwrap_or(0),
+                "command_occurrence" => {
+                    session.anchor.command_occurrence = value.parse().unwrap_or(0)
+                }
+                "command" => session.anchor.command = Some(String::from(value)),
+                "pane" => session.tool_pane = ToolPane::from_name(value),
+                "coverage" => session.show_coverage = true,
+                "original" => session.show_original = true,
+                "fold" => {
+                    if let Ok(line) = value.parse() {
+                        session.folds.push(line);
+                    }
+                }
+                "breakpoint" => {
+                    let numbers: Vec<usize> = value
+                        .split(' ')
+                        .filter_map(|number| number.parse().ok())
+                        .collect();
+                    if let [line, enabled, ignore_until] = numbers[..] {
+                        session.breakpoints.insert(Breakpoint {
+                            line,
+                            enabled: enabled != 0,
+                            ignore_until,
+                        });
+                    }
+                }
+                "logpoint" => {
+                    if let Some((line, template)) = value.split_once(' ') {
+                        if let Ok(line) = line.parse() {
+                            session.logpoints.set(line, String::from(template));
+                        }
+                    }
+                }
+                "source" => session.source_code.push(String::from(value)),
+                _ => {}
+            }
+        }
+        Some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &[&str]) -> Vec<String> {
+        source.iter().map(|line| String::from(*line)).collect()
+    }
+
+    fn breakpoint_lines(session: &Session) -> Vec<usize> {
+        session.breakpoints.iter().map(|bp| bp.line).collect()
+    }
+
+    #[test]
+    fn points_follow_their_commands() {
+        let mut session = Session {
+            source_code: lines(&["a", "b", "c"]),
+            cursor: 2,
+            ..Session::default()
+        };
+        session.breakpoints.toggle(1);
+        session.breakpoints.toggle(3);
+        session.logpoints.set(2, String::from("at c"));
+        session.relocate(&lines(&["new", "a", "b", "c"]));
+        assert_eq!(breakpoint_lines(&session), [2, 4]);
+        assert_eq!(
+            session.logpoints.iter().collect::<Vec<_>>(),
+            [(&3, &String::from("at c"))]
+        );
+        assert_eq!(session.cursor, 3);
+    }
+
+    #[test]
+    fn points_on_removed_lines_are_dropped() {
+        let mut session = Session {
+            source_code: lines(&["a", "b", "c", "d", "e"]),
+            cursor: 3,
+            ..Session::default()
+        };
+        for line in 0..5 {
+            session.breakpoints.insert(Breakpoint {
+                line,
+                enabled: line % 2 == 0,
+                ignore_until: line,
+            });
+        }
+        session.logpoints.set(2, String::from("at c"));
+        session.logpoints.set(3, String::from("at d"));
+        // `b` is edited, `c` and `d` are removed
+        session.relocate(&lines(&["a", "B", "e"]));
+        let breakpoints: Vec<(usize, bool, usize)> = session
+            .breakpoints
+            .iter()
+            .map(|bp| (bp.line, bp.enabled, bp.ignore_until))
+            .collect();
+        assert_eq!(breakpoints, [(0, true, 0), (1, false, 1), (2, true, 4)]);
+        assert_eq!(session.logpoints.iter().count(), 0);
+        assert!(session.cursor < 3);
+    }
+
+    #[test]
+    fn everything_removed() {
+        let mut session = Session {
+            source_code: lines(&["a", "b"]),
+            ..Session::default()
+        };
+        session.breakpoints.toggle(0);
+        session.breakpoints.toggle(1);
+        session.relocate(&lines(&["x"]));
+        assert_eq!(breakpoint_lines(&session), [0]);
+        session.relocate(&[]);
+        assert!(session.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn serialized_session_reads_back() {
+        let mut session = Session {
+            source_code: lines(&["/x/ {", "s/a/b/", "}", ""]),
+            cursor: 1,
+            anchor: StateAnchor {
+                state: 12,
+                input_line: 2,
+                cycle_step: 5,
+                command: Some(String::from("s/a/b/")),
+                command_occurrence: 1,
+            },
+            tool_pane: Some(ToolPane::Expectation),
+            show_coverage: true,
+            show_original: false,
+            folds: vec![0],
+            ..Session::default()
+        };
+        session.breakpoints.insert(Breakpoint {
+            line: 1,
+            enabled: false,
+            ignore_until: 3,
+        });
+        session
+            .logpoints
+            .set(2, String::from("line {line}: {pattern}"));
+        let text = session.serialize();
+        let parsed = Session::parse(&text).unwrap();
+        assert_eq!(parsed.serialize(), text);
+        assert_eq!(parsed.source_code, session.source_code);
+        assert_eq!(parsed.anchor.command.as_deref(), Some("s/a/b/"));
+        assert_eq!(parsed.tool_pane, Some(ToolPane::Expectation));
+        let bp = parsed.breakpoints.nth(0).unwrap();
+        assert_eq!((bp.line, bp.enabled, bp.ignore_until), (1, false, 3));
+    }
+
+    #[test]
+    fn foreign_files_are_ignored() {
+        assert!(Session::parse("something else\ncursor 1\n").is_none());
+        let session = Session::parse("desed-session 1\ncursor x\nbreakpoint 1 2\nunknown\n");
+        let session = session.unwrap();
+        assert_eq!(session.cursor, 0);
+        assert!(session.breakpoints.is_empty());
+    }
+}
@@ -1,29 +1,107 @@
+use crate::cli::Options;
+use crate::diff::Change;
 use crate::file_watcher::FileWatcher;
-use crate::sed::debugger::{Debugger, DebuggingState};
+use crate::sed::comparison::TraceComparison;
+use crate::sed::coverage::Coverage;
+use crate::sed::debugger::{Debugger, DebuggingState, InfiniteLoop};
+use crate::sed::evaluation::{Branch, BranchStep, Evaluator, RegexTest};
+use crate::sed::expectation::Expectation;
+use crate::sed::explain::explain;
+use crate::sed::highlight::{highlight, line_kinds, TokenKind};
+use crate::sed::lint::{lint, locate_in_program, Diagnostic, Severity};
+use crate::sed::profile::Profile;
+use crate::sed::regex::{
+    describe_element, describe_quantifier, quote, unescape_command_regexes, Element, Node,
+    RegexTree,
+};
+use crate::sed::script::{Argument, Position, Script};
+use crate::sed::source_map::SourceMap;
+use crate::ui::breakpoints::Breakpoints;
+use crate::ui::editor::Editor;
 use crate::ui::generic::{ApplicationExitReason, UiAgent};
+use crate::ui::logpoints::{LogMessage, Logpoints};
+use crate::ui::session::Session;
 use anyhow::{Context, Result};
 use ratatui::backend::CrosstermBackend;
-use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, MouseEvent, MouseEventKind};
+use ratatui::crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind,
+};
 use ratatui::crossterm::execute;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph, Wrap};
 use ratatui::Frame;
 use ratatui::Terminal;
 use std::cmp::{max, min};
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::fs;
 use std::io;
+use std::path::Path;
 use std::sync::mpsc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct Tui<'a> {
-    debugger: &'a Debugger,
+    debugger: Debugger,
+    /// Options sed was run with
+    settings: &'a Options,
+    /// Debugger from before the last reload, if any. Used to show what the reload changed.
+    previous_debugger: Option<Debugger>,
+    /// Differences between previous and current execution
+    comparison: Option<TraceComparison>,
+    /// Comparison of output with expected output, if user provided any
+    expectation: Option<Expectation>,
+    /// How many times was each line executed
+    coverage: Coverage,
+    /// For each state, how many times was its line reached up to and including the state
+    line_hits: Vec<usize>,
+    /// If true, execution counts are shown next to source code
+    show_coverage: bool,
+    /// Where does the execution spend its steps
+    profile: Profile,
+    /// Problems found in the script by linter
+    diagnostics: Vec<Diagnostic>,
+    /// Each line of the program parsed, so it can be highlighted
+    program: Vec<Option<Script>>,
+    /// `{...}` blocks of the program, as lines of the opening and the closing brace
+    blocks: Vec<(usize, usize)>,
+    /// Lines opening blocks which are folded into one row
+    folds: Vec<usize>,
+    /// Where are commands of the program in the original script, if it could be parsed
+    source_map: Option<SourceMap>,
+    /// If true, the original script is shown instead of the program as sed prints it
+    show_original: bool,
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
-    file_watcher: FileWatcher,
+    /// Watches source files for changes. This is moved into the input thread
+    /// once the UI starts.
+    file_watcher: Option<FileWatcher>,
     /// Collection of lines which are designated as breakpoints
-    breakpoints: HashSet<usize>,
+    breakpoints: Breakpoints,
+    /// Message templates attached to lines
+    logpoints: Logpoints,
+    /// Messages produced by logpoints over the whole execution
+    log_messages: Vec<LogMessage>,
+    /// Auxiliary pane displayed under source code, if any.
+    tool_pane: ToolPaneState,
+    /// Text input user is currently writing into, if any.
+    prompt: Option<Prompt>,
+    /// Runs regexes typed by user against pattern space
+    evaluator: Evaluator,
+    /// Regex typed by user, optionally with replacement and flags
+    regex_query: String,
+    /// Result of the last regex test, with the state and query it was made for
+    regex_test: Option<(usize, String, Result<RegexTest, String>)>,
+    /// Commands last run in REPL, with the state they started from and what they did
+    repl: Option<(usize, String, Result<Branch, String>)>,
+    /// Script run again from a state with edited buffers, with the state, whether it ran
+    /// until the end of input, and what it did
+    what_if: Option<(usize, bool, Result<Branch, String>)>,
+    /// Script being edited in place of source code, if any
+    editor: Option<Editor>,
+    /// Script as the editor saved it, with modification time of input file at that moment.
+    /// File watcher reports the save as well, but the script already runs again by then.
+    saved_files: Option<(String, Option<SystemTime>)>,
     /// Remembers which line has user selected (has cursor on).
     cursor: usize,
     /// UI is refreshed automatically on user input.
@@ -41,6 +119,8 @@ pub struct Tui<'a> {
     pressed_keys_buffer: String,
     /// Remembers at which state are we currently. User can step back and forth.
     current_state: usize,
+    /// If true, source code scrolls to follow the line being executed. Otherwise it follows cursor.
+    use_execution_pointer_as_focus_line: bool,
 }
 impl<'a> Tui<'a> {
     /// Create new TUI that gathers data from the debugger.
@@ -49,10 +129,14 @@ impl<'a> Tui<'a> {
     #[allow(unused_must_use)]
     // NOTE: We don't care that some actions here fail (for example mouse handling),
     // as some features that we're trying to enable here are not necessary for desed.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        debugger: &'a Debugger,
+        settings: &'a Options,
+        debugger: Debugger,
+        previous_debugger: Option<Debugger>,
+        expectation: Option<Expectation>,
         file_watcher: FileWatcher,
-        current_state: usize,
+        mut session: Session,
     ) -> Result<Self> {
         let mut stdout = io::stdout();
         execute!(stdout, event::EnableMouseCapture);
@@ -61,18 +145,231 @@ impl<'a> Tui<'a> {
             .with_context(|| "Failed to initialize terminal with crossterm backend.")?;
         ratatui::crossterm::terminal::enable_raw_mode()?;
         terminal.hide_cursor();
+        // Source code might have changed since the session was saved
+        session.relocate(&debugger.source_code);
+        let log_messages = session.logpoints.collect_messages(&debugger);
+        let (diagnostics, source_map) = Tui::analyze_script(settings, &debugger);
+        let program = Script::parse_program(&debugger.source_code);
+        let blocks = Script::program_blocks(&program);
+        let folds = Tui::open_folds(&session.folds, &blocks);
+        let comparison = previous_debugger
+            .as_ref()
+            .map(|previous| TraceComparison::new(previous, &debugger));
+        let selection = Tui::initial_selection(expectation.as_ref(), session.tool_pane);
         Ok(Tui {
-            debugger,
+            settings,
+            comparison,
+            expectation,
+            coverage: Coverage::new(&debugger),
+            line_hits: Tui::line_hits(&debugger),
+            show_coverage: session.show_coverage,
+            profile: Profile::new(&debugger),
+            diagnostics,
+            blocks,
+            folds,
+            program,
+            show_original: session.show_original && source_map.is_some(),
+            source_map,
             terminal,
-            file_watcher,
-            breakpoints: HashSet::new(),
-            cursor: 0,
+            file_watcher: Some(file_watcher),
+            breakpoints: session.breakpoints,
+            logpoints: session.logpoints,
+            log_messages,
+            tool_pane: ToolPaneState {
+                kind: session.tool_pane,
+                selection,
+                ..ToolPaneState::default()
+            },
+            prompt: None,
+            evaluator: Evaluator::new(settings, &debugger),
+            regex_query: String::new(),
+            regex_test: None,
+            repl: None,
+            what_if: None,
+            editor: None,
+            saved_files: None,
+            cursor: min(session.cursor, debugger.source_code.len()),
             forced_refresh_rate: 200,
             pressed_keys_buffer: String::new(),
-            current_state,
+            current_state: debugger.locate(&session.anchor),
+            use_execution_pointer_as_focus_line: false,
+            debugger,
+            previous_debugger,
         })
     }
 
+    /// Run the script again and show the new execution, keeping breakpoints and such
+    /// and staying in the same state if possible, just like reload does. The current
+    /// execution becomes the previous one, so the two can be compared.
+    fn rerun(&mut self) -> Result<()> {
+        let debugger = Debugger::new(self.settings.clone())?;
+        let expectation = match &self.settings.expected_output {
+            Some(path) => Some(Expectation::read(path, &debugger)?),
+            None => None,
+        };
+        let mut session = self.session();
+        session.relocate(&debugger.source_code);
+        let (diagnostics, source_map) = Tui::analyze_script(self.settings, &debugger);
+        self.program = Script::parse_program(&debugger.source_code);
+        self.blocks = Script::program_blocks(&self.program);
+        self.folds = Tui::open_folds(&session.folds, &self.blocks);
+        self.comparison = Some(TraceComparison::new(&self.debugger, &debugger));
+        self.tool_pane.selection =
+            Tui::initial_selection(expectation.as_ref(), self.tool_pane.kind);
+        self.expectation = expectation;
+        self.coverage = Coverage::new(&debugger);
+        self.line_hits = Tui::line_hits(&debugger);
+        self.profile = Profile::new(&debugger);
+        self.diagnostics = diagnostics;
+        self.show_original = self.show_original && source_map.is_some();
+        self.source_map = source_map;
+        self.log_messages = session.logpoints.collect_messages(&debugger);
+        self.breakpoints = session.breakpoints;
+        self.logpoints = session.logpoints;
+        self.evaluator = Evaluator::new(self.settings, &debugger);
+        self.regex_test = None;
+        self.repl = None;
+        self.what_if = None;
+        self.cursor = min(session.cursor, debugger.source_code.len());
+        self.current_state = debugger.locate(&session.anchor);
+        self.previous_debugger = Some(std::mem::replace(&mut self.debugger, debugger));
+        Ok(())
+    }
+
+    /// Read the original script, find problems in it and map its commands to the program
+    /// as desed shows it.
+    ///
+    /// If the script can't be read or parsed, there is nothing to show, sed itself will complain.
+    fn analyze_script(
+        settings: &Options,
+        debugger: &Debugger,
+    ) -> (Vec<Diagnostic>, Option<SourceMap>) {
+        let source = match fs::read_to_string(&settings.sed_script) {
+            Ok(source) => source,
+            Err(_) => return (Vec::new(), None),
+        };
+        let (script, mut diagnostics) = lint(&source, settings.posix());
+        let source_map = script.map(|script| {
+            locate_in_program(&mut diagnostics, &script, &debugger.source_code);
+            SourceMap::new(&source, &script, &debugger.source_code)
+        });
+        (diagnostics, source_map)
+    }
+
+    /// Folded lines which still open a block. They might not after the script changed.
+    fn open_folds(folds: &[usize], blocks: &[(usize, usize)]) -> Vec<usize> {
+        folds
+            .iter()
+            .copied()
+            .filter(|line| blocks.iter().any(|(open, _)| open == line))
+            .collect()
+    }
+
+    /// Item selected in tool pane when execution is shown. Comparison with expected output
+    /// starts with the first wrong line selected.
+    fn initial_selection(expectation: Option<&Expectation>, tool_pane: Option<ToolPane>) -> usize {
+        match (expectation, tool_pane) {
+            (Some(expectation), Some(ToolPane::Expectation)) => {
+                expectation.first_mismatch().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Count how many times was the line of each state reached, up to and including the state.
+    /// Breakpoints are hit this many times, no matter how user got to the state.
+    fn line_hits(debugger: &Debugger) -> Vec<usize> {
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        (0..debugger.count_of_states())
+            .filter_map(|i| debugger.peek_at_state(i))
+            .map(|state| {
+                let count = counts.entry(state.current_line).or_insert(0);
+                *count += 1;
+                *count
+            })
+            .collect()
+    }
+
+    /// How many times was given line reached up to and including given state.
+    fn hit_count(debugger: &Debugger, line_hits: &[usize], line: usize, state: usize) -> usize {
+        (0..=state)
+            .rev()
+            .find(|i| debugger.peek_at_state(*i).map(|s| s.current_line) == Some(line))
+            .map(|i| line_hits[i])
+            .unwrap_or(0)
+    }
+
+    /// Should running stop at given state, because it hits a breakpoint?
+    fn stops_at(&self, state: usize) -> bool {
+        self.debugger
+            .peek_at_state(state)
+            .map(|s| {
+                self.breakpoints
+                    .stops(s.current_line, self.line_hits[state])
+            })
+            .unwrap_or(false)
+    }
+
+    /// Remember everything user has set up, so it can be restored later.
+    fn session(&self) -> Session {
+        Session {
+            source_code: self.debugger.source_code.clone(),
+            cursor: self.cursor,
+            anchor: self.debugger.anchor(self.current_state),
+            breakpoints: self.breakpoints.clone(),
+            logpoints: self.logpoints.clone(),
+            tool_pane: self.tool_pane.kind,
+            show_coverage: self.show_coverage,
+            show_original: self.show_original,
+            folds: self.folds.clone(),
+        }
+    }
+
+    /// Can the cursor be on given line of the program? The original script shows only lines
+    /// with commands, not continuation of text of `a` and such. Folded blocks show only
+    /// the opening line.
+    fn is_visible(&self, line: usize) -> bool {
+        let folded = self
+            .blocks
+            .iter()
+            .any(|(open, close)| self.folds.contains(open) && *open < line && line <= *close);
+        if folded {
+            return false;
+        }
+        match &self.source_map {
+            Some(source_map) if self.show_original => {
+                line == self.debugger.source_code.len() || source_map.location(line).is_some()
+            }
+            _ => true,
+        }
+    }
+
+    /// Line (0-based) of the original script with command under cursor. If the script couldn't
+    /// be mapped to the program, or there is no command under cursor, this is the first line.
+    fn script_line(&self) -> usize {
+        self.source_map
+            .as_ref()
+            .and_then(|source_map| source_map.location(self.cursor))
+            .map(|(start, _)| start.line)
+            .unwrap_or(0)
+    }
+
+    /// Move cursor to the next visible line, if there is any.
+    fn move_cursor_down(&mut self) {
+        if let Some(line) =
+            (self.cursor + 1..=self.debugger.source_code.len()).find(|line| self.is_visible(*line))
+        {
+            self.cursor = line;
+        }
+    }
+
+    /// Move cursor to the previous visible line, if there is any.
+    fn move_cursor_up(&mut self) {
+        if let Some(line) = (0..self.cursor).rev().find(|line| self.is_visible(*line)) {
+            self.cursor = line;
+        }
+    }
+
     /// Reads given buffer and returns it as a number.
     ///
     /// A default value will be return if the number is non-parsable (typically empty buffer) or is
@@ -90,27 +387,27 @@ impl<'a> Tui<'a> {
     }
 
     /// Generate layout and call individual draw methods for each layout part.
-    #[allow(clippy::too_many_arguments)]
-    fn draw_layout_and_subcomponents(
-        f: &mut Frame,
-        debugger: &Debugger,
-        state: &DebuggingState,
-        breakpoints: &HashSet<usize>,
-        // Line (0-based) which user has selected via cursor
-        cursor: usize,
-        // Line (0-based) which sed interpreter currently executes
-        interpreter_line: usize,
-        // Line (0-based) which should be approximately at the center of the screen
-        focused_line: usize,
-        draw_memory: &mut DrawMemory,
-    ) {
+    fn draw_layout_and_subcomponents(f: &mut Frame, data: &DrawData, draw_memory: &mut DrawMemory) {
         let total_size = f.area();
+        let debugger = data.debugger;
+        let state = data.state;
+        let tool_pane = data.tool_pane;
 
         if let [left_plane, right_plane] = Layout::default()
             .direction(Direction::Horizontal)
             .constraints([Constraint::Ratio(2, 3), Constraint::Ratio(1, 3)].as_ref())
             .split(total_size)[..]
         {
+            // If there is a tool pane open, it takes bottom third of the left plane
+            let (source_plane, tool_plane) = if tool_pane.kind.is_some() {
+                let split = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Ratio(2, 3), Constraint::Ratio(1, 3)].as_ref())
+                    .split(left_plane);
+                (split[0], Some(split[1]))
+            } else {
+                (left_plane, None)
+            };
             if let [pattern_plane, hold_plane, regex_match_plane, output_plane] = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints(
@@ -124,16 +421,111 @@ impl<'a> Tui<'a> {
                 )
                 .split(right_plane)[..]
             {
-                Tui::draw_source_code(
-                    f,
-                    &debugger.source_code,
-                    breakpoints,
-                    focused_line,
-                    cursor,
-                    interpreter_line,
-                    draw_memory,
-                    left_plane,
-                );
+                if let Some(editor) = data.editor {
+                    Tui::draw_editor(f, editor, draw_memory, source_plane);
+                } else {
+                    let (mut rows, name) = match data.source_map {
+                        Some(source_map) => (
+                            Tui::original_rows(
+                                source_map,
+                                debugger.source_code.len(),
+                                data.coverage,
+                                data.cursor,
+                                data.interpreter_line,
+                            ),
+                            "Original script",
+                        ),
+                        None => (
+                            Tui::program_rows(
+                                &debugger.source_code,
+                                data.program,
+                                data.blocks,
+                                data.coverage,
+                            ),
+                            "Source code",
+                        ),
+                    };
+                    Tui::fold_rows(&mut rows, data.blocks, data.folds, data.breakpoints);
+                    Tui::draw_block_guides(&mut rows, data.blocks, data.folds);
+                    Tui::draw_source_code(
+                        f,
+                        &rows,
+                        name,
+                        data.breakpoints,
+                        data.logpoints,
+                        data.coverage,
+                        data.diagnostics,
+                        debugger.infinite_loop.as_ref(),
+                        data.current_state,
+                        data.focused_line,
+                        data.cursor,
+                        data.interpreter_line,
+                        draw_memory,
+                        source_plane,
+                    );
+                }
+                if let (Some(kind), Some(tool_plane)) = (tool_pane.kind, tool_plane) {
+                    match kind {
+                        ToolPane::Breakpoints => Tui::draw_breakpoints(
+                            f,
+                            debugger,
+                            data.line_hits,
+                            data.current_state,
+                            data.breakpoints,
+                            tool_pane,
+                            tool_plane,
+                        ),
+                        ToolPane::Log => Tui::draw_log(
+                            f,
+                            data.log_messages,
+                            data.current_state,
+                            tool_pane,
+                            tool_plane,
+                        ),
+                        ToolPane::Expectation => {
+                            Tui::draw_expectation(f, data.expectation, tool_pane, tool_plane)
+                        }
+                        ToolPane::Explain => Tui::draw_explanation(
+                            f,
+                            &debugger.source_code,
+                            data.program,
+                            data.focused_line,
+                            data.extended_regex,
+                            tool_plane,
+                        ),
+                        ToolPane::Regex => Tui::draw_regex_breakdown(
+                            f,
+                            data.program,
+                            data.focused_line,
+                            data.extended_regex,
+                            tool_plane,
+                        ),
+                        ToolPane::RegexTester => {
+                            Tui::draw_regex_test(f, data.regex_query, data.regex_test, tool_plane)
+                        }
+                        ToolPane::Repl => {
+                            Tui::draw_repl(f, debugger, data.repl, tool_pane, tool_plane)
+                        }
+                        ToolPane::WhatIf => {
+                            Tui::draw_what_if(f, debugger, data.what_if, tool_pane, tool_plane)
+                        }
+                        ToolPane::Profile => Tui::draw_profile(
+                            f,
+                            &debugger.source_code,
+                            data.profile,
+                            tool_pane,
+                            tool_plane,
+                        ),
+                        ToolPane::Diff => Tui::draw_comparison(
+                            f,
+                            data.previous_debugger,
+                            debugger,
+                            data.comparison,
+                            tool_pane,
+                            tool_plane,
+                        ),
+                    }
+                }
                 Tui::draw_text(
                     f,
                     String::from(" Pattern space "),
@@ -153,6 +545,9 @@ impl<'a> Tui<'a> {
                     state.output.as_ref().map(|s| s.join("\n")).as_ref(),
                     output_plane,
                 );
+                if let Some(prompt) = data.prompt {
+                    Tui::draw_prompt(f, prompt, total_size);
+                }
             } else {
                 panic!("Failed to generate vertically split layout 1:1:1:1.");
             }
@@ -163,27 +558,76 @@ impl<'a> Tui<'a> {
 
     /// Draw source code into main window.github.com/s2e/s2e
     ///
-    /// Handles scrolling and breakpoint display as well.
-    ///
-    /// TODO: syntax highlighting
+    /// Handles scrolling, breakpoint display and syntax highlighting as well.
+    /// Each row shows one or more lines of the program, see [SourceRow].
     #[allow(clippy::too_many_arguments)]
     fn draw_source_code(
         f: &mut Frame,
-        source_code: &[String],
-        breakpoints: &HashSet<usize>,
+        rows: &[SourceRow],
+        name: &str,
+        breakpoints: &Breakpoints,
+        logpoints: &Logpoints,
+        coverage: Option<&Coverage>,
+        diagnostics: &[Diagnostic],
+        infinite_loop: Option<&InfiniteLoop>,
+        current_state: usize,
         focused_line: usize,
         cursor: usize,
         interpreter_line: usize,
         draw_memory: &mut DrawMemory,
         area: Rect,
     ) {
+        draw_memory.source_code_area = area;
+        draw_memory.row_lines = rows
+            .iter()
+            .map(|row| row.program_lines.first().copied())
+            .collect();
+        let mut title = vec![Span::raw(match coverage {
+            Some(coverage) => format!(
+                " {} (executed {}/{} lines) ",
+                name,
+                coverage.covered_lines(),
+                coverage.total_lines()
+            ),
+            None => format!(" {} ", name),
+        })];
+        let loop_style = Style::default()
+            .fg(Color::LightRed)
+            .add_modifier(Modifier::BOLD);
+        if let Some(infinite_loop) = infinite_loop {
+            title.push(Span::styled(
+                format!(
+                    " Infinite loop in states {}-{}, sed was stopped ",
+                    infinite_loop.first_state, infinite_loop.repeated_state
+                ),
+                loop_style,
+            ));
+        }
+        if let Some(severity) = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.severity)
+            .max()
+        {
+            title.push(Span::styled(
+                format!(" Lint: {} problems ", diagnostics.len()),
+                Style::default().fg(match severity {
+                    Severity::Error => Color::LightRed,
+                    Severity::Warning => Color::Yellow,
+                }),
+            ));
+        }
+        let in_loop = infinite_loop
+            .map(|infinite_loop| infinite_loop.contains(current_state))
+            .unwrap_or(false);
+        let closing_branch = infinite_loop.and_then(|infinite_loop| infinite_loop.closing_branch);
         let block_source_code = Block::default()
-            .title(" Source code ")
+            .title(Line::from(title))
             .borders(Borders::ALL);
         let mut text_output: Vec<Line> = Vec::new();
 
         // Scroll:
         // Focused line is line that should always be at the center of the screen.
+        let focused_row = Tui::row_of(rows, focused_line);
         let display_start;
         {
             let grace_lines = 10;
@@ -193,16 +637,14 @@ impl<'a> Tui<'a> {
             let minimum_startline = 0;
             // Maximum startline that should be possible to have in any case
             // Magical number 4: I don't know what it's doing here, but it works this way. Otherwise
-            // we just keep maximum scroll four lines early.
-            let maximum_startline = (source_code.len() as i32 - 1) - height + 4;
+            // we just keep maximum scroll four lines early. The last row is the phantom one.
+            let maximum_startline = (rows.len() as i32 - 2) - height + 4;
             // Minimum startline position that makes sense - we want visible code but within limits of the source code height.
-            let mut minimum_viable_startline = max(
-                focused_line as i32 - height + grace_lines,
-                minimum_startline,
-            ) as usize;
+            let mut minimum_viable_startline =
+                max(focused_row as i32 - height + grace_lines, minimum_startline) as usize;
             // Maximum startline position that makes sense - we want visible code but within limits of the source code height
             let mut maximum_viable_startline = max(
-                min(focused_line as i32 - grace_lines, maximum_startline),
+                min(focused_row as i32 - grace_lines, maximum_startline),
                 minimum_startline,
             ) as usize;
             // Sometimes, towards end of file, maximum and minim viable lines have swapped values.
@@ -222,50 +664,943 @@ impl<'a> Tui<'a> {
             draw_memory.current_startline = display_start;
         }
 
-        // Define closure that prints one more line of source code
-        let mut add_new_line = |line_number| {
+        for row in rows.iter().skip(display_start) {
+            let lines = &row.program_lines;
             // Define colors depending whether currently selected line has a breakpoint
-            let linenr_color = if breakpoints.contains(&line_number) {
+            let linenr_color = if lines.iter().any(|line| breakpoints.is_enabled(*line)) {
                 Color::LightRed
+            } else if lines.iter().any(|line| breakpoints.contains(*line)) {
+                Color::Magenta
+            } else if lines.iter().any(|line| logpoints.contains(*line)) {
+                Color::LightGreen
             } else {
                 Color::Yellow
             };
             // Define background color depending on whether we have cursor here
-            let linenr_bg_color = if line_number == cursor {
+            let linenr_bg_color = if lines.contains(&cursor) {
                 Color::DarkGray
             } else {
                 Color::Reset
             };
-            // Format line indicator. It's different if the currently executing line is here
-            let linenr_format = if line_number == interpreter_line {
-                format!("{: <3}▶", (line_number + 1))
+            // Format line indicator. It's different if the currently executing line is here,
+            // and different yet if the line is executed as part of infinite loop
+            let linenr_format = if lines.contains(&interpreter_line) && in_loop {
+                format!("{: <3}↻", (row.number + 1))
+            } else if lines.contains(&interpreter_line) {
+                format!("{: <3}▶", (row.number + 1))
             } else {
-                format!("{: <4}", (line_number + 1))
+                format!("{: <4}", (row.number + 1))
             };
+            let mut spans = Vec::with_capacity(3);
+            if let Some(coverage) = coverage {
+                // With coverage shown, lines that never ran are highlighted
+                let count = lines.iter().filter_map(|line| coverage.count(*line)).max();
+                spans.push(match count {
+                    Some(0) => {
+                        Span::styled(format!("{: >5} ", 0), Style::default().fg(Color::LightRed))
+                    }
+                    Some(count) => Span::styled(
+                        format!("{: >5} ", count),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    None => Span::raw(format!("{: >6}", "")),
+                });
+            }
+            spans.push(Span::styled(
+                linenr_format,
+                Style::default().fg(linenr_color).bg(linenr_bg_color),
+            ));
+            if !row.guides.is_empty() {
+                spans.push(Span::styled(
+                    format!("{} ", row.guides),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            spans.extend(row.text.iter().cloned());
+            if closing_branch
+                .map(|closing_branch| lines.contains(&closing_branch))
+                .unwrap_or(false)
+            {
+                spans.push(Span::styled("  ◀ closes infinite loop", loop_style));
+            }
+            // Show the most severe problem of the line, if any
+            spans.extend(Tui::diagnostic_span(diagnostics.iter().filter(
+                |diagnostic| {
+                    diagnostic
+                        .program_line
+                        .map(|line| lines.contains(&line))
+                        .unwrap_or(false)
+                },
+            )));
             // Send the line we defined earlier to be displayed
-            text_output.push(Line::from(vec![
+            text_output.push(Line::from(spans));
+        }
+        let paragraph = Paragraph::new(text_output)
+            .block(block_source_code)
+            .wrap(Wrap { trim: false });
+        f.render_widget(paragraph, area);
+    }
+
+    /// Describe the first of problems of a line, mentioning how many others there are.
+    fn diagnostic_span<'d>(
+        mut diagnostics: impl Iterator<Item = &'d Diagnostic>,
+    ) -> Option<Span<'static>> {
+        let first = diagnostics.next()?;
+        let others = diagnostics.count();
+        let (marker, color) = match first.severity {
+            Severity::Error => ("✖", Color::LightRed),
+            Severity::Warning => ("⚠", Color::Yellow),
+        };
+        let more = if others > 0 {
+            format!(" (+{} more)", others)
+        } else {
+            String::new()
+        };
+        Some(Span::styled(
+            format!("  {} {}{}", marker, first.message, more),
+            Style::default().fg(color),
+        ))
+    }
+
+    /// Draw script being edited in place of source code, with cursor shown as reversed
+    /// character. Problems are shown next to their lines, just like in source code. If sed
+    /// refused to save the script, its complaint is shown instead.
+    fn draw_editor(f: &mut Frame, editor: &Editor, draw_memory: &mut DrawMemory, area: Rect) {
+        // Scroll as little as possible to keep cursor visible
+        let height = max(area.height.saturating_sub(2), 1) as usize;
+        let start = &mut draw_memory.editor_startline;
+        if editor.cursor.line < *start {
+            *start = editor.cursor.line;
+        } else if editor.cursor.line >= *start + height {
+            *start = editor.cursor.line + 1 - height;
+        }
+        let error_style = Style::default().fg(Color::LightRed);
+        let mut title = vec![
+            Span::raw(if editor.modified {
+                " Editing script [+] "
+            } else {
+                " Editing script "
+            }),
+            if editor.confirm_discard {
+                Span::styled(" Unsaved changes, Esc again to discard them ", error_style)
+            } else {
                 Span::styled(
-                    linenr_format,
-                    Style::default().fg(linenr_color).bg(linenr_bg_color),
-                ),
-                if let Some(source) = source_code.get(line_number) {
-                    Span::raw(source)
+                    " Ctrl-S: save and run, Ctrl-K: delete line, Esc: discard ",
+                    Style::default().fg(Color::DarkGray),
+                )
+            },
+        ];
+        if let Some((None, message)) = &editor.error {
+            title.push(Span::styled(format!(" ✖ sed: {} ", message), error_style));
+        }
+        let text: Vec<Line> = editor
+            .lines
+            .iter()
+            .enumerate()
+            .skip(*start)
+            .take(height)
+            .map(|(line, source)| {
+                // Tabs would take no space, so cursor couldn't be seen on them
+                let mut chars: Vec<char> = source
+                    .chars()
+                    .map(|c| if c == '\t' { ' ' } else { c })
+                    .collect();
+                let mut styles: Vec<Style> = line_kinds(&editor.highlights, line, chars.len())
+                    .into_iter()
+                    .map(Tui::token_style)
+                    .collect();
+                let has_cursor = line == editor.cursor.line;
+                if has_cursor {
+                    // Cursor at the end of line is shown after the last character
+                    if editor.cursor.column == chars.len() {
+                        chars.push(' ');
+                        styles.push(Style::default());
+                    }
+                    styles[editor.cursor.column] =
+                        styles[editor.cursor.column].add_modifier(Modifier::REVERSED);
+                }
+                let mut spans = vec![Span::styled(
+                    format!("{: <4}", line + 1),
+                    Style::default().fg(Color::Yellow).bg(if has_cursor {
+                        Color::DarkGray
+                    } else {
+                        Color::Reset
+                    }),
+                )];
+                spans.extend(Tui::styled_spans(&chars, &styles));
+                match &editor.error {
+                    Some((Some(error_line), message)) if *error_line == line => {
+                        spans.push(Span::styled(format!("  ✖ sed: {}", message), error_style));
+                    }
+                    _ => spans.extend(Tui::diagnostic_span(
+                        editor
+                            .diagnostics
+                            .iter()
+                            .filter(|diagnostic| diagnostic.position.line == line),
+                    )),
+                }
+                Line::from(spans)
+            })
+            .collect();
+        let block = Block::default()
+            .title(Line::from(title))
+            .borders(Borders::ALL);
+        f.render_widget(Paragraph::new(text).block(block), area);
+    }
+
+    /// Find row showing given line of the program. Lines that are not shown on their own,
+    /// such as continuation of text of `a`, belong to the row of the closest line above.
+    fn row_of(rows: &[SourceRow], line: usize) -> usize {
+        (0..=line)
+            .rev()
+            .find_map(|line| {
+                rows.iter()
+                    .position(|row| row.program_lines.contains(&line))
+            })
+            .unwrap_or(0)
+    }
+
+    /// Rows with one line of the program each, as sed prints it. Lines are indented by depth
+    /// of `{...}` blocks they are in.
+    fn program_rows<'b>(
+        source_code: &'b [String],
+        program: &[Option<Script>],
+        blocks: &[(usize, usize)],
+        coverage: Option<&Coverage>,
+    ) -> Vec<SourceRow<'b>> {
+        let mut rows: Vec<SourceRow> = source_code
+            .iter()
+            .enumerate()
+            .map(|(line, source)| {
+                let never_ran = coverage.and_then(|coverage| coverage.count(line)) == Some(0);
+                let depth = blocks
+                    .iter()
+                    .filter(|(open, close)| *open < line && line < *close)
+                    .count();
+                let mut text = vec![Span::raw("  ".repeat(depth))];
+                text.extend(if never_ran {
+                    // Lines that never ran are highlighted as a whole
+                    vec![Span::styled(
+                        source.as_str(),
+                        Style::default().fg(Color::LightRed),
+                    )]
                 } else {
-                    Span::raw("")
+                    Tui::highlight_command(source, program.get(line).and_then(Option::as_ref))
+                });
+                SourceRow {
+                    number: line,
+                    program_lines: vec![line],
+                    guides: String::new(),
+                    text,
+                }
+            })
+            .collect();
+        // Add one more "phantom" line so we see line where current segment execution ends
+        rows.push(SourceRow {
+            number: source_code.len(),
+            program_lines: vec![source_code.len()],
+            guides: String::new(),
+            text: Vec::new(),
+        });
+        rows
+    }
+
+    /// Rows with lines of the original script. The executing command is highlighted in place,
+    /// and the command under cursor is underlined.
+    fn original_rows<'b>(
+        source_map: &'b SourceMap,
+        program_length: usize,
+        coverage: Option<&Coverage>,
+        cursor: usize,
+        interpreter_line: usize,
+    ) -> Vec<SourceRow<'b>> {
+        let executing = source_map.location(interpreter_line);
+        let selected = source_map.location(cursor);
+        let covers = |location: Option<(Position, Position)>, position: Position| {
+            location
+                .map(|(start, end)| start <= position && position < end)
+                .unwrap_or(false)
+        };
+        let mut rows: Vec<SourceRow> = source_map
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(line, source)| {
+                let program_lines = source_map.program_lines_on(line);
+                let never_ran = coverage
+                    .map(|coverage| {
+                        !program_lines.is_empty()
+                            && program_lines
+                                .iter()
+                                .all(|line| coverage.count(*line) == Some(0))
+                    })
+                    .unwrap_or(false);
+                let chars: Vec<char> = source.chars().collect();
+                let styles: Vec<Style> = line_kinds(&source_map.highlights, line, chars.len())
+                    .into_iter()
+                    .enumerate()
+                    .map(|(column, kind)| {
+                        let position = Position { line, column };
+                        let mut style = if never_ran {
+                            Style::default().fg(Color::LightRed)
+                        } else {
+                            Tui::token_style(kind)
+                        };
+                        if covers(executing, position) {
+                            style = style.add_modifier(Modifier::REVERSED);
+                        }
+                        if covers(selected, position) {
+                            style = style.add_modifier(Modifier::UNDERLINED);
+                        }
+                        style
+                    })
+                    .collect();
+                SourceRow {
+                    number: line,
+                    program_lines,
+                    guides: String::new(),
+                    text: Tui::styled_spans(&chars, &styles),
+                }
+            })
+            .collect();
+        rows.push(SourceRow {
+            number: source_map.lines.len(),
+            program_lines: vec![program_length],
+            guides: String::new(),
+            text: Vec::new(),
+        });
+        rows
+    }
+
+    /// Merge rows of folded blocks into the row with the opening brace. The row shows how much
+    /// is folded and how many breakpoints are hidden in it.
+    fn fold_rows(
+        rows: &mut Vec<SourceRow>,
+        blocks: &[(usize, usize)],
+        folds: &[usize],
+        breakpoints: &Breakpoints,
+    ) {
+        let mut i = 0;
+        while i < rows.len() {
+            let folded = blocks
+                .iter()
+                .find(|(open, _)| folds.contains(open) && rows[i].program_lines.contains(open));
+            if let Some((open, close)) = folded.copied() {
+                let mut hidden = 0;
+                while !rows[i].program_lines.contains(&close) && i + 1 < rows.len() {
+                    let row = rows.remove(i + 1);
+                    rows[i].program_lines.extend(row.program_lines);
+                    hidden += 1;
+                }
+                let hidden_breakpoints = (open + 1..=close)
+                    .filter(|line| breakpoints.contains(*line))
+                    .count();
+                let row = &mut rows[i];
+                row.text.push(Span::styled(
+                    format!(" … }} ({} lines)", hidden),
+                    Style::default().fg(Color::DarkGray),
+                ));
+                if hidden_breakpoints > 0 {
+                    row.text.push(Span::styled(
+                        format!(" ● {} breakpoints", hidden_breakpoints),
+                        Style::default().fg(Color::LightRed),
+                    ));
+                }
+            }
+            i += 1;
+        }
+    }
+
+    /// Draw guides of `{...}` blocks, one column for each level of nesting. Rows without
+    /// commands, such as comments, belong to the block of the next command.
+    fn draw_block_guides(rows: &mut [SourceRow], blocks: &[(usize, usize)], folds: &[usize]) {
+        // Deepest nesting of blocks
+        let width = blocks
+            .iter()
+            .map(|(open, close)| {
+                blocks
+                    .iter()
+                    .filter(|(outer, outer_close)| outer <= open && close <= outer_close)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        if width == 0 {
+            return;
+        }
+        let mut next_line = None;
+        for row in rows.iter_mut().rev() {
+            let (first, last, lines) = match (
+                row.program_lines.iter().min(),
+                row.program_lines.iter().max(),
+            ) {
+                (Some(first), Some(last)) => (*first, *last, row.program_lines.clone()),
+                // Belongs to the block of the next command
+                _ => match next_line {
+                    Some(line) => (line, line, Vec::new()),
+                    None => (usize::MAX, usize::MAX, Vec::new()),
                 },
-            ]));
+            };
+            next_line = Some(first);
+            let mut guides: String = blocks
+                .iter()
+                .filter(|(open, close)| {
+                    if lines.is_empty() {
+                        *open < first && first <= *close
+                    } else {
+                        *open <= last && first <= *close
+                    }
+                })
+                .map(
+                    |(open, close)| match (lines.contains(open), lines.contains(close)) {
+                        (true, true) if folds.contains(open) => '▸',
+                        (true, true) => '─',
+                        (true, false) => '┌',
+                        (false, true) => '└',
+                        (false, false) => '│',
+                    },
+                )
+                .collect();
+            while guides.chars().count() < width {
+                guides.push(' ');
+            }
+            row.guides = guides;
+        }
+    }
+
+    /// Split line of the program into spans colored by syntax of the command on it. Lines that are
+    /// not commands on their own, such as continuation of text of `a`, are not colored.
+    fn highlight_command<'b>(source: &'b str, command: Option<&Script>) -> Vec<Span<'b>> {
+        let script = match command {
+            Some(script) => script,
+            None => return vec![Span::raw(source)],
         };
-        for number in display_start..source_code.len() {
-            add_new_line(number);
+        let chars: Vec<char> = source.chars().collect();
+        let styles: Vec<Style> = line_kinds(&highlight(script), 0, chars.len())
+            .into_iter()
+            .map(Tui::token_style)
+            .collect();
+        Tui::styled_spans(&chars, &styles)
+    }
+
+    /// Join neighbouring characters of the same style into spans.
+    fn styled_spans(chars: &[char], styles: &[Style]) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+        let mut start = 0;
+        for end in 1..=chars.len() {
+            if end == chars.len() || styles[end] != styles[start] {
+                let text: String = chars[start..end].iter().collect();
+                spans.push(Span::styled(text, styles[start]));
+                start = end;
+            }
         }
-        // Add one more "phantom" line so we see line where current segment execution ends
-        add_new_line(source_code.len());
-        let paragraph = Paragraph::new(text_output)
-            .block(block_source_code)
+        spans
+    }
+
+    fn token_style(kind: Option<TokenKind>) -> Style {
+        let style = Style::default();
+        match kind {
+            None => style,
+            Some(TokenKind::Address) => style.fg(Color::Cyan),
+            Some(TokenKind::Command) => style.fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            Some(TokenKind::Label) => style.fg(Color::Magenta),
+            Some(TokenKind::Regex) => style.fg(Color::Green),
+            Some(TokenKind::Replacement) => style.fg(Color::LightBlue),
+            Some(TokenKind::Reference) => style.fg(Color::LightBlue).add_modifier(Modifier::BOLD),
+            Some(TokenKind::Escape) => style.fg(Color::LightCyan),
+            Some(TokenKind::Flags) => style.fg(Color::LightMagenta),
+            Some(TokenKind::Delimiter) => style.fg(Color::DarkGray),
+            Some(TokenKind::Text) => style.fg(Color::White),
+            Some(TokenKind::Comment) => style.fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+        }
+    }
+
+    /// Draw list of all breakpoints, together with their state, how many times they were hit
+    /// up to the current state and how many of the next hits are ignored.
+    fn draw_breakpoints(
+        f: &mut Frame,
+        debugger: &Debugger,
+        line_hits: &[usize],
+        current_state: usize,
+        breakpoints: &Breakpoints,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let block_breakpoints = tool_pane.block(" Breakpoints ");
+        let mut text: Vec<Line> = Vec::new();
+        if breakpoints.is_empty() {
+            text.push(Line::from(vec![Span::styled(
+                "No breakpoints",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            )]));
+        }
+        // Keep selected breakpoint visible
+        let skip = tool_pane.scroll_offset(area);
+        for (i, bp) in breakpoints.iter().enumerate().skip(skip) {
+            let hits = Tui::hit_count(debugger, line_hits, bp.line, current_state);
+            let (marker, marker_color) = if bp.enabled {
+                ("●", Color::LightRed)
+            } else {
+                ("○", Color::Magenta)
+            };
+            let bg_color = if tool_pane.focused && i == tool_pane.selection {
+                Color::DarkGray
+            } else {
+                Color::Reset
+            };
+            text.push(Line::from(vec![
+                Span::styled(
+                    format!("{} ", marker),
+                    Style::default().fg(marker_color).bg(bg_color),
+                ),
+                Span::styled(
+                    format!("{: <4}", bp.line + 1),
+                    Style::default().fg(Color::Yellow).bg(bg_color),
+                ),
+                Span::styled(
+                    format!(
+                        " hits: {: <4} ignore: {: <4} ",
+                        hits,
+                        bp.ignore_until.saturating_sub(hits)
+                    ),
+                    Style::default().fg(Color::DarkGray).bg(bg_color),
+                ),
+                Span::raw(
+                    debugger
+                        .source_code
+                        .get(bp.line)
+                        .map(|s| s.as_str())
+                        .unwrap_or(""),
+                ),
+            ]));
+        }
+        let paragraph = Paragraph::new(text).block(block_breakpoints);
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw messages produced by logpoints. Messages that will be produced only
+    /// later in the execution are grayed out.
+    fn draw_log(
+        f: &mut Frame,
+        log_messages: &[LogMessage],
+        current_state: usize,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let block_log = tool_pane.block(" Log ");
+        let mut text: Vec<Line> = Vec::new();
+        if log_messages.is_empty() {
+            text.push(Line::from(vec![Span::styled(
+                "No messages, press p to attach logpoint to a line",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            )]));
+        }
+        let skip = tool_pane.scroll_offset(area);
+        for (i, message) in log_messages.iter().enumerate().skip(skip) {
+            let bg_color = if tool_pane.focused && i == tool_pane.selection {
+                Color::DarkGray
+            } else {
+                Color::Reset
+            };
+            let fg_color = if message.state <= current_state {
+                Color::LightBlue
+            } else {
+                Color::DarkGray
+            };
+            text.push(Line::from(vec![
+                Span::styled(
+                    format!("#{: <6}", message.state),
+                    Style::default().fg(Color::Yellow).bg(bg_color),
+                ),
+                Span::styled(
+                    message.text.as_str(),
+                    Style::default().fg(fg_color).bg(bg_color),
+                ),
+            ]));
+        }
+        let paragraph = Paragraph::new(text).block(block_log);
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw explanation of the command on given line of the program. Lines that are not
+    /// commands on their own, such as text of `a`, belong to the command above.
+    fn draw_explanation(
+        f: &mut Frame,
+        source_code: &[String],
+        program: &[Option<Script>],
+        line: usize,
+        extended_regex: bool,
+        area: Rect,
+    ) {
+        let command = (0..=line).rev().find_map(|line| {
+            let script = program.get(line)?.as_ref()?;
+            Some((line, script.commands.first()?))
+        });
+        let (title, text) = match command {
+            Some((line, command)) => {
+                let source = [source_code[line].as_str()];
+                let text = explain(command, &source, extended_regex)
+                    .into_iter()
+                    .map(|(topic, sentence)| {
+                        Line::from(vec![
+                            Span::styled(
+                                format!("{}: ", topic),
+                                Style::default().add_modifier(Modifier::BOLD),
+                            ),
+                            Span::raw(sentence),
+                        ])
+                    })
+                    .collect();
+                (format!(" Explain: {} ", source_code[line]), text)
+            }
+            None => (
+                String::from(" Explain "),
+                vec![Line::from(Span::styled(
+                    "No command here",
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::DarkGray),
+                ))],
+            ),
+        };
+        let paragraph = Paragraph::new(text)
+            .block(Block::default().title(title).borders(Borders::ALL))
             .wrap(Wrap { trim: false });
         f.render_widget(paragraph, area);
     }
 
+    /// Draw the most expensive cycles, loops and lines side by side.
+    ///
+    /// Selection goes through cycles first, then loops and then lines, see [ProfileEntry].
+    fn draw_profile(
+        f: &mut Frame,
+        source_code: &[String],
+        profile: &Profile,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let total_steps = profile.total_steps();
+        let block_profile = tool_pane.block(" Profile ");
+        let inner_area = block_profile.inner(area);
+        f.render_widget(block_profile, area);
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Ratio(1, 3),
+                    Constraint::Ratio(1, 3),
+                    Constraint::Ratio(1, 3),
+                ]
+                .as_ref(),
+            )
+            .spacing(2)
+            .split(inner_area);
+
+        let cycles: Vec<String> = profile
+            .cycles
+            .iter()
+            .map(|cycle| format!("{: >6} steps  input line {}", cycle.steps, cycle.input_line))
+            .collect();
+        let loops: Vec<String> = profile
+            .loops
+            .iter()
+            .map(|l| {
+                format!(
+                    "{: >6} steps  {: >5}x  :{} (lines {}-{})",
+                    l.steps,
+                    l.iterations,
+                    l.label,
+                    l.start + 1,
+                    l.end + 1
+                )
+            })
+            .collect();
+        let lines: Vec<String> = profile
+            .lines
+            .iter()
+            .map(|line| {
+                format!(
+                    "{: >6}x  {: <4}{}",
+                    line.count,
+                    line.line + 1,
+                    source_code.get(line.line).map(|s| s.trim()).unwrap_or("")
+                )
+            })
+            .collect();
+
+        let cycle_count = profile.cycles.len().max(1);
+        let headers = [
+            format!(
+                "Cycles: {} steps, {} per cycle",
+                total_steps,
+                total_steps / cycle_count
+            ),
+            String::from("Loops: steps, iterations"),
+            String::from("Lines: executions"),
+        ];
+        let mut first_entry = 0;
+        for ((header, rows), column) in headers
+            .iter()
+            .zip([cycles, loops, lines])
+            .zip(columns.iter())
+        {
+            let selection = tool_pane
+                .selection
+                .checked_sub(first_entry)
+                .filter(|selection| *selection < rows.len());
+            first_entry += rows.len();
+            Tui::draw_profile_column(f, header, &rows, selection, tool_pane.focused, *column);
+        }
+    }
+
+    /// Draw one column of profile with header, keeping selected row (if any) visible.
+    fn draw_profile_column(
+        f: &mut Frame,
+        header: &str,
+        rows: &[String],
+        selection: Option<usize>,
+        focused: bool,
+        area: Rect,
+    ) {
+        let mut text: Vec<Line> = vec![Line::from(Span::styled(
+            header,
+            Style::default().add_modifier(Modifier::BOLD),
+        ))];
+        if rows.is_empty() {
+            text.push(Line::from(Span::styled(
+                "Nothing here",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            )));
+        }
+        // One line is taken by header
+        let visible_rows = max(area.height as usize, 2) - 1;
+        let skip = selection
+            .map(|selection| (selection + 1).saturating_sub(visible_rows))
+            .unwrap_or(0);
+        for (i, row) in rows.iter().enumerate().skip(skip) {
+            let bg_color = if focused && selection == Some(i) {
+                Color::DarkGray
+            } else {
+                Color::Reset
+            };
+            text.push(Line::from(Span::styled(
+                row.as_str(),
+                Style::default().fg(Color::LightBlue).bg(bg_color),
+            )));
+        }
+        f.render_widget(Paragraph::new(text), area);
+    }
+
+    /// Draw where did processing of each input line start to differ from the previous
+    /// execution, followed by difference of final outputs.
+    fn draw_comparison(
+        f: &mut Frame,
+        previous_debugger: Option<&Debugger>,
+        debugger: &Debugger,
+        comparison: Option<&TraceComparison>,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let block_comparison = tool_pane.block(" Changes since last reload ");
+        let (previous_debugger, comparison) = match (previous_debugger, comparison) {
+            (Some(previous_debugger), Some(comparison)) => (previous_debugger, comparison),
+            _ => {
+                let paragraph = Paragraph::new(Span::styled(
+                    "No previous execution, reload (l) after editing the script to compare",
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::DarkGray),
+                ))
+                .block(block_comparison);
+                f.render_widget(paragraph, area);
+                return;
+            }
+        };
+        // Describe command and pattern space of a state, if it exists
+        let describe = |debugger: &Debugger, state: Option<usize>| -> String {
+            match state.and_then(|i| debugger.peek_at_state(i)) {
+                Some(state) => format!(
+                    "{} [{}]",
+                    debugger
+                        .source_code
+                        .get(state.current_line)
+                        .map(|s| s.as_str())
+                        .unwrap_or("end of cycle"),
+                    state.pattern_buffer
+                ),
+                None => String::from("(cycle ended)"),
+            }
+        };
+
+        let mut text: Vec<Line> = Vec::new();
+        if comparison.divergences.is_empty() {
+            text.push(Line::from(Span::styled(
+                "All input lines are processed the same way as before",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            )));
+        }
+        for (i, divergence) in comparison.divergences.iter().enumerate() {
+            let bg_color = if tool_pane.focused && i == tool_pane.selection {
+                Color::DarkGray
+            } else {
+                Color::Reset
+            };
+            text.push(Line::from(vec![
+                Span::styled(
+                    format!(
+                        "input line {: <4} step {: <3} ",
+                        divergence.input_line, divergence.step
+                    ),
+                    Style::default().fg(Color::Yellow).bg(bg_color),
+                ),
+                Span::styled(
+                    describe(previous_debugger, divergence.state_before),
+                    Style::default().fg(Color::LightRed).bg(bg_color),
+                ),
+                Span::styled(" → ", Style::default().bg(bg_color)),
+                Span::styled(
+                    describe(debugger, divergence.state_after),
+                    Style::default().fg(Color::LightGreen).bg(bg_color),
+                ),
+            ]));
+        }
+        text.push(Line::from(Span::styled(
+            if comparison.output_changed() {
+                "Output:"
+            } else {
+                "Output: unchanged"
+            },
+            Style::default().fg(Color::DarkGray),
+        )));
+        for (i, change) in comparison.output_diff.iter().enumerate() {
+            let bg_color =
+                if tool_pane.focused && comparison.divergences.len() + i == tool_pane.selection {
+                    Color::DarkGray
+                } else {
+                    Color::Reset
+                };
+            let (prefix, line, color) = match change {
+                Change::Same(line) => (" ", line, Color::Reset),
+                Change::Removed(line) => ("-", line, Color::LightRed),
+                Change::Added(line) => ("+", line, Color::LightGreen),
+            };
+            text.push(Line::from(Span::styled(
+                format!("{} {}", prefix, line),
+                Style::default().fg(color).bg(bg_color),
+            )));
+        }
+        // Keep selection visible. There is one more line between divergences and output.
+        let skip = tool_pane.scroll_offset(area)
+            + (tool_pane.selection >= comparison.divergences.len()) as usize;
+        let paragraph = Paragraph::new(text)
+            .block(block_comparison)
+            .scroll((skip as u16, 0));
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw expected and actual output side by side, together with input line
+    /// sed was processing when it printed each line.
+    fn draw_expectation(
+        f: &mut Frame,
+        expectation: Option<&Expectation>,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let block_expectation = tool_pane.block(" Expected output | Actual output ");
+        let expectation = match expectation {
+            Some(expectation) => expectation,
+            None => {
+                let paragraph = Paragraph::new(Span::styled(
+                    "No expected output, start desed with --expect FILE to compare",
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::DarkGray),
+                ))
+                .block(block_expectation);
+                f.render_widget(paragraph, area);
+                return;
+            }
+        };
+        // Two columns of the same width, plus column with input line
+        let input_line_width = 10;
+        let column_width = (area.width as usize).saturating_sub(2 + input_line_width + 3) / 2;
+        let fit = |text: Option<&String>| -> String {
+            let text: String = text
+                .map(|t| t.chars().take(column_width).collect())
+                .unwrap_or_default();
+            format!("{: <width$}", text, width = column_width)
+        };
+        let mut text: Vec<Line> = Vec::new();
+        if expectation.first_mismatch().is_none() {
+            text.push(Line::from(Span::styled(
+                "Output matches expected output",
+                Style::default().fg(Color::LightGreen),
+            )));
+        }
+        let skip = tool_pane.scroll_offset(area);
+        for (i, line) in expectation.lines.iter().enumerate().skip(skip) {
+            let bg_color = if tool_pane.focused && i == tool_pane.selection {
+                Color::DarkGray
+            } else {
+                Color::Reset
+            };
+            let (expected_color, actual_color) = if line.matches() {
+                (Color::Reset, Color::Reset)
+            } else {
+                (Color::LightGreen, Color::LightRed)
+            };
+            text.push(Line::from(vec![
+                Span::styled(
+                    fit(line.expected.as_ref()),
+                    Style::default().fg(expected_color).bg(bg_color),
+                ),
+                Span::styled(" │ ", Style::default().fg(Color::DarkGray).bg(bg_color)),
+                Span::styled(
+                    fit(line.actual.as_ref()),
+                    Style::default().fg(actual_color).bg(bg_color),
+                ),
+                Span::styled(
+                    line.input_line
+                        .map(|input_line| format!(" in:{}", input_line))
+                        .unwrap_or_default(),
+                    Style::default().fg(Color::Yellow).bg(bg_color),
+                ),
+            ]));
+        }
+        let paragraph = Paragraph::new(text).block(block_expectation);
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw prompt over the bottom of the screen.
+    fn draw_prompt(f: &mut Frame, prompt: &Prompt, screen: Rect) {
+        let height = min(3, screen.height);
+        let area = Rect::new(
+            screen.x,
+            screen.y + screen.height - height,
+            screen.width,
+            height,
+        );
+        let block = Block::default()
+            .title(prompt.kind.title())
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow));
+        let paragraph = Paragraph::new(Line::from(vec![
+            Span::raw(prompt.text.as_str()),
+            Span::styled("█", Style::default().fg(Color::Yellow)),
+        ]))
+        .block(block);
+        f.render_widget(Clear, area);
+        f.render_widget(paragraph, area);
+    }
+
     /// Draw regex. This either prints "No matches" in dark gray, italics if there are no matches,
     /// or prints all the matches with their capture group number beforehand.
     fn draw_regex_space(f: &mut Frame, regex_space: &[String], area: Rect) {
@@ -282,19 +1617,596 @@ impl<'a> Tui<'a> {
             )]));
         } else {
             for (i, m) in regex_space.iter().enumerate() {
+                // Groups have the same colors as in regex breakdown
+                let color = if i == 0 {
+                    Color::DarkGray
+                } else {
+                    Tui::group_color(i)
+                };
+                text.push(Line::from(vec![
+                    Span::styled(format!("\n\\{}    ", i), Style::default().fg(color)),
+                    Span::raw(m),
+                ]));
+            }
+        }
+        let paragraph = Paragraph::new(text)
+            .block(block_regex_space)
+            .wrap(Wrap { trim: false });
+        f.render_widget(paragraph, area);
+    }
+
+    /// Color of capture group, so the group can be found in regex breakdown, regex matches
+    /// and replacement.
+    fn group_color(group: usize) -> Color {
+        const GROUP_COLORS: [Color; 6] = [
+            Color::LightCyan,
+            Color::LightMagenta,
+            Color::LightYellow,
+            Color::LightGreen,
+            Color::LightBlue,
+            Color::LightRed,
+        ];
+        GROUP_COLORS[(group + GROUP_COLORS.len() - 1) % GROUP_COLORS.len()]
+    }
+
+    /// Draw regexes of the command on given line of the program as trees of groups, classes,
+    /// anchors, quantifiers and back-references. Groups are numbered and colored the same way
+    /// as in regex matches, and so are references to them in replacement.
+    fn draw_regex_breakdown(
+        f: &mut Frame,
+        program: &[Option<Script>],
+        line: usize,
+        extended_regex: bool,
+        area: Rect,
+    ) {
+        let command = program
+            .get(line)
+            .and_then(Option::as_ref)
+            .and_then(|script| script.commands.first())
+            .map(unescape_command_regexes);
+        let syntax = if extended_regex { "ERE" } else { "BRE" };
+        let mut text: Vec<Line> = Vec::new();
+        if let Some(command) = &command {
+            for regex in command.regexes() {
+                let pattern = &regex.pattern.text;
+                let tree = RegexTree::parse(pattern, extended_regex);
+                let chars: Vec<char> = pattern.chars().collect();
+                if !text.is_empty() {
+                    text.push(Line::from(""));
+                }
                 text.push(Line::from(vec![
                     Span::styled(
-                        format!("\n\\{}    ", i),
+                        format!("/{}/{}", pattern, regex.flags),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(
+                        match tree.groups {
+                            1 => format!("  {}, 1 group", syntax),
+                            groups => format!("  {}, {} groups", syntax, groups),
+                        },
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                ]));
+                if pattern.is_empty() {
+                    text.push(Line::from(Span::styled(
+                        "Empty regex matches the last regex used",
+                        Style::default().fg(Color::DarkGray),
+                    )));
+                }
+                Tui::regex_breakdown_lines(&chars, &tree.alternatives, "", &mut text);
+            }
+            if let Argument::Substitute { replacement, .. } = &command.argument {
+                let mut spans = vec![Span::styled(
+                    "Replacement ",
+                    Style::default().add_modifier(Modifier::BOLD),
+                )];
+                let mut chars = replacement.text.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match (c, chars.peek()) {
+                        ('\\', Some(digit @ '1'..='9')) => {
+                            let group = *digit as usize - '0' as usize;
+                            spans.push(Span::styled(
+                                format!("\\{}", digit),
+                                Style::default()
+                                    .fg(Tui::group_color(group))
+                                    .add_modifier(Modifier::BOLD),
+                            ));
+                            chars.next();
+                        }
+                        ('&', _) => spans.push(Span::styled(
+                            "&",
+                            Style::default().add_modifier(Modifier::BOLD),
+                        )),
+                        (c, _) => spans.push(Span::raw(c.to_string())),
+                    }
+                }
+                text.push(Line::from(""));
+                text.push(Line::from(spans));
+            }
+        }
+        if text.is_empty() {
+            text.push(Line::from(Span::styled(
+                "No regex here",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            )));
+        }
+        let paragraph = Paragraph::new(text)
+            .block(Block::default().title(" Regex ").borders(Borders::ALL))
+            .wrap(Wrap { trim: false });
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw commands run in REPL as a branch of execution: each executed command with pattern
+    /// space after it, and hold space and output if the command changed them.
+    fn draw_repl(
+        f: &mut Frame,
+        debugger: &Debugger,
+        repl: Option<&(usize, String, Result<Branch, String>)>,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let mut text: Vec<Line> = Vec::new();
+        let (state, commands, branch) = match repl {
+            Some(repl) => repl,
+            None => {
+                text.push(Line::from(Span::styled(
+                    "Press : and type commands to run from the current state",
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::DarkGray),
+                )));
+                f.render_widget(Paragraph::new(text).block(tool_pane.block(" REPL ")), area);
+                return;
+            }
+        };
+        let mut header = vec![Span::styled(
+            format!(":{}", commands),
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        match branch {
+            Err(error) => {
+                text.push(Line::from(header));
+                text.push(Line::from(Span::styled(
+                    format!("sed: {}", error),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            Ok(branch) => {
+                header.push(Span::styled(
+                    format!("  from state {}, {} steps", state, branch.steps.len()),
+                    Style::default().fg(Color::DarkGray),
+                ));
+                if branch.stopped {
+                    header.push(Span::styled(
+                        "  stopped, looks like infinite loop",
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                text.push(Line::from(header));
+                let width = branch
+                    .steps
+                    .iter()
+                    .map(|step| step.command.chars().count())
+                    .max()
+                    .unwrap_or(0);
+                // Header takes one of the lines
+                let mut scroll_area = area;
+                scroll_area.height = scroll_area.height.saturating_sub(1);
+                let skip = tool_pane.scroll_offset(scroll_area);
+                let mut previous_hold = debugger
+                    .peek_at_state(*state)
+                    .map(|state| &state.hold_buffer);
+                for (i, step) in branch.steps.iter().enumerate() {
+                    let hold_changed = previous_hold != Some(&step.hold_buffer);
+                    previous_hold = Some(&step.hold_buffer);
+                    if i < skip {
+                        continue;
+                    }
+                    let selected = tool_pane.focused && i == tool_pane.selection;
+                    text.push(Line::from(Tui::branch_step_spans(
+                        step,
+                        width,
+                        hold_changed,
+                        selected,
+                    )));
+                }
+            }
+        }
+        let paragraph = Paragraph::new(text).block(tool_pane.block(" REPL "));
+        f.render_widget(paragraph, area);
+    }
+
+    /// Draw the script run again from a state with edited pattern or hold space, compared to
+    /// the original execution from the same state. Steps where the fork runs a different
+    /// command, or ends up with different pattern space, show what the original did.
+    fn draw_what_if(
+        f: &mut Frame,
+        debugger: &Debugger,
+        what_if: Option<&(usize, bool, Result<Branch, String>)>,
+        tool_pane: &ToolPaneState,
+        area: Rect,
+    ) {
+        let mut text: Vec<Line> = Vec::new();
+        let (state, whole_input, branch) = match what_if {
+            Some((state, whole_input, branch)) => (*state, *whole_input, branch),
+            None => {
+                text.push(Line::from(Span::styled(
+                    "Press w or W to edit pattern or hold space and run the script again",
+                    Style::default()
+                        .add_modifier(Modifier::ITALIC)
+                        .fg(Color::DarkGray),
+                )));
+                f.render_widget(
+                    Paragraph::new(text).block(tool_pane.block(" What if ")),
+                    area,
+                );
+                return;
+            }
+        };
+        let mut header = vec![Span::styled(
+            format!(
+                "Fork of state {}, rest of {}",
+                state,
+                if whole_input { "input" } else { "cycle" }
+            ),
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        let branch = match branch {
+            Ok(branch) => branch,
+            Err(error) => {
+                text.push(Line::from(header));
+                text.push(Line::from(Span::styled(
+                    format!("sed: {}", error),
+                    Style::default().fg(Color::Red),
+                )));
+                f.render_widget(
+                    Paragraph::new(text).block(tool_pane.block(" What if ")),
+                    area,
+                );
+                return;
+            }
+        };
+        // Step of the fork is compared to the same step of the original, and pattern space
+        // after it to the original state that follows
+        let differs = |i: usize, step: &BranchStep| {
+            let original = debugger.peek_at_state(state + i);
+            let after = debugger.peek_at_state(state + i + 1);
+            original.map(|s| s.current_line) != step.line
+                || after.map(|s| &s.pattern_buffer) != Some(&step.pattern_buffer)
+        };
+        let first_difference = branch
+            .steps
+            .iter()
+            .enumerate()
+            .position(|(i, step)| differs(i, step));
+        header.push(Span::styled(
+            match first_difference {
+                Some(i) => format!(
+                    ", {} steps, differs from step {}",
+                    branch.steps.len(),
+                    i + 1
+                ),
+                None => format!(", {} steps, same as original", branch.steps.len()),
+            },
+            Style::default().fg(Color::DarkGray),
+        ));
+        if branch.stopped {
+            header.push(Span::styled(
+                "  stopped, looks like infinite loop",
+                Style::default().fg(Color::Red),
+            ));
+        }
+        text.push(Line::from(header));
+        let width = branch
+            .steps
+            .iter()
+            .map(|step| step.command.chars().count())
+            .max()
+            .unwrap_or(0);
+        // Header takes one of the lines
+        let mut scroll_area = area;
+        scroll_area.height = scroll_area.height.saturating_sub(1);
+        let skip = tool_pane.scroll_offset(scroll_area);
+        let mut previous_hold = debugger
+            .peek_at_state(state)
+            .map(|state| &state.hold_buffer);
+        for (i, step) in branch.steps.iter().enumerate() {
+            let hold_changed = previous_hold != Some(&step.hold_buffer);
+            previous_hold = Some(&step.hold_buffer);
+            if i < skip {
+                continue;
+            }
+            let selected = tool_pane.focused && i == tool_pane.selection;
+            let mut spans = vec![Span::styled(
+                format!("{: <4}", i + 1),
+                Style::default().fg(if differs(i, step) {
+                    Color::Red
+                } else {
+                    Color::DarkGray
+                }),
+            )];
+            spans.extend(Tui::branch_step_spans(step, width, hold_changed, selected));
+            let original = debugger.peek_at_state(state + i);
+            let after = debugger.peek_at_state(state + i + 1);
+            match (original, after) {
+                (None, _) => spans.push(Span::styled(
+                    "  original ended",
+                    Style::default().fg(Color::Red),
+                )),
+                (Some(original), _) if Some(original.current_line) != step.line => {
+                    spans.push(Span::styled(
+                        "  original ran ",
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                    spans.push(Span::styled(
+                        debugger
+                            .source_code
+                            .get(original.current_line)
+                            .map(|command| command.trim())
+                            .unwrap_or_default(),
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                (_, Some(after)) if after.pattern_buffer != step.pattern_buffer => {
+                    spans.push(Span::styled(
+                        "  original had ",
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                    spans.push(Span::styled(
+                        after.pattern_buffer.as_str(),
+                        Style::default().fg(Color::Red),
+                    ));
+                }
+                _ => {}
+            }
+            text.push(Line::from(spans));
+        }
+        let paragraph = Paragraph::new(text).block(tool_pane.block(" What if "));
+        f.render_widget(paragraph, area);
+    }
+
+    /// Spans of one step of a branch: the command padded to given width, pattern space after
+    /// it, and hold space and output, if the command changed them.
+    fn branch_step_spans(
+        step: &BranchStep,
+        width: usize,
+        hold_changed: bool,
+        selected: bool,
+    ) -> Vec<Span<'_>> {
+        let bg_color = if selected {
+            Color::DarkGray
+        } else {
+            Color::Reset
+        };
+        let mut spans = vec![
+            Span::styled(
+                format!("{: <width$}  ", step.command, width = width),
+                Style::default().fg(Color::Yellow).bg(bg_color),
+            ),
+            Span::styled(step.pattern_buffer.as_str(), Style::default().bg(bg_color)),
+        ];
+        if hold_changed {
+            spans.push(Span::styled(
+                "  hold ",
+                Style::default().fg(Color::DarkGray),
+            ));
+            spans.push(Span::styled(
+                step.hold_buffer.as_str(),
+                Style::default().fg(Color::LightBlue),
+            ));
+        }
+        if !step.output.is_empty() {
+            spans.push(Span::styled(
+                "  prints ",
+                Style::default().fg(Color::DarkGray),
+            ));
+            spans.push(Span::styled(
+                step.output.join("\\n"),
+                Style::default().fg(Color::LightGreen),
+            ));
+        }
+        spans
+    }
+
+    /// Draw pattern space with everything regex typed by user matched. Groups of the first
+    /// match are colored the same way as in regex matches, and listed under pattern space.
+    fn draw_regex_test(
+        f: &mut Frame,
+        query: &str,
+        test: Option<&Result<RegexTest, String>>,
+        area: Rect,
+    ) {
+        let mut text: Vec<Line> = Vec::new();
+        match test {
+            _ if query.is_empty() => text.push(Line::from(Span::styled(
+                "Press / and type a regex, or regex/replacement/flags, to try it on pattern space",
+                Style::default()
+                    .add_modifier(Modifier::ITALIC)
+                    .fg(Color::DarkGray),
+            ))),
+            None => {}
+            Some(Err(error)) => {
+                text.push(Line::from(Span::styled(
+                    format!("/{}", query),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )));
+                text.push(Line::from(Span::styled(
+                    format!("sed: {}", error),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            Some(Ok(test)) => {
+                text.push(Line::from(vec![
+                    Span::styled(
+                        format!("/{}", query),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(
+                        match test.matches.len() {
+                            1 => String::from("  1 match"),
+                            matches => format!("  {} matches", matches),
+                        },
                         Style::default().fg(Color::DarkGray),
                     ),
-                    Span::raw(m),
                 ]));
+                let chars: Vec<char> = test.pattern_space.chars().collect();
+                let mut styles = vec![Style::default(); chars.len()];
+                for range in &test.matches {
+                    for style in &mut styles[range.clone()] {
+                        *style = style.add_modifier(Modifier::UNDERLINED | Modifier::BOLD);
+                    }
+                }
+                // Inner groups are drawn over outer ones
+                for (group, range) in test.groups.iter().enumerate().skip(1) {
+                    if let Some(range) = range {
+                        for style in &mut styles[range.clone()] {
+                            *style = style.fg(Tui::group_color(group));
+                        }
+                    }
+                }
+                text.push(Line::from(Tui::styled_spans(&chars, &styles)));
+                text.push(Line::from(""));
+                for (group, range) in test.groups.iter().enumerate() {
+                    let color = if group == 0 {
+                        Color::DarkGray
+                    } else {
+                        Tui::group_color(group)
+                    };
+                    let matched = match range {
+                        Some(range) => Span::raw(chars[range.clone()].iter().collect::<String>()),
+                        None => Span::styled(
+                            "(didn't take part in the match)",
+                            Style::default()
+                                .add_modifier(Modifier::ITALIC)
+                                .fg(Color::DarkGray),
+                        ),
+                    };
+                    text.push(Line::from(vec![
+                        Span::styled(format!("\\{}    ", group), Style::default().fg(color)),
+                        matched,
+                    ]));
+                }
+                if let Some(result) = &test.result {
+                    text.push(Line::from(""));
+                    text.push(Line::from(vec![
+                        Span::styled("Result ", Style::default().add_modifier(Modifier::BOLD)),
+                        Span::raw(result.as_str()),
+                    ]));
+                }
+            }
+        }
+        let paragraph = Paragraph::new(text)
+            .block(
+                Block::default()
+                    .title(" Regex tester ")
+                    .borders(Borders::ALL),
+            )
+            .wrap(Wrap { trim: false });
+        f.render_widget(paragraph, area);
+    }
+
+    /// Add lines of regex breakdown, drawn as a tree with given prefix. Alternatives, if there
+    /// are more, are nodes of their own.
+    fn regex_breakdown_lines(
+        chars: &[char],
+        alternatives: &[Vec<Element>],
+        prefix: &str,
+        text: &mut Vec<Line>,
+    ) {
+        let items: Vec<BreakdownItem> = if alternatives.len() == 1 {
+            Tui::breakdown_items(chars, &alternatives[0])
+        } else {
+            alternatives
+                .iter()
+                .enumerate()
+                .map(|(i, elements)| BreakdownItem {
+                    source: match (elements.first(), elements.last()) {
+                        (Some(first), Some(last)) => chars[first.start..last.end].iter().collect(),
+                        _ => String::new(),
+                    },
+                    description: format!("alternative {}", i + 1),
+                    group: None,
+                    children: Some(std::slice::from_ref(elements)),
+                })
+                .collect()
+        };
+        let count = items.len();
+        for (i, item) in items.into_iter().enumerate() {
+            let last = i + 1 == count;
+            let style = match item.group {
+                Some(group) => Style::default().fg(Tui::group_color(group)),
+                None => Style::default(),
+            };
+            text.push(Line::from(vec![
+                Span::styled(
+                    format!("{}{}", prefix, if last { "└ " } else { "├ " }),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                Span::styled(item.source, style),
+                Span::styled(
+                    format!("  {}", item.description),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ]));
+            if let Some(children) = item.children {
+                let prefix = format!("{}{}", prefix, if last { "  " } else { "│ " });
+                Tui::regex_breakdown_lines(chars, children, &prefix, text);
             }
         }
-        let paragraph = Paragraph::new(text)
-            .block(block_regex_space)
-            .wrap(Wrap { trim: false });
-        f.render_widget(paragraph, area);
+    }
+
+    /// Items of regex breakdown for elements matched one after another. Runs of literal
+    /// characters are joined into one item.
+    fn breakdown_items<'t>(chars: &[char], elements: &'t [Element]) -> Vec<BreakdownItem<'t>> {
+        let mut items = Vec::new();
+        // Start, end and text of literal characters in a row
+        let mut literal: Option<(usize, usize, String)> = None;
+        for element in elements {
+            if let (Node::Literal(c), None) = (&element.node, &element.quantifier) {
+                let run = literal.get_or_insert((element.start, element.start, String::new()));
+                run.1 = element.end;
+                run.2.push(*c);
+                continue;
+            }
+            if let Some((start, end, run)) = literal.take() {
+                items.push(BreakdownItem::literal(chars, start, end, &run));
+            }
+            let source = chars[element.start..element.end].iter().collect();
+            items.push(match &element.node {
+                Node::Group {
+                    number,
+                    alternatives,
+                } => BreakdownItem {
+                    source,
+                    description: match &element.quantifier {
+                        Some(quantifier) => {
+                            format!("group \\{}, {}", number, describe_quantifier(quantifier))
+                        }
+                        None => format!("group \\{}", number),
+                    },
+                    group: Some(*number),
+                    children: Some(alternatives),
+                },
+                Node::BackReference(number) => BreakdownItem {
+                    source,
+                    description: describe_element(element),
+                    group: Some(*number),
+                    children: None,
+                },
+                _ => BreakdownItem {
+                    source,
+                    description: describe_element(element),
+                    group: None,
+                    children: None,
+                },
+            });
+        }
+        if let Some((start, end, run)) = literal {
+            items.push(BreakdownItem::literal(chars, start, end, &run));
+        }
+        items
     }
 
     /// Draw simple text in area, wrapping, with light blue fg color. Do nothing else.
@@ -311,6 +2223,472 @@ impl<'a> Tui<'a> {
         f.render_widget(paragraph, area);
     }
 
+    /// Handle key press while tool pane is focused.
+    fn handle_tool_pane_key(&mut self, event: KeyEvent) {
+        let item_count = match self.tool_pane.kind {
+            Some(ToolPane::Breakpoints) => self.breakpoints.len(),
+            Some(ToolPane::Log) => self.log_messages.len(),
+            Some(ToolPane::Diff) => self
+                .comparison
+                .as_ref()
+                .map(|c| c.divergences.len() + c.output_diff.len())
+                .unwrap_or(0),
+            Some(ToolPane::Expectation) => self
+                .expectation
+                .as_ref()
+                .map(|e| e.lines.len())
+                .unwrap_or(0),
+            Some(ToolPane::Profile) => ProfileEntry::count(&self.profile),
+            Some(ToolPane::Repl) => match &self.repl {
+                Some((_, _, Ok(branch))) => branch.steps.len(),
+                _ => 0,
+            },
+            Some(ToolPane::WhatIf) => match &self.what_if {
+                Some((_, _, Ok(branch))) => branch.steps.len(),
+                _ => 0,
+            },
+            Some(ToolPane::Explain)
+            | Some(ToolPane::Regex)
+            | Some(ToolPane::RegexTester)
+            | None => 0,
+        };
+        let count = Tui::get_pressed_key_buffer_as_number(&self.pressed_keys_buffer, 1) as isize;
+        match event.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.tool_pane.move_selection(count, item_count);
+                self.follow_what_if_step();
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.tool_pane.move_selection(-count, item_count);
+                self.follow_what_if_step();
+            }
+            // Return focus back to source code
+            KeyCode::Esc => {
+                self.tool_pane.focused = false;
+            }
+            KeyCode::Char(digit @ '0'..='9') => {
+                self.pressed_keys_buffer.push(digit);
+                return;
+            }
+            _ => match self.tool_pane.kind {
+                Some(ToolPane::Breakpoints) => self.handle_breakpoint_pane_key(event),
+                Some(ToolPane::Log) => self.handle_log_pane_key(event),
+                Some(ToolPane::Diff) => self.handle_diff_pane_key(event),
+                Some(ToolPane::Expectation) => self.handle_expectation_pane_key(event),
+                Some(ToolPane::Profile) => self.handle_profile_pane_key(event),
+                Some(ToolPane::Repl) => self.handle_repl_pane_key(event),
+                Some(ToolPane::WhatIf) => self.handle_what_if_pane_key(event),
+                Some(ToolPane::Explain)
+                | Some(ToolPane::Regex)
+                | Some(ToolPane::RegexTester)
+                | None => {}
+            },
+        }
+        self.pressed_keys_buffer.clear();
+    }
+
+    /// Handle key press while breakpoint manager is focused.
+    fn handle_breakpoint_pane_key(&mut self, event: KeyEvent) {
+        let selection = self.tool_pane.selection;
+        match event.code {
+            // Jump to breakpoint
+            KeyCode::Enter => {
+                if let Some(bp) = self.breakpoints.nth(selection) {
+                    self.cursor = bp.line;
+                    self.tool_pane.focused = false;
+                    self.use_execution_pointer_as_focus_line = false;
+                }
+            }
+            // Delete breakpoint
+            KeyCode::Char('d') | KeyCode::Delete => {
+                if let Some(line) = self.breakpoints.nth(selection).map(|bp| bp.line) {
+                    self.breakpoints.remove(line);
+                    self.tool_pane.move_selection(0, self.breakpoints.len());
+                }
+            }
+            // Enable or disable breakpoint
+            KeyCode::Char(' ') | KeyCode::Char('e') => {
+                if let Some(bp) = self.breakpoints.nth_mut(selection) {
+                    bp.enabled = !bp.enabled;
+                }
+            }
+            // Ignore next N hits, where N is typed number. Without number, stop ignoring.
+            KeyCode::Char('i') => {
+                let ignore_count =
+                    Tui::get_pressed_key_buffer_as_number(&self.pressed_keys_buffer, 0);
+                if let Some(bp) = self.breakpoints.nth_mut(selection) {
+                    bp.ignore_until = if ignore_count > 0 {
+                        Tui::hit_count(&self.debugger, &self.line_hits, bp.line, self.current_state)
+                            + ignore_count
+                    } else {
+                        0
+                    };
+                }
+            }
+            // Close breakpoint manager
+            KeyCode::Char('B') => {
+                self.tool_pane.toggle(ToolPane::Breakpoints);
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle key press while logpoint messages are focused.
+    fn handle_log_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Jump to state that produced the message
+            KeyCode::Enter => {
+                if let Some(message) = self.log_messages.get(self.tool_pane.selection) {
+                    self.current_state = message.state;
+                    self.tool_pane.focused = false;
+                    self.use_execution_pointer_as_focus_line = true;
+                }
+            }
+            // Close log
+            KeyCode::Char('L') => {
+                self.tool_pane.toggle(ToolPane::Log);
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Move cursor to command of selected step of what-if fork, so it can be stepped through.
+    fn follow_what_if_step(&mut self) {
+        if self.tool_pane.kind != Some(ToolPane::WhatIf) {
+            return;
+        }
+        if let Some((_, _, Ok(branch))) = &self.what_if {
+            if let Some(line) = branch
+                .steps
+                .get(self.tool_pane.selection)
+                .and_then(|step| step.line)
+            {
+                self.cursor = line;
+                self.use_execution_pointer_as_focus_line = false;
+            }
+        }
+    }
+
+    /// Handle key press while what-if fork is focused.
+    fn handle_what_if_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Jump to the original state the selected step is compared to
+            KeyCode::Enter => {
+                if let Some((state, _, _)) = &self.what_if {
+                    self.current_state = min(
+                        state + self.tool_pane.selection,
+                        self.debugger.count_of_states() - 1,
+                    );
+                    self.tool_pane.focused = false;
+                    self.use_execution_pointer_as_focus_line = true;
+                }
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle key press while REPL is focused.
+    fn handle_repl_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Edit commands and run them again
+            KeyCode::Char(':') => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Repl,
+                    text: self
+                        .repl
+                        .as_ref()
+                        .map(|(_, commands, _)| commands.clone())
+                        .unwrap_or_default(),
+                });
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle key press while comparison with previous execution is focused.
+    fn handle_diff_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Jump to state where selected input line started to be processed differently
+            KeyCode::Enter => {
+                let target = self
+                    .comparison
+                    .as_ref()
+                    .and_then(|c| c.divergences.get(self.tool_pane.selection))
+                    .and_then(|d| d.state_after);
+                if let Some(target) = target {
+                    self.current_state = target;
+                    self.tool_pane.focused = false;
+                    self.use_execution_pointer_as_focus_line = true;
+                }
+            }
+            KeyCode::Char('D') => {
+                self.tool_pane.toggle(ToolPane::Diff);
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle key press while comparison with expected output is focused.
+    fn handle_expectation_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Jump to state that printed selected line
+            KeyCode::Enter => {
+                let target = self
+                    .expectation
+                    .as_ref()
+                    .and_then(|e| e.lines.get(self.tool_pane.selection))
+                    .and_then(|line| line.state);
+                if let Some(target) = target {
+                    self.current_state = target;
+                    self.tool_pane.focused = false;
+                    self.use_execution_pointer_as_focus_line = true;
+                }
+            }
+            // Select next wrong line
+            KeyCode::Char('n') => {
+                if let Some(expectation) = &self.expectation {
+                    if let Some(next) = expectation
+                        .lines
+                        .iter()
+                        .enumerate()
+                        .skip(self.tool_pane.selection + 1)
+                        .find(|(_, line)| !line.matches())
+                        .map(|(i, _)| i)
+                    {
+                        self.tool_pane.selection = next;
+                    }
+                }
+            }
+            KeyCode::Char('E') => {
+                self.tool_pane.toggle(ToolPane::Expectation);
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle key press while profile is focused.
+    fn handle_profile_pane_key(&mut self, event: KeyEvent) {
+        match event.code {
+            // Jump to selected cycle, or move cursor to selected loop or line
+            KeyCode::Enter => {
+                match ProfileEntry::nth(&self.profile, self.tool_pane.selection) {
+                    Some(ProfileEntry::Cycle(first_state)) => {
+                        self.current_state = first_state;
+                        self.use_execution_pointer_as_focus_line = true;
+                    }
+                    Some(ProfileEntry::Line(line)) => {
+                        self.cursor = line;
+                        self.use_execution_pointer_as_focus_line = false;
+                    }
+                    None => return,
+                }
+                self.tool_pane.focused = false;
+            }
+            KeyCode::Char('P') => {
+                self.tool_pane.toggle(ToolPane::Profile);
+            }
+            KeyCode::Char('q') => {
+                self.tool_pane.focused = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// Edit the script.
+    fn handle_editor_key(&mut self, event: KeyEvent) {
+        // UNWRAP: This is called only while editing
+        let editor = self.editor.as_mut().unwrap();
+        let control = event.modifiers.contains(KeyModifiers::CONTROL);
+        // Any other key cancels discarding
+        let confirmed = std::mem::take(&mut editor.confirm_discard);
+        match event.code {
+            KeyCode::Esc if editor.modified && !confirmed => editor.confirm_discard = true,
+            KeyCode::Esc => self.editor = None,
+            KeyCode::Char('s') if control => self.save_script(),
+            KeyCode::Char('k') if control => editor.delete_line(),
+            KeyCode::Char(c) if !control => editor.insert(c),
+            KeyCode::Tab => editor.insert('\t'),
+            KeyCode::Enter => editor.insert_line_break(),
+            KeyCode::Backspace => editor.delete_backward(),
+            KeyCode::Delete => editor.delete_forward(),
+            KeyCode::Left => editor.move_left(),
+            KeyCode::Right => editor.move_right(),
+            KeyCode::Up => editor.move_vertically(-1),
+            KeyCode::Down => editor.move_vertically(1),
+            KeyCode::PageUp => editor.move_vertically(-10),
+            KeyCode::PageDown => editor.move_vertically(10),
+            KeyCode::Home => editor.move_home(),
+            KeyCode::End => editor.move_end(),
+            _ => {}
+        }
+    }
+
+    /// Save the edited script and run it again, unless sed refuses it. The editor closes
+    /// once the new execution is shown.
+    fn save_script(&mut self) {
+        // UNWRAP: This is called only while editing
+        let editor = self.editor.as_mut().unwrap();
+        let text = editor.text();
+        if let Err(error) = self.evaluator.check_script(&text) {
+            editor.error = Some(error);
+            return;
+        }
+        if let Err(error) = fs::write(&self.settings.sed_script, &text) {
+            editor.error = Some((None, format!("Failed to save script: {}", error)));
+            return;
+        }
+        self.saved_files = Some((text, Tui::modification_time(&self.settings.input_file)));
+        match self.rerun() {
+            Ok(()) => self.editor = None,
+            Err(error) => {
+                if let Some(editor) = self.editor.as_mut() {
+                    editor.error = Some((None, format!("{:#}", error)));
+                }
+            }
+        }
+    }
+
+    /// When was the file modified last time, if it can be told.
+    fn modification_time(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Are the script and input the same as when the editor saved the script?
+    fn files_match_saved(&self) -> bool {
+        match &self.saved_files {
+            Some((script, input_modified)) => {
+                fs::read_to_string(&self.settings.sed_script).ok().as_ref() == Some(script)
+                    && Tui::modification_time(&self.settings.input_file) == *input_modified
+            }
+            None => false,
+        }
+    }
+
+    /// Handle key press while prompt is open.
+    fn handle_prompt_key(&mut self, event: KeyEvent) {
+        // UNWRAP: This is called only when prompt is open
+        let prompt = self.prompt.as_mut().unwrap();
+        match event.code {
+            KeyCode::Char(c) => prompt.text.push(c),
+            KeyCode::Backspace => {
+                prompt.text.pop();
+            }
+            KeyCode::Tab => {
+                if let PromptKind::WhatIf { whole_input, .. } = &mut prompt.kind {
+                    *whole_input = !*whole_input;
+                }
+            }
+            KeyCode::Esc => {
+                // Giving up on testing regex or REPL closes its results too
+                if let Some(PromptKind::RegexTester | PromptKind::Repl) =
+                    self.prompt.take().map(|p| p.kind)
+                {
+                    self.tool_pane.kind = None;
+                    self.tool_pane.focused = false;
+                }
+                return;
+            }
+            KeyCode::Enter => {
+                // UNWRAP: See above
+                let prompt = self.prompt.take().unwrap();
+                match prompt.kind {
+                    PromptKind::Logpoint(line) => {
+                        self.logpoints.set(line, prompt.text);
+                        self.log_messages = self.logpoints.collect_messages(&self.debugger);
+                    }
+                    // Results stay open and follow pattern space while stepping
+                    PromptKind::RegexTester => {}
+                    PromptKind::Repl => {
+                        if let Some(state) = self.debugger.peek_at_state(self.current_state) {
+                            let branch = self.evaluator.run_commands(
+                                &state.pattern_buffer,
+                                &state.hold_buffer,
+                                &prompt.text,
+                            );
+                            // Select the last step, which has the result
+                            self.tool_pane.selection = match &branch {
+                                Ok(branch) => branch.steps.len().saturating_sub(1),
+                                Err(_) => 0,
+                            };
+                            self.repl = Some((self.current_state, prompt.text, branch));
+                            self.tool_pane.kind = Some(ToolPane::Repl);
+                            self.tool_pane.focused = true;
+                        }
+                    }
+                    PromptKind::WhatIf { hold, whole_input } => {
+                        if let Some(state) = self.debugger.peek_at_state(self.current_state) {
+                            let (pattern_buffer, hold_buffer) = if hold {
+                                (state.pattern_buffer.as_str(), prompt.text.as_str())
+                            } else {
+                                (prompt.text.as_str(), state.hold_buffer.as_str())
+                            };
+                            let branch = match &self.source_map {
+                                Some(source_map) => self.evaluator.what_if(
+                                    &self.debugger,
+                                    source_map,
+                                    self.current_state,
+                                    pattern_buffer,
+                                    hold_buffer,
+                                    whole_input,
+                                ),
+                                None => Err(String::from("The original script couldn't be parsed")),
+                            };
+                            self.what_if = Some((self.current_state, whole_input, branch));
+                            self.tool_pane.kind = Some(ToolPane::WhatIf);
+                            self.tool_pane.focused = true;
+                            self.tool_pane.selection = 0;
+                        }
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+        // Regex is tested live, as user types it
+        if let PromptKind::RegexTester = prompt.kind {
+            self.regex_query = prompt.text.clone();
+        }
+    }
+
+    /// Match regex typed by user against pattern space of current state, unless it was
+    /// already done.
+    fn update_regex_test(&mut self) {
+        if self.tool_pane.kind != Some(ToolPane::RegexTester) || self.regex_query.is_empty() {
+            return;
+        }
+        if let Some((state, query, _)) = &self.regex_test {
+            if *state == self.current_state && *query == self.regex_query {
+                return;
+            }
+        }
+        if let Some(state) = self.debugger.peek_at_state(self.current_state) {
+            let test = self
+                .evaluator
+                .test_regex(&state.pattern_buffer, &self.regex_query);
+            self.regex_test = Some((self.current_state, self.regex_query.clone(), test));
+        }
+    }
+
     /// Use crossterm and stdout to restore terminal state.
     ///
     /// This shall be called on application exit.
@@ -345,10 +2723,13 @@ impl UiAgent for Tui<'_> {
         // Setup event loop and input handling
         let (tx, rx) = mpsc::channel();
         let tick_rate = Duration::from_millis(self.forced_refresh_rate);
-        let mut file_watcher = self.file_watcher;
+        let mut file_watcher = self
+            .file_watcher
+            .take()
+            .with_context(|| "UI was already started before.")?;
 
         // Thread that will send interrupt signals to UI thread (this one)
-        thread::spawn(move || {
+        let input_thread = thread::spawn(move || {
             let mut last_tick = Instant::now();
             loop {
                 // Oh we got an event from user
@@ -386,35 +2767,37 @@ impl UiAgent for Tui<'_> {
         self.terminal.clear().with_context(|| {
             "Failed to clear terminal during drawing state. Do you have modern term?"
         })?;
-        let mut use_execution_pointer_as_focus_line = false;
         let mut draw_memory: DrawMemory = DrawMemory::default();
 
         // UI thread that manages drawing
         loop {
-            let current_state = self.debugger.peek_at_state(self.current_state)
-                .with_context(||"We got ourselves into impossible state. This is logical error, please report a bug.")?;
-            let debugger = &self.debugger;
-            let line_number = current_state.current_line;
             // Wait for interrupt
             match rx.recv()? {
                 // Handle user input. Vi-like controls are available,
                 // including prefixing a command with number to execute it
                 // multiple times (in case of breakpoint toggles breakpoint on given line).
+                Interrupt::KeyPressed(event) if self.editor.is_some() => {
+                    self.handle_editor_key(event);
+                }
+                Interrupt::KeyPressed(event) if self.prompt.is_some() => {
+                    self.handle_prompt_key(event);
+                }
+                Interrupt::KeyPressed(event) if self.tool_pane.focused => {
+                    self.handle_tool_pane_key(event);
+                }
                 Interrupt::KeyPressed(event) => match event.code {
                     // Exit
                     KeyCode::Char('q') => {
-                        return Ok(ApplicationExitReason::UserExit);
+                        return Ok(ApplicationExitReason::UserExit(self.session()));
                     }
                     // Move cursor down
                     KeyCode::Char('j') | KeyCode::Down => {
                         for _ in
                             0..Tui::get_pressed_key_buffer_as_number(&self.pressed_keys_buffer, 1)
                         {
-                            if self.cursor < debugger.source_code.len() {
-                                self.cursor += 1;
-                            }
+                            self.move_cursor_down();
                         }
-                        use_execution_pointer_as_focus_line = false;
+                        self.use_execution_pointer_as_focus_line = false;
                         self.pressed_keys_buffer.clear();
                     }
                     // Move cursor up
@@ -422,23 +2805,21 @@ impl UiAgent for Tui<'_> {
                         for _ in
                             0..Tui::get_pressed_key_buffer_as_number(&self.pressed_keys_buffer, 1)
                         {
-                            if self.cursor > 0 {
-                                self.cursor -= 1;
-                            }
+                            self.move_cursor_up();
                         }
-                        use_execution_pointer_as_focus_line = false;
+                        self.use_execution_pointer_as_focus_line = false;
                         self.pressed_keys_buffer.clear();
                     }
                     // Go to top of file
                     KeyCode::Char('g') => {
                         self.cursor = 0;
-                        use_execution_pointer_as_focus_line = false;
+                        self.use_execution_pointer_as_focus_line = false;
                         self.pressed_keys_buffer.clear();
                     }
                     // Go to bottom of file
                     KeyCode::Char('G') => {
-                        self.cursor = debugger.source_code.len();
-                        use_execution_pointer_as_focus_line = false;
+                        self.cursor = self.debugger.source_code.len();
+                        self.use_execution_pointer_as_focus_line = false;
                         self.pressed_keys_buffer.clear();
                     }
                     // Toggle breakpoint on current line
@@ -450,23 +2831,187 @@ impl UiAgent for Tui<'_> {
                         } else {
                             breakpoint_target -= 1;
                         }
-                        if self.breakpoints.contains(&breakpoint_target) {
-                            self.breakpoints.remove(&breakpoint_target);
+                        self.breakpoints.toggle(breakpoint_target);
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Attach logpoint message to line under cursor
+                    KeyCode::Char('p') => {
+                        self.prompt = Some(Prompt {
+                            kind: PromptKind::Logpoint(self.cursor),
+                            text: self.logpoints.get(self.cursor).cloned().unwrap_or_default(),
+                        });
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Show messages collected by logpoints
+                    KeyCode::Char('L') => {
+                        self.tool_pane.toggle(ToolPane::Log);
+                        // Start at the last message that was already produced
+                        self.tool_pane.selection = self
+                            .log_messages
+                            .iter()
+                            .filter(|m| m.state <= self.current_state)
+                            .count()
+                            .saturating_sub(1);
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Show how many times was each line executed
+                    KeyCode::Char('c') => {
+                        self.show_coverage = !self.show_coverage;
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Switch between the program as sed prints it and the original script
+                    KeyCode::Char('o') => {
+                        self.show_original = !self.show_original && self.source_map.is_some();
+                        if !self.is_visible(self.cursor) {
+                            self.move_cursor_up();
+                        }
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Fold or unfold block under cursor
+                    KeyCode::Char('z') => {
+                        let block = self
+                            .blocks
+                            .iter()
+                            .filter(|(open, close)| *open <= self.cursor && self.cursor <= *close)
+                            .map(|(open, _)| *open)
+                            .max();
+                        if let Some(open) = block {
+                            if let Some(i) = self.folds.iter().position(|line| *line == open) {
+                                self.folds.remove(i);
+                            } else {
+                                self.folds.push(open);
+                                self.cursor = open;
+                            }
+                        }
+                        if !self.is_visible(self.cursor) {
+                            self.move_cursor_up();
+                        }
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Fold all blocks, or unfold them if any is folded
+                    KeyCode::Char('Z') => {
+                        if self.folds.is_empty() {
+                            self.folds = self.blocks.iter().map(|(open, _)| *open).collect();
+                        } else {
+                            self.folds.clear();
+                        }
+                        if !self.is_visible(self.cursor) {
+                            self.move_cursor_up();
+                        }
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Explain command under cursor or at execution pointer. The pane doesn't
+                    // take focus, so it follows cursor.
+                    KeyCode::Char('x') => {
+                        if self.tool_pane.kind == Some(ToolPane::Explain) {
+                            self.tool_pane.kind = None;
+                        } else {
+                            self.tool_pane.kind = Some(ToolPane::Explain);
+                        }
+                        self.tool_pane.focused = false;
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Break down regexes of command under cursor or at execution pointer.
+                    // Just like explanation, this follows cursor.
+                    KeyCode::Char('v') => {
+                        if self.tool_pane.kind == Some(ToolPane::Regex) {
+                            self.tool_pane.kind = None;
                         } else {
-                            self.breakpoints.insert(breakpoint_target);
+                            self.tool_pane.kind = Some(ToolPane::Regex);
+                        }
+                        self.tool_pane.focused = false;
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Try out a regex, or a replacement, on pattern space. Results follow
+                    // current state, just like pattern space.
+                    KeyCode::Char('/') => {
+                        self.prompt = Some(Prompt {
+                            kind: PromptKind::RegexTester,
+                            text: self.regex_query.clone(),
+                        });
+                        self.tool_pane.kind = Some(ToolPane::RegexTester);
+                        self.tool_pane.focused = false;
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Run any commands from current state, without touching the script
+                    KeyCode::Char(':') => {
+                        self.prompt = Some(Prompt {
+                            kind: PromptKind::Repl,
+                            text: self
+                                .repl
+                                .as_ref()
+                                .map(|(_, commands, _)| commands.clone())
+                                .unwrap_or_default(),
+                        });
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Edit pattern or hold space and run the script again from current state
+                    KeyCode::Char('w') | KeyCode::Char('W') => {
+                        let hold = event.code == KeyCode::Char('W');
+                        self.prompt = Some(Prompt {
+                            kind: PromptKind::WhatIf {
+                                hold,
+                                whole_input: false,
+                            },
+                            text: self
+                                .debugger
+                                .peek_at_state(self.current_state)
+                                .map(|state| {
+                                    if hold {
+                                        state.hold_buffer.clone()
+                                    } else {
+                                        state.pattern_buffer.clone()
+                                    }
+                                })
+                                .unwrap_or_default(),
+                        });
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Show where does the execution spend its steps
+                    KeyCode::Char('P') => {
+                        self.tool_pane.toggle(ToolPane::Profile);
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Compare with execution before the last reload
+                    KeyCode::Char('D') => {
+                        self.tool_pane.toggle(ToolPane::Diff);
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Compare output with expected output
+                    KeyCode::Char('E') => {
+                        self.tool_pane.toggle(ToolPane::Expectation);
+                        if let Some(first_mismatch) =
+                            self.expectation.as_ref().and_then(|e| e.first_mismatch())
+                        {
+                            self.tool_pane.selection = first_mismatch;
+                        }
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Jump to first state that differs from execution before the last reload
+                    KeyCode::Char('f') => {
+                        if let Some(target) =
+                            self.comparison.as_ref().and_then(|c| c.first_divergence())
+                        {
+                            self.current_state = target;
+                            self.use_execution_pointer_as_focus_line = true;
                         }
                         self.pressed_keys_buffer.clear();
                     }
+                    // Open breakpoint manager, or close it if it's already focused
+                    KeyCode::Char('B') => {
+                        self.tool_pane.toggle(ToolPane::Breakpoints);
+                        self.pressed_keys_buffer.clear();
+                    }
                     // Step forward
                     KeyCode::Char('s') => {
                         for _ in
                             0..Tui::get_pressed_key_buffer_as_number(&self.pressed_keys_buffer, 1)
                         {
-                            if self.current_state < debugger.count_of_states() - 1 {
+                            if self.current_state < self.debugger.count_of_states() - 1 {
                                 self.current_state += 1;
                             }
                         }
-                        use_execution_pointer_as_focus_line = true;
+                        self.use_execution_pointer_as_focus_line = true;
                         self.pressed_keys_buffer.clear();
                     }
                     // Step backwards
@@ -478,46 +3023,52 @@ impl UiAgent for Tui<'_> {
                                 self.current_state -= 1;
                             }
                         }
-                        use_execution_pointer_as_focus_line = true;
+                        self.use_execution_pointer_as_focus_line = true;
                         self.pressed_keys_buffer.clear();
                     }
                     // Run till end or breakpoint
                     KeyCode::Char('r') => {
-                        use_execution_pointer_as_focus_line = true;
+                        self.use_execution_pointer_as_focus_line = true;
                         self.pressed_keys_buffer.clear();
-                        while self.current_state < debugger.count_of_states() - 1 {
+                        while self.current_state < self.debugger.count_of_states() - 1 {
                             self.current_state += 1;
-                            if self.breakpoints.contains(
-                                &self
-                                    .debugger
-                                    .peek_at_state(self.current_state)
-                                    .unwrap()
-                                    .current_line,
-                            ) {
+                            if self.stops_at(self.current_state) {
                                 break;
                             }
                         }
                     }
                     // Same as 'r', but backwards
                     KeyCode::Char('R') => {
-                        use_execution_pointer_as_focus_line = true;
+                        self.use_execution_pointer_as_focus_line = true;
                         self.pressed_keys_buffer.clear();
                         while self.current_state > 0 {
                             self.current_state -= 1;
-                            if self.breakpoints.contains(
-                                &self
-                                    .debugger
-                                    .peek_at_state(self.current_state)
-                                    .unwrap()
-                                    .current_line,
-                            ) {
+                            if self.stops_at(self.current_state) {
                                 break;
                             }
                         }
                     }
+                    // Edit the script right here, starting at the line under cursor
+                    KeyCode::Char('e') => {
+                        let line = self.script_line();
+                        if let Ok(source) = fs::read_to_string(&self.settings.sed_script) {
+                            self.editor = Some(Editor::new(&source, line, self.settings.posix()));
+                        }
+                        self.pressed_keys_buffer.clear();
+                    }
+                    // Edit the script in $EDITOR. The editor reads keys from the same terminal,
+                    // so input thread has to stop first. It does once it can't send anything.
+                    KeyCode::Char('V') => {
+                        let line = self.script_line();
+                        let session = self.session();
+                        drop(rx);
+                        let _ = input_thread.join();
+                        return Ok(ApplicationExitReason::Edit(session, line, self.debugger));
+                    }
                     // Reload source code and try to enter current state again
                     KeyCode::Char('l') => {
-                        return Ok(ApplicationExitReason::Reload(self.current_state));
+                        let session = self.session();
+                        return Ok(ApplicationExitReason::Reload(session, self.debugger));
                     }
                     KeyCode::Char(other) => match other {
                         '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => {
@@ -532,55 +3083,97 @@ impl UiAgent for Tui<'_> {
                         self.pressed_keys_buffer.clear();
                     }
                 },
+                // Source code isn't shown while editing, so there is nothing to click or scroll
+                Interrupt::MouseEvent(_) if self.editor.is_some() => {}
                 Interrupt::MouseEvent(event) => match event.kind {
                     // Button pressed, mark current line as breakpoint
                     MouseEventKind::Up(_button) => {
-                        let target_breakpoint =
-                            (event.row - 1) as usize + draw_memory.current_startline;
-                        if self.breakpoints.contains(&target_breakpoint) {
-                            self.breakpoints.remove(&target_breakpoint);
-                        } else {
-                            self.breakpoints.insert(target_breakpoint);
+                        let area = draw_memory.source_code_area;
+                        // Ignore clicks outside of source code, such as into tool pane
+                        if event.row > area.y
+                            && event.row < area.y + area.height
+                            && event.column < area.x + area.width
+                        {
+                            let row =
+                                (event.row - area.y - 1) as usize + draw_memory.current_startline;
+                            if let Some(Some(line)) = draw_memory.row_lines.get(row) {
+                                self.breakpoints.toggle(*line);
+                            }
                         }
                     }
                     MouseEventKind::ScrollUp => {
-                        if self.cursor > 0 {
-                            self.cursor -= 1;
-                        }
-                        use_execution_pointer_as_focus_line = false;
+                        self.move_cursor_up();
+                        self.use_execution_pointer_as_focus_line = false;
                     }
                     MouseEventKind::ScrollDown => {
-                        if self.cursor < debugger.source_code.len() {
-                            self.cursor += 1;
-                        }
-                        use_execution_pointer_as_focus_line = false;
+                        self.move_cursor_down();
+                        self.use_execution_pointer_as_focus_line = false;
                     }
                     _ => {}
                 },
+                // The edited script would overwrite changes made elsewhere anyway
+                Interrupt::FileChanged if self.editor.is_some() => {}
+                // Script saved by the editor runs already
+                Interrupt::FileChanged if self.files_match_saved() => {}
                 Interrupt::FileChanged => {
-                    return Ok(ApplicationExitReason::Reload(self.current_state));
+                    let session = self.session();
+                    return Ok(ApplicationExitReason::Reload(session, self.debugger));
                 }
                 Interrupt::IntervalElapsed => {}
             }
             // Draw
-            let breakpoints = &self.breakpoints;
-            let cursor = self.cursor;
-            self.terminal.draw(|f| {
-                Tui::draw_layout_and_subcomponents(
-                    f,
-                    debugger,
-                    current_state,
-                    breakpoints,
-                    cursor,
-                    line_number,
-                    if use_execution_pointer_as_focus_line {
-                        line_number
-                    } else {
-                        cursor
-                    },
-                    &mut draw_memory,
-                )
-            })?;
+            self.update_regex_test();
+            let debugger = &self.debugger;
+            let current_state = debugger.peek_at_state(self.current_state)
+                .with_context(||"We got ourselves into impossible state. This is logical error, please report a bug.")?;
+            let line_number = current_state.current_line;
+            let data = DrawData {
+                debugger,
+                previous_debugger: self.previous_debugger.as_ref(),
+                comparison: self.comparison.as_ref(),
+                expectation: self.expectation.as_ref(),
+                profile: &self.profile,
+                diagnostics: &self.diagnostics,
+                program: &self.program,
+                blocks: &self.blocks,
+                folds: &self.folds,
+                extended_regex: self.settings.extended_regex(),
+                source_map: if self.show_original {
+                    self.source_map.as_ref()
+                } else {
+                    None
+                },
+                coverage: if self.show_coverage {
+                    Some(&self.coverage)
+                } else {
+                    None
+                },
+                state: current_state,
+                current_state: self.current_state,
+                breakpoints: &self.breakpoints,
+                line_hits: &self.line_hits,
+                logpoints: &self.logpoints,
+                log_messages: &self.log_messages,
+                tool_pane: &self.tool_pane,
+                prompt: self.prompt.as_ref(),
+                regex_query: &self.regex_query,
+                repl: self.repl.as_ref(),
+                what_if: self.what_if.as_ref(),
+                editor: self.editor.as_ref(),
+                regex_test: match &self.regex_test {
+                    Some((_, query, test)) if *query == self.regex_query => Some(test),
+                    _ => None,
+                },
+                cursor: self.cursor,
+                interpreter_line: line_number,
+                focused_line: if self.use_execution_pointer_as_focus_line {
+                    line_number
+                } else {
+                    self.cursor
+                },
+            };
+            self.terminal
+                .draw(|f| Tui::draw_layout_and_subcomponents(f, &data, &mut draw_memory))?;
         }
     }
 }
@@ -593,15 +3186,276 @@ enum Interrupt {
     IntervalElapsed,
 }
 
+/// Everything that is displayed in one frame.
+struct DrawData<'b> {
+    debugger: &'b Debugger,
+    previous_debugger: Option<&'b Debugger>,
+    comparison: Option<&'b TraceComparison>,
+    expectation: Option<&'b Expectation>,
+    /// Execution counts of lines, if they should be shown
+    coverage: Option<&'b Coverage>,
+    /// Problems found in the script by linter
+    diagnostics: &'b [Diagnostic],
+    /// Each line of the program parsed
+    program: &'b [Option<Script>],
+    /// `{...}` blocks of the program, as lines of the opening and the closing brace
+    blocks: &'b [(usize, usize)],
+    /// Lines opening blocks which are folded
+    folds: &'b [usize],
+    /// Are regexes extended, as with `sed -E`?
+    extended_regex: bool,
+    /// Original script, if it should be shown instead of the program
+    source_map: Option<&'b SourceMap>,
+    profile: &'b Profile,
+    state: &'b DebuggingState,
+    /// Number of the displayed state
+    current_state: usize,
+    breakpoints: &'b Breakpoints,
+    /// For each state, how many times was its line reached up to the state
+    line_hits: &'b [usize],
+    logpoints: &'b Logpoints,
+    log_messages: &'b [LogMessage],
+    tool_pane: &'b ToolPaneState,
+    prompt: Option<&'b Prompt>,
+    /// Regex typed by user to test on pattern space
+    regex_query: &'b str,
+    /// What the regex matched, or why sed couldn't use it
+    regex_test: Option<&'b Result<RegexTest, String>>,
+    /// Commands run in REPL, with the state they started from and what they did
+    repl: Option<&'b (usize, String, Result<Branch, String>)>,
+    /// Script run again with edited buffers, compared to the original execution
+    what_if: Option<&'b (usize, bool, Result<Branch, String>)>,
+    /// Script being edited, if it should be shown instead of source code
+    editor: Option<&'b Editor>,
+    /// Line (0-based) which user has selected via cursor
+    cursor: usize,
+    /// Line (0-based) which sed interpreter currently executes
+    interpreter_line: usize,
+    /// Line (0-based) which should be approximately at the center of the screen
+    focused_line: usize,
+}
+
+/// One row of source code pane. It shows a line of the program, or a line of the original
+/// script with any number of commands of the program on it.
+struct SourceRow<'b> {
+    /// Line number (0-based) shown in the gutter
+    number: usize,
+    /// Lines (0-based) of the program on this row
+    program_lines: Vec<usize>,
+    /// Guides of `{...}` blocks the row is in, drawn in the gutter
+    guides: String,
+    text: Vec<Span<'b>>,
+}
+
+/// One line of regex breakdown, see [Tui::draw_regex_breakdown].
+struct BreakdownItem<'t> {
+    /// Part of regex, as written
+    source: String,
+    description: String,
+    /// Group the item is or refers to, so it has the color of the group
+    group: Option<usize>,
+    /// Alternatives nested in the item, drawn under it
+    children: Option<&'t [Vec<Element>]>,
+}
+impl BreakdownItem<'_> {
+    /// Item for literal characters in a row, between given offsets of regex.
+    fn literal(chars: &[char], start: usize, end: usize, text: &str) -> Self {
+        BreakdownItem {
+            source: chars[start..end].iter().collect(),
+            description: format!("text {}", quote(text)),
+            group: None,
+            children: None,
+        }
+    }
+}
+
 /// This is currently used to remember last scroll
 /// position so screen doesn't wiggle as much.
 struct DrawMemory {
     current_startline: usize,
+    /// The first line of the edited script shown last time
+    editor_startline: usize,
+    /// Where was source code drawn last time. Used to map mouse clicks to lines.
+    source_code_area: Rect,
+    /// The first line of the program on each row of source code drawn last time
+    row_lines: Vec<Option<usize>>,
 }
 impl DrawMemory {
     fn default() -> Self {
         DrawMemory {
             current_startline: 0,
+            editor_startline: 0,
+            source_code_area: Rect::default(),
+            row_lines: Vec::new(),
+        }
+    }
+}
+
+/// Auxiliary panes that can be displayed under source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolPane {
+    /// List of breakpoints, see [Breakpoints]
+    Breakpoints,
+    /// Messages produced by logpoints, see [Logpoints]
+    Log,
+    /// Comparison with execution before the last reload, see [TraceComparison]
+    Diff,
+    /// Expected and actual output side by side, see [Expectation]
+    Expectation,
+    /// The most expensive cycles, loops and lines, see [Profile]
+    Profile,
+    /// Plain English description of the focused command, see [explain]
+    Explain,
+    /// Breakdown of regexes of the focused command, see [RegexTree]
+    Regex,
+    /// Regex typed by user matched against pattern space, see [Evaluator::test_regex]
+    RegexTester,
+    /// Commands typed by user run from the current state, see [Evaluator::run_commands]
+    Repl,
+    /// Script run again with edited pattern or hold space, see [Evaluator::what_if]
+    WhatIf,
+}
+impl ToolPane {
+    /// Name used to identify the pane in saved sessions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToolPane::Breakpoints => "breakpoints",
+            ToolPane::Log => "log",
+            ToolPane::Diff => "diff",
+            ToolPane::Expectation => "expect",
+            ToolPane::Profile => "profile",
+            ToolPane::Explain => "explain",
+            ToolPane::Regex => "regex",
+            ToolPane::RegexTester => "tester",
+            ToolPane::Repl => "repl",
+            ToolPane::WhatIf => "whatif",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ToolPane> {
+        match name {
+            "breakpoints" => Some(ToolPane::Breakpoints),
+            "log" => Some(ToolPane::Log),
+            "diff" => Some(ToolPane::Diff),
+            "expect" => Some(ToolPane::Expectation),
+            "profile" => Some(ToolPane::Profile),
+            "explain" => Some(ToolPane::Explain),
+            "regex" => Some(ToolPane::Regex),
+            "tester" => Some(ToolPane::RegexTester),
+            "repl" => Some(ToolPane::Repl),
+            "whatif" => Some(ToolPane::WhatIf),
+            _ => None,
+        }
+    }
+}
+
+/// What can be selected in profile. Items are ordered the same way they are drawn:
+/// cycles, then loops, then lines.
+enum ProfileEntry {
+    /// Cycle starting with given state
+    Cycle(usize),
+    /// Line of source code, the start of a loop or a hot line
+    Line(usize),
+}
+impl ProfileEntry {
+    fn count(profile: &Profile) -> usize {
+        profile.cycles.len() + profile.loops.len() + profile.lines.len()
+    }
+
+    fn nth(profile: &Profile, index: usize) -> Option<ProfileEntry> {
+        let cycles = profile
+            .cycles
+            .iter()
+            .map(|c| ProfileEntry::Cycle(c.first_state));
+        let loops = profile.loops.iter().map(|l| ProfileEntry::Line(l.start));
+        let lines = profile.lines.iter().map(|l| ProfileEntry::Line(l.line));
+        cycles.chain(loops).chain(lines).nth(index)
+    }
+}
+
+/// Single line text input displayed at the bottom of the screen.
+struct Prompt {
+    kind: PromptKind,
+    text: String,
+}
+
+/// What is the text user writes into prompt for.
+enum PromptKind {
+    /// Message template of logpoint on given line
+    Logpoint(usize),
+    /// Regex tried out on pattern space, see [ToolPane::RegexTester]
+    RegexTester,
+    /// Commands run from the current state, see [ToolPane::Repl]
+    Repl,
+    /// Pattern space, or hold space if `hold` is true, to run the script again with,
+    /// see [ToolPane::WhatIf]
+    WhatIf { hold: bool, whole_input: bool },
+}
+impl PromptKind {
+    fn title(&self) -> String {
+        match self {
+            PromptKind::Logpoint(line) => format!(
+                " Logpoint on line {} ({{input_line}}, {{line}}, {{command}}, {{pattern}}, {{hold}}, {{state}}) ",
+                line + 1
+            ),
+            PromptKind::RegexTester => {
+                String::from(" Test on pattern space (regex or regex/replacement/flags) ")
+            }
+            PromptKind::Repl => String::from(" Run from current state (such as G;s/\\n/,/) "),
+            PromptKind::WhatIf { hold, whole_input } => format!(
+                " What if {} was this? Runs rest of {} (Tab: rest of {}) ",
+                if *hold { "hold space" } else { "pattern space" },
+                if *whole_input { "input" } else { "cycle" },
+                if *whole_input { "cycle" } else { "input" },
+            ),
+        }
+    }
+}
+
+/// Remembers which tool pane is open and what is selected in it.
+#[derive(Default)]
+struct ToolPaneState {
+    kind: Option<ToolPane>,
+    /// If true, keyboard input goes to the tool pane instead of source code.
+    focused: bool,
+    /// Index of selected item within the pane.
+    selection: usize,
+}
+impl ToolPaneState {
+    /// Open and focus given pane. If it's already open and focused, close it instead.
+    fn toggle(&mut self, kind: ToolPane) {
+        if self.kind == Some(kind) && self.focused {
+            self.kind = None;
+            self.focused = false;
+        } else {
+            if self.kind != Some(kind) {
+                self.selection = 0;
+            }
+            self.kind = Some(kind);
+            self.focused = true;
+        }
+    }
+
+    /// Move selection by given offset, keeping it within number of items.
+    fn move_selection(&mut self, offset: isize, item_count: usize) {
+        let target = self.selection as isize + offset;
+        self.selection = min(max(target, 0) as usize, item_count.saturating_sub(1));
+    }
+
+    /// How many items should be skipped when drawing into area, so the selection stays visible.
+    fn scroll_offset(&self, area: Rect) -> usize {
+        // Two lines are taken by borders
+        let visible_items = max(area.height as usize, 3) - 2;
+        (self.selection + 1).saturating_sub(visible_items)
+    }
+
+    /// Bordered block with given title. Focused pane is highlighted.
+    fn block<'b>(&self, title: &'b str) -> Block<'b> {
+        let block = Block::default().title(title).borders(Borders::ALL);
+        if self.focused {
+            block.border_style(Style::default().fg(Color::Yellow))
+        } else {
+            block
         }
     }
 }